@@ -0,0 +1 @@
+ALTER TABLE account ADD COLUMN cleared_status TEXT NOT NULL DEFAULT 'cleared';
@@ -0,0 +1 @@
+ALTER TABLE account ADD COLUMN sign_from_kind INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,6 @@
+CREATE TABLE processed_file (
+    hash TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    imported_at TEXT NOT NULL,
+    transaction_count INTEGER NOT NULL
+);
@@ -0,0 +1 @@
+ALTER TABLE transaction_import ADD COLUMN import_id TEXT;
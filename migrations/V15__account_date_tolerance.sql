@@ -0,0 +1 @@
+ALTER TABLE account ADD COLUMN date_tolerance INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,8 @@
+CREATE TABLE statement_import (
+    id INTEGER PRIMARY KEY,
+    account_id INTEGER NOT NULL REFERENCES account(id),
+    path TEXT NOT NULL,
+    start_date TEXT NOT NULL,
+    end_date TEXT NOT NULL,
+    imported_at TEXT NOT NULL
+);
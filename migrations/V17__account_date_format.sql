@@ -0,0 +1 @@
+ALTER TABLE account ADD COLUMN date_format TEXT;
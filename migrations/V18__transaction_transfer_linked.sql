@@ -0,0 +1 @@
+ALTER TABLE transaction_import ADD COLUMN transfer_linked INTEGER NOT NULL DEFAULT 0;
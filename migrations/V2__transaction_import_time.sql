@@ -0,0 +1,18 @@
+-- The time of day is part of what tells transactions apart, so it joins the unique key. SQLite
+-- can't alter a constraint, hence the copy. Rows without a time (including every existing one)
+-- have ''.
+CREATE TABLE transaction_import_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    amount INTEGER NOT NULL,
+    date_posted TEXT NOT NULL,
+    time_posted TEXT NOT NULL DEFAULT '',
+    account_id INTEGER NOT NULL REFERENCES account(id),
+    UNIQUE(amount, date_posted, time_posted, account_id)
+);
+
+INSERT INTO transaction_import_new(id, amount, date_posted, account_id)
+SELECT id, amount, date_posted, account_id
+FROM transaction_import;
+
+DROP TABLE transaction_import;
+ALTER TABLE transaction_import_new RENAME TO transaction_import;
@@ -0,0 +1 @@
+ALTER TABLE account ADD COLUMN amount_tolerance INTEGER NOT NULL DEFAULT 0;
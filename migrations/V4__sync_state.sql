@@ -0,0 +1,4 @@
+CREATE TABLE sync_state (
+    account_id INTEGER PRIMARY KEY REFERENCES account(id),
+    server_knowledge INTEGER NOT NULL
+);
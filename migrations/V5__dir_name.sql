@@ -0,0 +1,2 @@
+ALTER TABLE budget ADD COLUMN dir_name TEXT;
+ALTER TABLE account ADD COLUMN dir_name TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE transaction_import ADD COLUMN payee_name TEXT;
+ALTER TABLE transaction_import ADD COLUMN memo TEXT;
@@ -0,0 +1,6 @@
+CREATE TABLE payee_rule (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pattern TEXT NOT NULL,
+    replacement TEXT NOT NULL,
+    is_regex INTEGER NOT NULL DEFAULT 0
+);
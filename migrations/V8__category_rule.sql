@@ -0,0 +1,13 @@
+CREATE TABLE category (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    budget_id INTEGER NOT NULL REFERENCES budget(id),
+    uuid TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    group_name TEXT
+);
+
+CREATE TABLE category_rule (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    payee_pattern TEXT NOT NULL,
+    category_id INTEGER NOT NULL REFERENCES category(id)
+);
@@ -0,0 +1 @@
+ALTER TABLE account ADD COLUMN transfer_payee_id TEXT;
@@ -0,0 +1,339 @@
+use crate::client::YnabClient;
+use crate::db::config;
+use crate::error::ImportError;
+use crate::retry::{is_unauthorized, retry_if};
+use crate::setup::env_token;
+use anyhow::{anyhow, Context, Result};
+use chrono::{Duration, Utc};
+use futures::future::BoxFuture;
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn};
+use url::Url;
+use uuid::Uuid;
+use ynab_api::apis::Error;
+
+const AUTHORIZE_URL: &str = "https://app.ynab.com/oauth/authorize";
+const TOKEN_URL: &str = "https://app.ynab.com/oauth/token";
+
+// The redirect URI has to be registered with the OAuth application exactly, so the listener always
+// uses the same port
+pub const REDIRECT_PORT: u16 = 8976;
+const REDIRECT_PATH: &str = "/callback";
+
+// Access tokens last two hours. They are refreshed a little early so a request started just before
+// expiry doesn't fail.
+const EXPIRY_MARGIN_SECONDS: i64 = 300;
+
+// The refresh token and client secret are kept in the system keyring rather than the DB
+const KEYRING_SERVICE: &str = "ynab-importer";
+const REFRESH_TOKEN_ENTRY: &str = "refresh_token";
+const CLIENT_SECRET_ENTRY: &str = "client_secret";
+
+#[derive(Deserialize, Debug)]
+pub struct Tokens {
+    pub access_token: String,
+    refresh_token: String,
+    // Seconds
+    expires_in: i64,
+}
+
+// Everything needed to refresh the stored access token, read from the DB and keyring up front so
+// the request itself doesn't need a connection
+pub struct RefreshRequest {
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+}
+
+impl RefreshRequest {
+    pub async fn send(self) -> Result<Tokens> {
+        debug!("Refreshing access token");
+        request_tokens(&[
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+            ("grant_type", "refresh_token"),
+            ("refresh_token", &self.refresh_token),
+        ])
+        .await
+    }
+}
+
+pub fn redirect_uri() -> String {
+    format!("http://localhost:{}{}", REDIRECT_PORT, REDIRECT_PATH)
+}
+
+pub fn authorize_url(client_id: &str, state: &str) -> Result<Url> {
+    Ok(Url::parse_with_params(
+        AUTHORIZE_URL,
+        &[
+            ("client_id", client_id),
+            ("redirect_uri", &redirect_uri()),
+            ("response_type", "code"),
+            ("state", state),
+        ],
+    )?)
+}
+
+fn keyring_entry(name: &str) -> Result<keyring::Entry> {
+    Ok(keyring::Entry::new(KEYRING_SERVICE, name)?)
+}
+
+// Pulls the authorization code out of the first line of the redirect request, e.g.
+// "GET /callback?code=abc&state=xyz HTTP/1.1". state has to match the one sent to YNAB.
+fn parse_redirect(request_line: &str, state: &str) -> Result<String> {
+    let target = request_line
+        .split_whitespace()
+        .nth(1)
+        .ok_or_else(|| anyhow!("malformed redirect request"))?;
+    let url = Url::parse(&format!("http://localhost{}", target))?;
+    let params: HashMap<_, _> = url.query_pairs().collect();
+    if let Some(err) = params.get("error") {
+        return Err(anyhow!("authorization was denied: {}", err));
+    }
+    if params.get("state").map(|s| s.as_ref()) != Some(state) {
+        return Err(anyhow!(
+            "redirect state doesn't match the authorization request"
+        ));
+    }
+    params
+        .get("code")
+        .map(|code| code.to_string())
+        .ok_or_else(|| anyhow!("redirect has no authorization code"))
+}
+
+// Waits for the browser to be redirected back to the listener and answers with a page telling the
+// user they can go back to the importer
+async fn wait_for_code(listener: &TcpListener, state: &str) -> Result<String> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut request_line = String::new();
+        BufReader::new(&mut stream)
+            .read_line(&mut request_line)
+            .await?;
+
+        // Browsers also ask for things like /favicon.ico
+        let is_redirect = request_line
+            .split_whitespace()
+            .nth(1)
+            .is_some_and(|target| target.starts_with(REDIRECT_PATH));
+        if !is_redirect {
+            stream
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            continue;
+        }
+
+        let result = parse_redirect(&request_line, state);
+        let body = match result {
+            Ok(_) => "Signed in to YNAB, this tab can be closed.",
+            Err(_) => "Signing in to YNAB failed, see the importer for details.",
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\
+            Connection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        return result;
+    }
+}
+
+async fn request_tokens(params: &[(&str, &str)]) -> Result<Tokens> {
+    let response = reqwest::Client::new()
+        .post(TOKEN_URL)
+        .form(params)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json().await?)
+}
+
+// Saves new tokens. The access token goes where the rest of the importer already reads it from.
+pub fn store(conn: &Connection, tokens: &Tokens) -> Result<()> {
+    config::set(conn, config::ACCESS_TOKEN, &tokens.access_token)?;
+    config::set_token_expires_at(conn, Utc::now() + Duration::seconds(tokens.expires_in))?;
+    keyring_entry(REFRESH_TOKEN_ENTRY)?.set_password(&tokens.refresh_token)?;
+    Ok(())
+}
+
+// Signs in through the browser with YNAB's authorization-code flow and stores the tokens. Returns
+// the new access token.
+pub async fn login(conn: &Connection, client_id: &str, client_secret: &str) -> Result<String> {
+    let listener = TcpListener::bind(("127.0.0.1", REDIRECT_PORT)).await?;
+    let state = Uuid::new_v4().to_string();
+    let url = authorize_url(client_id, &state)?;
+    info!(%url, "Opening YNAB authorization page");
+    if let Err(err) = webbrowser::open(url.as_str()) {
+        warn!(%err, "Failed to open a browser, visit the authorization page manually");
+    }
+
+    let code = wait_for_code(&listener, &state).await?;
+    let tokens = request_tokens(&[
+        ("client_id", client_id),
+        ("client_secret", client_secret),
+        ("grant_type", "authorization_code"),
+        ("redirect_uri", &redirect_uri()),
+        ("code", &code),
+    ])
+    .await?;
+    config::set(conn, config::OAUTH_CLIENT_ID, client_id)?;
+    keyring_entry(CLIENT_SECRET_ENTRY)?.set_password(client_secret)?;
+    store(conn, &tokens)?;
+    Ok(tokens.access_token)
+}
+
+// Stops refreshing the stored token, e.g. when a personal access token replaces it
+pub fn logout(conn: &Connection) -> Result<()> {
+    if config::get_opt(conn, config::OAUTH_CLIENT_ID)?.is_none() {
+        return Ok(());
+    }
+    config::unset(conn, config::OAUTH_CLIENT_ID)?;
+    config::unset(conn, config::TOKEN_EXPIRES_AT)?;
+    for name in [REFRESH_TOKEN_ENTRY, CLIENT_SECRET_ENTRY] {
+        match keyring_entry(name)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+// What's needed to refresh the stored access token if it came from login and is about to expire.
+// None for a token that is still good, and for personal access tokens, which never expire.
+pub fn refresh_request(conn: &Connection) -> Result<Option<RefreshRequest>> {
+    let Some(client_id) = config::get_opt(conn, config::OAUTH_CLIENT_ID)? else {
+        return Ok(None);
+    };
+    let margin = Duration::seconds(EXPIRY_MARGIN_SECONDS);
+    if config::get_token_expires_at(conn)?.is_some_and(|at| at - Utc::now() > margin) {
+        return Ok(None);
+    }
+    Ok(Some(stored_refresh_request(client_id)?))
+}
+
+fn stored_refresh_request(client_id: String) -> Result<RefreshRequest> {
+    Ok(RefreshRequest {
+        client_id,
+        client_secret: keyring_entry(CLIENT_SECRET_ENTRY)?.get_password()?,
+        refresh_token: keyring_entry(REFRESH_TOKEN_ENTRY)?.get_password()?,
+    })
+}
+
+// Refreshes the access token after YNAB rejected it, whatever its recorded expiry says. A personal
+// access token has no refresh token, so the only fix is setting up again.
+async fn refresh_rejected(conn: &Connection) -> Result<String> {
+    let Some(client_id) = config::get_opt(conn, config::OAUTH_CLIENT_ID)? else {
+        return Err(ImportError::TokenRejectedError.into());
+    };
+    warn!("YNAB rejected the access token, refreshing it");
+    let tokens = stored_refresh_request(client_id)?
+        .send()
+        .await
+        .context(ImportError::TokenRejectedError)?;
+    store(conn, &tokens)?;
+    Ok(tokens.access_token)
+}
+
+// The token from YNAB_ACCESS_TOKEN if set, otherwise the stored access token, refreshed first if
+// needed
+pub async fn access_token(conn: &Connection) -> Result<String> {
+    if let Some(token) = env_token()? {
+        return Ok(token);
+    }
+    match refresh_request(conn)? {
+        None => config::get_access_token(conn),
+        Some(request) => {
+            let tokens = request.send().await?;
+            store(conn, &tokens)?;
+            Ok(tokens.access_token)
+        }
+    }
+}
+
+// Makes an API call with the stored access token, retrying the errors `retryable` accepts as
+// retry_if does. If YNAB rejects the token (401) it is refreshed and the call made once more, which
+// is safe for any call as YNAB didn't act on the rejected one.
+pub async fn with_token_refresh<T, E, F>(
+    conn: &Connection,
+    client: &dyn YnabClient,
+    max_retries: usize,
+    retryable: fn(&Error<E>) -> bool,
+    mut call: F,
+) -> Result<T>
+where
+    E: Debug + Send + Sync + 'static,
+    F: for<'a> FnMut(&'a dyn YnabClient) -> BoxFuture<'a, Result<T, Error<E>>>,
+{
+    let current = client.with_access_token(access_token(conn).await?);
+    match retry_if(max_retries, retryable, || call(current.as_ref())).await {
+        Err(err) if is_unauthorized(&err) => {
+            let refreshed = client.with_access_token(refresh_rejected(conn).await?);
+            match retry_if(max_retries, retryable, || call(refreshed.as_ref())).await {
+                Err(err) if is_unauthorized(&err) => Err(ImportError::TokenRejectedError.into()),
+                res => Ok(res?),
+            }
+        }
+        res => Ok(res?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fake::FakeClient;
+    use crate::db::test_conn;
+    use crate::retry::is_retryable_create;
+    use ynab_api::models::PostTransactionsWrapper;
+
+    #[test]
+    fn test_parse_redirect() {
+        assert_eq!(
+            parse_redirect("GET /callback?code=abc&state=xyz HTTP/1.1\r\n", "xyz").unwrap(),
+            "abc"
+        );
+        assert!(parse_redirect("GET /callback?code=abc&state=other HTTP/1.1", "xyz").is_err());
+        assert!(parse_redirect("GET /callback?state=xyz HTTP/1.1", "xyz").is_err());
+        let err = parse_redirect(
+            "GET /callback?error=access_denied&state=xyz HTTP/1.1",
+            "xyz",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("access_denied"));
+    }
+
+    #[test]
+    fn test_authorize_url() {
+        let url = authorize_url("client", "xyz").unwrap();
+        let params: HashMap<_, _> = url.query_pairs().collect();
+        assert_eq!(params["client_id"], "client");
+        assert_eq!(params["redirect_uri"], redirect_uri());
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["state"], "xyz");
+    }
+
+    #[tokio::test]
+    async fn test_with_token_refresh_without_refresh_token() {
+        let conn = test_conn();
+        config::set(&conn, config::ACCESS_TOKEN, "token\n").unwrap();
+
+        let client = FakeClient::default();
+        client.state.lock().unwrap().error_status = Some(401);
+        let result = with_token_refresh(&conn, &client, 2, is_retryable_create, |client| {
+            client.create_transaction("budget".into(), PostTransactionsWrapper::new())
+        })
+        .await;
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ImportError::TokenRejectedError)
+        ));
+        let state = client.state.lock().unwrap();
+        assert_eq!(state.tokens, vec!["token".to_string()]);
+    }
+}
@@ -0,0 +1,316 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use ynab_api::apis::accounts_api::{self, GetAccountByIdError, GetAccountsError};
+use ynab_api::apis::budgets_api::{self, GetBudgetsError};
+use ynab_api::apis::configuration::Configuration;
+use ynab_api::apis::payees_api::{self, GetPayeesError};
+use ynab_api::apis::transactions_api::{
+    self, CreateTransactionError, GetTransactionsByAccountError, UpdateTransactionsError,
+};
+use ynab_api::apis::Error;
+use ynab_api::models::{
+    AccountResponse, AccountsResponse, BudgetSummaryResponse, PatchTransactionsWrapper,
+    PayeesResponse, PostTransactionsWrapper, SaveTransactionsResponse, TransactionsResponse,
+};
+
+// The YNAB endpoints the importer uses, so that tests can stand in for the API. Arguments are owned
+// so the returned futures only borrow the client.
+pub trait YnabClient: Send + Sync {
+    // A client making the same calls with a different access token, e.g. after refreshing it
+    fn with_access_token(&self, token: String) -> Box<dyn YnabClient>;
+
+    fn create_transaction(
+        &self,
+        budget_id: String,
+        data: PostTransactionsWrapper,
+    ) -> BoxFuture<'_, Result<SaveTransactionsResponse, Error<CreateTransactionError>>>;
+
+    // Updates existing transactions, found by their id or import_id
+    fn update_transactions(
+        &self,
+        budget_id: String,
+        data: PatchTransactionsWrapper,
+    ) -> BoxFuture<'_, Result<SaveTransactionsResponse, Error<UpdateTransactionsError>>>;
+
+    fn get_transactions_by_account(
+        &self,
+        budget_id: String,
+        account_id: String,
+        since_date: Option<String>,
+    ) -> BoxFuture<'_, Result<TransactionsResponse, Error<GetTransactionsByAccountError>>>;
+
+    fn get_budgets(
+        &self,
+        include_accounts: Option<bool>,
+    ) -> BoxFuture<'_, Result<BudgetSummaryResponse, Error<GetBudgetsError>>>;
+
+    fn get_accounts(
+        &self,
+        budget_id: String,
+    ) -> BoxFuture<'_, Result<AccountsResponse, Error<GetAccountsError>>>;
+
+    fn get_account_by_id(
+        &self,
+        budget_id: String,
+        account_id: String,
+    ) -> BoxFuture<'_, Result<AccountResponse, Error<GetAccountByIdError>>>;
+
+    fn get_payees(
+        &self,
+        budget_id: String,
+    ) -> BoxFuture<'_, Result<PayeesResponse, Error<GetPayeesError>>>;
+}
+
+impl YnabClient for Configuration {
+    fn with_access_token(&self, token: String) -> Box<dyn YnabClient> {
+        let mut api_config = self.clone();
+        api_config.bearer_access_token = Some(token);
+        Box::new(api_config)
+    }
+
+    fn create_transaction(
+        &self,
+        budget_id: String,
+        data: PostTransactionsWrapper,
+    ) -> BoxFuture<'_, Result<SaveTransactionsResponse, Error<CreateTransactionError>>> {
+        async move { transactions_api::create_transaction(self, &budget_id, data).await }.boxed()
+    }
+
+    fn update_transactions(
+        &self,
+        budget_id: String,
+        data: PatchTransactionsWrapper,
+    ) -> BoxFuture<'_, Result<SaveTransactionsResponse, Error<UpdateTransactionsError>>> {
+        async move { transactions_api::update_transactions(self, &budget_id, data).await }.boxed()
+    }
+
+    fn get_transactions_by_account(
+        &self,
+        budget_id: String,
+        account_id: String,
+        since_date: Option<String>,
+    ) -> BoxFuture<'_, Result<TransactionsResponse, Error<GetTransactionsByAccountError>>> {
+        async move {
+            transactions_api::get_transactions_by_account(
+                self,
+                &budget_id,
+                &account_id,
+                since_date,
+                None,
+                None,
+            )
+            .await
+        }
+        .boxed()
+    }
+
+    fn get_budgets(
+        &self,
+        include_accounts: Option<bool>,
+    ) -> BoxFuture<'_, Result<BudgetSummaryResponse, Error<GetBudgetsError>>> {
+        async move { budgets_api::get_budgets(self, include_accounts).await }.boxed()
+    }
+
+    fn get_accounts(
+        &self,
+        budget_id: String,
+    ) -> BoxFuture<'_, Result<AccountsResponse, Error<GetAccountsError>>> {
+        async move { accounts_api::get_accounts(self, &budget_id, None).await }.boxed()
+    }
+
+    fn get_account_by_id(
+        &self,
+        budget_id: String,
+        account_id: String,
+    ) -> BoxFuture<'_, Result<AccountResponse, Error<GetAccountByIdError>>> {
+        async move { accounts_api::get_account_by_id(self, &budget_id, &account_id).await }.boxed()
+    }
+
+    fn get_payees(
+        &self,
+        budget_id: String,
+    ) -> BoxFuture<'_, Result<PayeesResponse, Error<GetPayeesError>>> {
+        async move { payees_api::get_payees(self, &budget_id, None).await }.boxed()
+    }
+}
+
+// A stand-in for YNAB with canned responses, for tests
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use futures::future;
+    use std::sync::{Arc, Mutex};
+    use uuid::Uuid;
+    use ynab_api::apis::ResponseContent;
+    use ynab_api::models::{
+        NewTransaction, Payee, PayeesResponseData, SaveTransactionWithIdOrImportId,
+        SaveTransactionsResponseData, TransactionClearedStatus, TransactionDetail,
+    };
+
+    #[derive(Default)]
+    pub struct FakeState {
+        // Import ids YNAB already has. Posting one of these again reports it as a duplicate.
+        pub import_ids: Vec<String>,
+        // Answer every request with this status instead, e.g. 401 for a rejected token
+        pub error_status: Option<u16>,
+        // Transactions sent in each create_transaction request
+        pub posted: Vec<Vec<NewTransaction>>,
+        // Updates sent with update_transactions, in order
+        pub updated: Vec<SaveTransactionWithIdOrImportId>,
+        // What get_payees lists
+        pub payees: Vec<Payee>,
+        // How many times get_payees was called
+        pub payee_requests: usize,
+        // Access token of each client handed out by with_access_token
+        pub tokens: Vec<String>,
+    }
+
+    // Clones, including the ones with_access_token returns, share the same state
+    #[derive(Clone, Default)]
+    pub struct FakeClient {
+        pub state: Arc<Mutex<FakeState>>,
+    }
+
+    fn status_error<E>(status: u16) -> Error<E> {
+        Error::ResponseError(ResponseContent {
+            status: status.try_into().unwrap(),
+            content: String::new(),
+            entity: None,
+        })
+    }
+
+    impl FakeClient {
+        fn respond<T: Send + 'static, E: Send + 'static>(
+            &self,
+            response: impl FnOnce(&mut FakeState) -> Result<T, Error<E>>,
+        ) -> BoxFuture<'static, Result<T, Error<E>>> {
+            let mut state = self.state.lock().unwrap();
+            let result = match state.error_status {
+                Some(status) => Err(status_error(status)),
+                None => response(&mut state),
+            };
+            future::ready(result).boxed()
+        }
+
+        fn saved(import_id: String) -> TransactionDetail {
+            TransactionDetail {
+                import_id: Some(Some(import_id)),
+                ..TransactionDetail::new(
+                    String::new(),
+                    String::new(),
+                    0,
+                    TransactionClearedStatus::Cleared,
+                    false,
+                    Uuid::nil(),
+                    false,
+                    String::new(),
+                    Vec::new(),
+                )
+            }
+        }
+    }
+
+    impl YnabClient for FakeClient {
+        fn with_access_token(&self, token: String) -> Box<dyn YnabClient> {
+            self.state.lock().unwrap().tokens.push(token);
+            Box::new(self.clone())
+        }
+
+        fn create_transaction(
+            &self,
+            _budget_id: String,
+            data: PostTransactionsWrapper,
+        ) -> BoxFuture<'_, Result<SaveTransactionsResponse, Error<CreateTransactionError>>>
+        {
+            self.respond(|state| {
+                let transactions = data.transactions.unwrap_or_default();
+                state.posted.push(transactions.clone());
+                let mut saved = Vec::new();
+                let mut duplicates = Vec::new();
+                for t in transactions {
+                    let import_id = t.import_id.flatten().unwrap_or_default();
+                    if state.import_ids.contains(&import_id) {
+                        duplicates.push(import_id);
+                    } else {
+                        state.import_ids.push(import_id.clone());
+                        saved.push(Self::saved(import_id));
+                    }
+                }
+                Ok(SaveTransactionsResponse::new(
+                    SaveTransactionsResponseData {
+                        transactions: Some(saved),
+                        duplicate_import_ids: Some(duplicates),
+                        ..SaveTransactionsResponseData::new(Vec::new(), 0)
+                    },
+                ))
+            })
+        }
+
+        fn update_transactions(
+            &self,
+            _budget_id: String,
+            data: PatchTransactionsWrapper,
+        ) -> BoxFuture<'_, Result<SaveTransactionsResponse, Error<UpdateTransactionsError>>>
+        {
+            self.respond(|state| {
+                let mut saved = Vec::new();
+                for t in data.transactions {
+                    let import_id = t.import_id.clone().flatten().unwrap_or_default();
+                    if state.import_ids.contains(&import_id) {
+                        saved.push(Self::saved(import_id));
+                    }
+                    state.updated.push(t);
+                }
+                Ok(SaveTransactionsResponse::new(
+                    SaveTransactionsResponseData {
+                        transactions: Some(saved),
+                        ..SaveTransactionsResponseData::new(Vec::new(), 0)
+                    },
+                ))
+            })
+        }
+
+        fn get_transactions_by_account(
+            &self,
+            _budget_id: String,
+            _account_id: String,
+            _since_date: Option<String>,
+        ) -> BoxFuture<'_, Result<TransactionsResponse, Error<GetTransactionsByAccountError>>>
+        {
+            self.respond(|_| Err(status_error(404)))
+        }
+
+        fn get_budgets(
+            &self,
+            _include_accounts: Option<bool>,
+        ) -> BoxFuture<'_, Result<BudgetSummaryResponse, Error<GetBudgetsError>>> {
+            self.respond(|_| Err(status_error(404)))
+        }
+
+        fn get_accounts(
+            &self,
+            _budget_id: String,
+        ) -> BoxFuture<'_, Result<AccountsResponse, Error<GetAccountsError>>> {
+            self.respond(|_| Err(status_error(404)))
+        }
+
+        fn get_account_by_id(
+            &self,
+            _budget_id: String,
+            _account_id: String,
+        ) -> BoxFuture<'_, Result<AccountResponse, Error<GetAccountByIdError>>> {
+            self.respond(|_| Err(status_error(404)))
+        }
+
+        fn get_payees(
+            &self,
+            _budget_id: String,
+        ) -> BoxFuture<'_, Result<PayeesResponse, Error<GetPayeesError>>> {
+            self.respond(|state| {
+                state.payee_requests += 1;
+                let payees = state.payees.clone();
+                Ok(PayeesResponse::new(PayeesResponseData::new(payees, 0)))
+            })
+        }
+    }
+}
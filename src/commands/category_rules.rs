@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use ynab_importer::db::{budget, category, category_rules, open_db_and_migrate};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    // Add a rule, which is tried after the existing ones for the budget
+    Add {
+        // Name of the budget the category belongs to
+        #[arg(short, long)]
+        budget: String,
+
+        // Text to look for in the payee (after any payee rules), matched case-insensitively
+        #[arg(short, long)]
+        payee: String,
+
+        // Name of the category in YNAB
+        #[arg(short, long)]
+        category: String,
+    },
+
+    // List the rules of every budget in the order they're tried
+    List,
+
+    // Remove a rule by its id, as shown by list
+    Remove {
+        id: i64,
+    },
+
+    // List the categories of a budget as of the last setup
+    Categories {
+        // Name of the budget
+        #[arg(short, long)]
+        budget: String,
+    },
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+    match args.action {
+        Action::Add {
+            budget,
+            payee,
+            category,
+        } => {
+            let budget = budget::with_name(&conn, &budget)?;
+            let row = category::get_for_budget(&conn, budget.id)?
+                .into_iter()
+                .find(|c| c.name.eq_ignore_ascii_case(&category))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "No category named {} in {}. Categories are stored by setup, run it \
+                        again to pick up new ones.",
+                        category,
+                        budget.name
+                    )
+                })?;
+            let id = category_rules::create(&conn, &payee, row.id)?;
+            println!("Added rule {}", id);
+        }
+        Action::List => {
+            let rules = category_rules::get_all(&conn)?;
+            if rules.is_empty() {
+                println!("No category rules");
+            }
+            for rule in rules {
+                let budget = budget::get(&conn, rule.budget_id)?;
+                println!(
+                    "[{}] {}: '{}' -> {}",
+                    rule.id, budget.name, rule.payee_pattern, rule.category_name
+                );
+            }
+        }
+        Action::Remove { id } => match category_rules::delete(&conn, id)? {
+            true => println!("Removed rule {}", id),
+            false => println!("No rule with id {}", id),
+        },
+        Action::Categories { budget } => {
+            let budget = budget::with_name(&conn, &budget)?;
+            for c in category::get_for_budget(&conn, budget.id)? {
+                println!("{} / {}", c.group_name.unwrap_or_default(), c.name);
+            }
+        }
+    }
+    Ok(())
+}
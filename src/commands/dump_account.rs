@@ -0,0 +1,94 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use ynab_api::apis::configuration::Configuration;
+use ynab_api::apis::transactions_api::get_transactions_by_account;
+use ynab_importer::auth;
+use ynab_importer::db::{account, budget, open_db_and_migrate};
+use ynab_importer::export::format_amount;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Name of the budget the account belongs to
+    #[arg(short, long)]
+    budget: String,
+
+    // Name of the account in YNAB
+    #[arg(short, long)]
+    account: String,
+
+    // Only show transactions on or after this date (YYYY-MM-DD)
+    #[arg(short, long)]
+    since: Option<NaiveDate>,
+}
+
+// Fetches and prints the transactions YNAB currently has for an account. Read-only, nothing is
+// written to the local DB.
+pub async fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+
+    let budget = budget::with_name(&conn, &args.budget)?;
+    let account = account::with_budget_and_name(&conn, budget.id, &args.account)?;
+
+    let mut api_config = Configuration::new();
+    api_config.bearer_access_token = Some(auth::access_token(&conn).await?);
+
+    let response = get_transactions_by_account(
+        &api_config,
+        &budget.uuid.hyphenated().to_string(),
+        &account.uuid.hyphenated().to_string(),
+        args.since.map(|d| d.to_string()),
+        None,
+        None,
+    )
+    .await?;
+
+    let rows: Vec<[String; 5]> = response
+        .data
+        .transactions
+        .into_iter()
+        .filter(|t| !t.deleted)
+        .map(|t| {
+            [
+                t.date,
+                format_amount(t.amount),
+                t.payee_name.flatten().unwrap_or_default(),
+                t.cleared.to_string(),
+                t.import_id.flatten().unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let headers = ["DATE", "AMOUNT", "PAYEE", "CLEARED", "IMPORT_ID"];
+    let mut widths = headers.map(|h| h.len());
+    for row in rows.iter() {
+        for (w, col) in widths.iter_mut().zip(row.iter()) {
+            *w = (*w).max(col.len());
+        }
+    }
+    let print_row = |row: [&str; 5]| {
+        println!(
+            "{:<w0$}  {:>w1$}  {:<w2$}  {:<w3$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+        );
+    };
+    print_row(headers);
+    for row in rows.iter() {
+        print_row(row.each_ref().map(|s| s.as_str()));
+    }
+
+    println!(
+        "\n{} transactions in {} / {}",
+        rows.len(),
+        budget.name,
+        account.name
+    );
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use ynab_importer::db::{config, open_db_and_migrate};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    // Expand an entity, or change what a built in one expands to
+    Add {
+        // Name of the entity, without the & and ;, e.g. eacute for &eacute;
+        name: String,
+
+        // Text to replace it with
+        text: String,
+    },
+
+    // List the entities added
+    List,
+
+    // Remove an added entity, going back to the built in expansion if there is one
+    Remove {
+        name: String,
+    },
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+    let mut entities = config::get_ofx_entities(&conn)?;
+    match args.action {
+        Action::Add { name, text } => {
+            let name = name.trim_matches(['&', ';']).to_string();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(anyhow!("Entity names can only contain letters and digits"));
+            }
+            println!("Expanding &{}; to '{}'", name, text);
+            entities.insert(name, text);
+            config::set_ofx_entities(&conn, &entities)?;
+        }
+        Action::List => {
+            if entities.is_empty() {
+                println!("No entities added");
+            }
+            for (name, text) in entities {
+                println!("&{}; -> '{}'", name, text);
+            }
+        }
+        Action::Remove { name } => match entities.remove(name.trim_matches(['&', ';'])) {
+            Some(_) => {
+                config::set_ofx_entities(&conn, &entities)?;
+                println!("Removed &{};", name);
+            }
+            None => println!("No entity &{}; was added", name),
+        },
+    }
+    Ok(())
+}
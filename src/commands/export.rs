@@ -0,0 +1,23 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use ynab_importer::db::open_db_and_migrate;
+use ynab_importer::export::export_csv;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // CSV file to write, replacing it if it exists
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+// Writes every imported transaction to a CSV file, e.g. for taxes. Only reads from the DB.
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+    let count = export_csv(&conn, &args.output)?;
+    println!(
+        "Exported {} transactions to {}",
+        count,
+        args.output.display()
+    );
+    Ok(())
+}
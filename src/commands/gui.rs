@@ -0,0 +1,40 @@
+use anyhow::{anyhow, Result};
+use eframe::egui::{self, IconData};
+use std::path::Path;
+use ynab_importer::{
+    db::{config, open_db_and_migrate},
+    ui::ConfigApp,
+};
+
+// Opens the setup window. Needs to be called from inside a tokio runtime, the views spawn their API
+// requests onto it.
+pub fn run() -> Result<()> {
+    let geometry = config::get_window_geometry(&open_db_and_migrate()?)?;
+
+    let icon = image::open(Path::new("./img/Yi.png"))?.to_rgba8();
+    let (icon_width, icon_height) = icon.dimensions();
+
+    let mut viewport = egui::ViewportBuilder::default()
+        .with_inner_size(geometry.map_or([640.0, 280.0], |g| g.size))
+        .with_drag_and_drop(true)
+        .with_icon(IconData {
+            rgba: icon.into_raw(),
+            width: icon_width,
+            height: icon_height,
+        });
+    if let Some(position) = geometry.and_then(|g| g.position) {
+        viewport = viewport.with_position(position);
+    }
+
+    let options = eframe::NativeOptions {
+        viewport,
+        ..Default::default()
+    };
+    eframe::run_native(
+        "YNAB Importer",
+        options,
+        Box::new(|cc| Ok(Box::new(ConfigApp::new(cc)))),
+    )
+    .map_err(|err| anyhow!("{}", err))?;
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use super::OutputFormat;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use ynab_importer::db::open_db_and_migrate;
+use ynab_importer::db::processed_file::ProcessedFileRow;
+use ynab_importer::event::{file_hash, EventHandler, ImportSummary};
+use ynab_importer::instance;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Statement to import. It has to be inside the monitored folder, since the budget and account
+    // are worked out from where it sits.
+    #[arg(short, long)]
+    path: PathBuf,
+
+    // Import the statement even if one with the same contents was already processed
+    #[arg(long)]
+    force: bool,
+
+    // Also list the transactions skipped as already imported
+    #[arg(long)]
+    show_skipped: bool,
+
+    // With json, the outcome (or error) is printed as a single object instead, which always
+    // includes the skipped transactions
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+}
+
+enum Outcome {
+    // The statement's contents were processed before, so it wasn't imported again
+    AlreadyProcessed(ProcessedFileRow),
+    Imported(ImportSummary),
+}
+
+async fn import(path: &Path, force: bool) -> Result<Outcome> {
+    // Importing alongside the service would race it on the same statements, as two services would
+    let _lock = instance::acquire(&instance::service_lock_path()?)
+        .context("move the statement into the monitored folder for the service to import it")?;
+    let conn = open_db_and_migrate()?;
+
+    // The configured transaction dir is stored canonicalized
+    let path = path.canonicalize()?;
+    let event_handler = EventHandler::new(conn)?;
+
+    let hash = file_hash(&path)?;
+    if !force {
+        if let Some(processed) = event_handler.already_processed(&hash)? {
+            return Ok(Outcome::AlreadyProcessed(processed));
+        }
+    }
+
+    let summary = event_handler.create_transactions_with_retry(&path).await?;
+    event_handler.record_processed(hash, &path, &summary)?;
+    Ok(Outcome::Imported(summary))
+}
+
+fn print_text(path: &Path, outcome: Outcome, show_skipped: bool) {
+    let summary = match outcome {
+        Outcome::AlreadyProcessed(processed) => {
+            println!(
+                "{}: same contents as {}, processed {}. Use --force to import it again",
+                path.display(),
+                processed.path,
+                processed.imported_at
+            );
+            return;
+        }
+        Outcome::Imported(summary) => summary,
+    };
+    println!(
+        "{}: created {} transactions, skipped {} already imported",
+        path.display(),
+        summary.created,
+        summary.skipped_existing.len()
+    );
+    if show_skipped {
+        for t in summary.skipped_existing {
+            println!(
+                "  {}  {:>10.2}  {}",
+                t.date,
+                t.amount_milli as f64 / 1000.0,
+                t.payee.unwrap_or_default()
+            );
+        }
+    }
+}
+
+// Imports a single statement once, without the service running. The file is left where it is, a
+// running service will skip it as already processed before archiving it.
+pub async fn run(args: Args) -> Result<()> {
+    let result = import(&args.path, args.force).await;
+    match args.format {
+        OutputFormat::Text => print_text(&args.path, result?, args.show_skipped),
+        OutputFormat::Json => {
+            let path = args.path.display().to_string();
+            let output = match &result {
+                Ok(Outcome::AlreadyProcessed(processed)) => json!({
+                    "path": path,
+                    "status": "already_processed",
+                    "processed_path": processed.path,
+                    "imported_at": processed.imported_at,
+                }),
+                Ok(Outcome::Imported(summary)) => json!({
+                    "path": path,
+                    "status": "imported",
+                    "summary": summary,
+                }),
+                Err(err) => json!({
+                    "path": path,
+                    "status": "error",
+                    "error": format!("{:#}", err),
+                }),
+            };
+            println!("{}", output);
+            // Still exits with an error status
+            result?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use ynab_importer::db::{account, budget, open_db_and_migrate};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Name of the budget the account belongs to
+    #[arg(short, long)]
+    budget: String,
+
+    // Name of the account in YNAB
+    #[arg(short, long)]
+    account: String,
+
+    // The <ACCTID> value the bank uses for this account in its statements
+    #[arg(short = 'i', long)]
+    acct_id: String,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+
+    let budget = budget::with_name(&conn, &args.budget)?;
+    let account = account::with_budget_and_name(&conn, budget.id, &args.account)?;
+    account::set_external_id(&conn, account.id, args.acct_id.trim())?;
+    println!(
+        "Linked {} / {} to ACCTID {}",
+        budget.name, account.name, args.acct_id
+    );
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use anyhow::Result;
+use std::fs;
+use std::path::PathBuf;
+use ynab_importer::auth::{self, redirect_uri};
+use ynab_importer::db::open_db_and_migrate;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Client ID of your YNAB OAuth application
+    #[arg(long)]
+    client_id: String,
+
+    // Path to a file containing the OAuth application's client secret, kept off the command line
+    #[arg(long)]
+    client_secret: PathBuf,
+}
+
+// Signs in to YNAB through the browser instead of using a personal access token file. The token is
+// refreshed automatically from then on, and setup can be run without --access-token.
+pub async fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+    let client_secret = fs::read_to_string(&args.client_secret)?;
+    println!(
+        "Waiting for YNAB to redirect to {} (it must be registered with the application)",
+        redirect_uri()
+    );
+    auth::login(&conn, &args.client_id, client_secret.trim()).await?;
+    println!("Signed in to YNAB");
+    Ok(())
+}
@@ -0,0 +1,27 @@
+pub mod category_rules;
+pub mod dump_account;
+pub mod entities;
+pub mod export;
+pub mod gui;
+pub mod import_file;
+pub mod link_account;
+pub mod login;
+pub mod payee_rules;
+pub mod prune;
+pub mod service;
+pub mod set_amount_tolerance;
+pub mod set_date_format;
+pub mod set_sign_from_type;
+pub mod setup;
+pub mod status;
+pub mod validate_fixtures;
+
+// How a command reports what it did on stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    // Lines meant for a person
+    #[default]
+    Text,
+    // A single JSON object, e.g. for piping into jq
+    Json,
+}
@@ -0,0 +1,69 @@
+use anyhow::Result;
+use clap::Subcommand;
+use ynab_importer::db::{open_db_and_migrate, payee_rules};
+use ynab_importer::rules::validate_pattern;
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(Subcommand, Debug)]
+enum Action {
+    // Add a rule, which is tried after the existing ones
+    Add {
+        // Text to look for in the statement's payee, matched case-insensitively
+        #[arg(short, long)]
+        pattern: String,
+
+        // Name to give the payee in YNAB instead
+        #[arg(short, long)]
+        replacement: String,
+
+        // Treat the pattern as a regex. $1 etc. in the replacement are filled in from the match.
+        #[arg(long)]
+        regex: bool,
+    },
+
+    // List the rules in the order they're tried
+    List,
+
+    // Remove a rule by its id, as shown by list
+    Remove {
+        id: i64,
+    },
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+    match args.action {
+        Action::Add {
+            pattern,
+            replacement,
+            regex,
+        } => {
+            validate_pattern(&pattern, regex)?;
+            let id = payee_rules::create(&conn, &pattern, &replacement, regex)?;
+            println!("Added rule {}", id);
+        }
+        Action::List => {
+            let rules = payee_rules::get_all(&conn)?;
+            if rules.is_empty() {
+                println!("No payee rules");
+            }
+            for rule in rules {
+                let kind = if rule.is_regex { "regex" } else { "text" };
+                println!(
+                    "[{}] {} '{}' -> '{}'",
+                    rule.id, kind, rule.pattern, rule.replacement
+                );
+            }
+        }
+        Action::Remove { id } => match payee_rules::delete(&conn, id)? {
+            true => println!("Removed rule {}", id),
+            false => println!("No rule with id {}", id),
+        },
+    }
+    Ok(())
+}
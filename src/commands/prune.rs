@@ -0,0 +1,78 @@
+use anyhow::Result;
+use std::io;
+use std::io::Write;
+use ynab_api::apis::configuration::Configuration;
+use ynab_importer::auth;
+use ynab_importer::db::{config, open_db_and_migrate};
+use ynab_importer::prune::{
+    archive_account_dir, archive_budget_dir, find_stale_accounts, find_stale_budgets,
+    prune_accounts, prune_budgets,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Also move the folders of removed budgets and accounts into <transaction_dir>/.archived
+    #[arg(short, long)]
+    archive: bool,
+}
+
+fn confirm(prompt: &str) -> bool {
+    print!("{} [y/N]: ", prompt);
+    io::stdout().flush().expect("stdout flush failed");
+    let mut input = String::new();
+    io::stdin()
+        .read_line(&mut input)
+        .expect("Failed to read line");
+    matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    let mut conn = open_db_and_migrate()?;
+
+    let mut api_config = Configuration::new();
+    api_config.bearer_access_token = Some(auth::access_token(&conn).await?);
+
+    let stale_budgets = find_stale_budgets(&conn, &api_config).await?;
+    let stale = find_stale_accounts(&conn, &api_config, &stale_budgets).await?;
+    if stale_budgets.is_empty() && stale.is_empty() {
+        println!("No deleted budgets or accounts found");
+        return Ok(());
+    }
+
+    if !stale_budgets.is_empty() {
+        println!("The following budgets no longer exist in YNAB:");
+        for s in stale_budgets.iter() {
+            println!("  {}", s.budget.name);
+        }
+    }
+    if !stale.is_empty() {
+        println!("The following accounts no longer exist in YNAB:");
+        for s in stale.iter() {
+            println!("  {} / {}", s.budget_name, s.account.name);
+        }
+    }
+    if !confirm("Remove them and their imported transactions from the local database?") {
+        println!("Aborted");
+        return Ok(());
+    }
+
+    let transaction_dir = config::get_transaction_dir(&conn)?;
+    prune_budgets(&mut conn, &stale_budgets)?;
+    prune_accounts(&mut conn, &stale)?;
+    println!(
+        "Removed {} budgets and {} accounts",
+        stale_budgets.len(),
+        stale.len()
+    );
+
+    if args.archive {
+        for s in stale_budgets.iter() {
+            archive_budget_dir(&transaction_dir, s)?;
+        }
+        for s in stale.iter() {
+            archive_account_dir(&transaction_dir, s)?;
+        }
+        println!("Archived folders");
+    }
+    Ok(())
+}
@@ -0,0 +1,395 @@
+use anyhow::Result;
+use image::EncodableLayout;
+use notify_debouncer_full::notify::RecursiveMode;
+use notify_debouncer_full::{new_debouncer, DebounceEventResult};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
+use tray_icon::{
+    menu::{Menu, MenuEvent, MenuId, MenuItem},
+    Icon, TrayIcon, TrayIconBuilder,
+};
+use ynab_importer::{
+    db::{config, config::ScanOrder, open_db_and_migrate},
+    event::{EventHandler, Handled},
+    health, instance,
+    logging::{self, LogLevel},
+    reconcile::reconcile_check,
+    scan::{existing_statements, import_existing},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Import the statements already in the monitored folder, then exit instead of watching it
+    #[arg(long)]
+    once: bool,
+
+    // Print the transactions the statements already in the monitored folder would create, then
+    // exit. Nothing is sent to YNAB or written to the DB.
+    #[arg(long)]
+    dry_run: bool,
+
+    // Serve /healthz and /metrics on this address while watching, e.g. 127.0.0.1:9180
+    #[arg(long)]
+    http_addr: Option<SocketAddr>,
+}
+
+// Prints what importing each statement in watch_dir would send to YNAB
+fn dry_run(event_handler: &EventHandler, watch_dir: &Path, scan_order: ScanOrder) -> Result<()> {
+    for path in existing_statements(event_handler, watch_dir, scan_order)? {
+        let prepared = match event_handler.prepare_import(&path) {
+            Ok(prepared) => prepared,
+            Err(err) => {
+                println!("{}: {:?}\n", path.display(), err);
+                continue;
+            }
+        };
+        println!(
+            "{} -> {} / {}: {} to create, {} to link as transfers, {} already imported",
+            path.display(),
+            prepared.budget.name,
+            prepared.account.name,
+            prepared.transactions.len(),
+            prepared.transfers.len(),
+            prepared.skipped_existing.len()
+        );
+        if let Some(earlier) = &prepared.overlapping {
+            println!(
+                "  Overlaps {} ({} to {})",
+                earlier.path, earlier.start, earlier.end
+            );
+        }
+        for t in prepared.transactions {
+            println!(
+                "  {}  {:>10.2}  {:<32}  {}",
+                t.date.unwrap_or_default(),
+                t.amount.unwrap_or_default() as f64 / 1000.0,
+                t.payee_name.flatten().unwrap_or_default(),
+                t.import_id.flatten().unwrap_or_default()
+            );
+        }
+        println!();
+    }
+    Ok(())
+}
+
+// How long a file which was still being written waits before it's checked again
+const REQUEUE_DELAY: Duration = Duration::from_secs(1);
+
+// How many times a file is checked before giving up on it, about a minute of it still being written
+const MAX_SETTLE_ATTEMPTS: u32 = 60;
+
+// Queues files which weren't ready to be imported yet, along with how many times they've been
+// checked. One is tried again every REQUEUE_DELAY, going to the back of the queue if it still isn't
+// ready, so a file that never settles doesn't hold up the others.
+fn requeue_unsettled(
+    result: Result<Handled>,
+    attempts: u32,
+    unsettled: &mut VecDeque<(PathBuf, u32)>,
+) {
+    match result {
+        Ok(Handled::Done) => (),
+        Ok(Handled::Unsettled(path)) if attempts >= MAX_SETTLE_ATTEMPTS => {
+            error!(path = %path.display(), "Gave up waiting for file to settle");
+        }
+        Ok(Handled::Unsettled(path)) => {
+            if !unsettled.iter().any(|(queued, _)| *queued == path) {
+                unsettled.push_back((path, attempts));
+            }
+        }
+        Err(err) => error!("{:?}", err),
+    }
+}
+
+// How often the imported transactions are compared with YNAB's while watching
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// How often the tray loop checks for menu clicks and whether the watcher has stopped
+const TRAY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Resolves on Ctrl-C, or SIGTERM on Unix
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            res = tokio::signal::ctrl_c() => res?,
+            _ = terminate.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+// Handles file system events until `shutdown` is set or the process is signalled to stop. An event
+// which is being handled when that happens is allowed to finish first, so an import is never cut
+// off between creating the YNAB transactions and recording them in the DB.
+async fn watch_folder(
+    event_handler: EventHandler,
+    watch_dir: PathBuf,
+    debounce: Duration,
+    recursive_mode: RecursiveMode,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let (tx_fs, mut rx_fs) = mpsc::unbounded_channel();
+    let mut debouncer = new_debouncer(debounce, None, move |res: DebounceEventResult| {
+        // Only fails once the receiver is dropped, i.e. we're shutting down
+        let _ = tx_fs.send(res);
+    })?;
+    debouncer.watch(&watch_dir, recursive_mode)?;
+    event_handler.metrics.set_watching(true);
+
+    let signal = async {
+        if let Err(err) = shutdown_signal().await {
+            // Keep running, the tray's Exit item still works
+            warn!("Failed to listen for shutdown signals: {:?}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    tokio::pin!(signal);
+
+    // The first check is a full interval after starting, rather than straight away
+    let mut reconcile_timer = tokio::time::interval_at(
+        tokio::time::Instant::now() + RECONCILE_INTERVAL,
+        RECONCILE_INTERVAL,
+    );
+
+    let mut unsettled = VecDeque::new();
+    loop {
+        tokio::select! {
+            // The sender is only dropped without setting it if the tray failed, which leaves the
+            // watcher running until the process is signalled
+            Ok(()) = shutdown.changed() => break,
+            _ = &mut signal => {
+                info!("Received shutdown signal");
+                break;
+            }
+            res = rx_fs.recv() => match res {
+                Some(Ok(events)) => {
+                    for event in events {
+                        requeue_unsettled(event_handler.handle(&event).await, 1, &mut unsettled);
+                    }
+                }
+                Some(Err(e)) => error!("watch error: {:?}", e),
+                None => break,
+            },
+            _ = tokio::time::sleep(REQUEUE_DELAY), if !unsettled.is_empty() => {
+                let Some((path, attempts)) = unsettled.pop_front() else {
+                    continue;
+                };
+                // Removed (or already imported from a later event) in the meantime
+                if path.exists() {
+                    let result = event_handler.handle_path(&path).await;
+                    requeue_unsettled(result, attempts + 1, &mut unsettled);
+                }
+            }
+            _ = reconcile_timer.tick() => {
+                // Gaps are logged as they're found
+                if let Err(err) = reconcile_check(&event_handler).await {
+                    warn!("Failed to compare imported transactions with YNAB: {:?}", err);
+                }
+            }
+        }
+    }
+    event_handler.metrics.set_watching(false);
+    debouncer.stop();
+    info!(dir = %watch_dir.display(), "Stopped watching");
+    Ok(())
+}
+
+struct Tray {
+    // Kept alive for as long as the icon should be shown
+    _icon: TrayIcon,
+    exit_id: MenuId,
+}
+
+fn build_tray(watch_dir: &Path) -> Result<Tray> {
+    let icon = image::open(Path::new("./img/Yi.png"))?.to_rgba8();
+    let (icon_width, icon_height) = icon.dimensions();
+
+    let status = MenuItem::new(format!("Watching {}", watch_dir.display()), false, None);
+    let exit = MenuItem::new("Exit", true, None);
+    let tray_menu = Menu::new();
+    tray_menu.append(&status)?;
+    tray_menu.append(&exit)?;
+
+    let tray_icon = TrayIconBuilder::new()
+        .with_tooltip("YNAB-importer")
+        .with_icon(Icon::from_rgba(
+            icon.as_bytes().to_vec(),
+            icon_width,
+            icon_height,
+        )?)
+        .with_menu(Box::new(tray_menu))
+        .build()?;
+
+    Ok(Tray {
+        _icon: tray_icon,
+        exit_id: exit.id().clone(),
+    })
+}
+
+impl Tray {
+    // Returns true once the service should stop, either because Exit was clicked or because the
+    // watcher stopped on its own
+    fn poll(&self, shutdown: &watch::Sender<bool>) -> bool {
+        while let Ok(event) = MenuEvent::receiver().try_recv() {
+            if event.id == self.exit_id {
+                shutdown.send_replace(true);
+            }
+        }
+        *shutdown.borrow() || shutdown.is_closed()
+    }
+}
+
+// The tray needs a GTK main loop on Linux, and a native event loop on the main thread on the other
+// platforms. Either way this blocks until the service should stop.
+#[cfg(target_os = "linux")]
+fn run_tray(watch_dir: &Path, shutdown: watch::Sender<bool>) -> Result<()> {
+    gtk::init()?;
+    let tray = build_tray(watch_dir)?;
+    gtk::glib::timeout_add_local(TRAY_POLL_INTERVAL, move || {
+        if tray.poll(&shutdown) {
+            gtk::main_quit();
+            gtk::glib::ControlFlow::Break
+        } else {
+            gtk::glib::ControlFlow::Continue
+        }
+    });
+    gtk::main();
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run_tray(watch_dir: &Path, shutdown: watch::Sender<bool>) -> Result<()> {
+    use std::time::Instant;
+    use winit::application::ApplicationHandler;
+    use winit::event::{StartCause, WindowEvent};
+    use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
+    use winit::window::WindowId;
+
+    struct TrayApp<'a> {
+        watch_dir: &'a Path,
+        shutdown: watch::Sender<bool>,
+        tray: Option<Tray>,
+        error: Option<anyhow::Error>,
+    }
+
+    impl ApplicationHandler for TrayApp<'_> {
+        fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: StartCause) {
+            // On macOS the icon can only be created once the event loop is running
+            if cause == StartCause::Init {
+                match build_tray(self.watch_dir) {
+                    Ok(tray) => self.tray = Some(tray),
+                    Err(err) => {
+                        self.error = Some(err);
+                        event_loop.exit();
+                    }
+                }
+            }
+        }
+
+        fn resumed(&mut self, _event_loop: &ActiveEventLoop) {}
+
+        fn window_event(&mut self, _: &ActiveEventLoop, _: WindowId, _: WindowEvent) {}
+
+        fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+            if let Some(tray) = &self.tray {
+                if tray.poll(&self.shutdown) {
+                    event_loop.exit();
+                    return;
+                }
+            }
+            event_loop
+                .set_control_flow(ControlFlow::WaitUntil(Instant::now() + TRAY_POLL_INTERVAL));
+        }
+    }
+
+    let event_loop = EventLoop::new()?;
+    let mut app = TrayApp {
+        watch_dir,
+        shutdown,
+        tray: None,
+        error: None,
+    };
+    event_loop.run_app(&mut app)?;
+    match app.error {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+// Runs the service until it is told to stop. Unlike the other commands it sets up logging itself,
+// since the log file's location is stored in the DB.
+pub fn run(args: Args, log_level: Option<LogLevel>, runtime: Runtime) -> Result<()> {
+    // Two services watching the same folder would race each other importing the same files. A dry
+    // run doesn't import anything, so it can run alongside the service.
+    let _lock = match args.dry_run {
+        true => None,
+        false => Some(instance::acquire(&instance::service_lock_path()?)?),
+    };
+
+    let db_conn = open_db_and_migrate()?;
+
+    // The service usually runs detached, so everything is also written to a log file
+    let _log_guard = logging::init_with_file(log_level, &config::get_log_dir(&db_conn)?)?;
+
+    let watch_dir = config::get_transaction_dir(&db_conn)?;
+    info!(dir = %watch_dir.display(), "Watching");
+    let concurrency = config::get_import_concurrency(&db_conn)?;
+    let scan_order = config::get_scan_order(&db_conn)?;
+    let debounce = Duration::from_secs(config::get_debounce_seconds(&db_conn)?);
+    let recursive_mode = match config::get_watch_recursive(&db_conn)? {
+        true => RecursiveMode::Recursive,
+        false => RecursiveMode::NonRecursive,
+    };
+    let event_handler = EventHandler::new(db_conn)?;
+    if args.dry_run {
+        return dry_run(&event_handler, &watch_dir, scan_order);
+    }
+
+    runtime.block_on(import_existing(
+        &event_handler,
+        &watch_dir,
+        scan_order,
+        concurrency,
+    ))?;
+    if args.once {
+        return Ok(());
+    }
+    if let Some(addr) = args.http_addr {
+        health::serve(addr, event_handler.metrics.clone())?;
+    }
+
+    // The tray has to own the main thread, so the watcher runs on its own
+    let (tx_shutdown, rx_shutdown) = watch::channel(false);
+    let watcher = {
+        let watch_dir = watch_dir.clone();
+        thread::spawn(move || {
+            runtime.block_on(watch_folder(
+                event_handler,
+                watch_dir,
+                debounce,
+                recursive_mode,
+                rx_shutdown,
+            ))
+        })
+    };
+
+    // Without a tray (e.g. no desktop session) the service keeps watching, it can still be stopped
+    // with a signal
+    if let Err(err) = run_tray(&watch_dir, tx_shutdown) {
+        warn!(
+            "Failed to show the tray icon, continuing without it: {:#}",
+            err
+        );
+    }
+    watcher.join().expect("Watcher thread panicked")
+}
@@ -0,0 +1,43 @@
+use anyhow::Result;
+use ynab_importer::db::{account, budget, open_db_and_migrate};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Name of the budget the account belongs to
+    #[arg(short, long)]
+    budget: String,
+
+    // Name of the account in YNAB
+    #[arg(short, long)]
+    account: String,
+
+    // Treat statement transactions within this many milliunits (1000 = $1.00) of an already imported
+    // transaction on the same date as duplicates. 0 only matches exact amounts.
+    #[arg(short, long)]
+    milliunits: u32,
+
+    // Also match transactions posted up to this many days apart, e.g. a pending card payment which
+    // settles a day or two later. Left unchanged if not given (0, only the same date, by default).
+    #[arg(short, long)]
+    days: Option<u32>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+
+    let budget = budget::with_name(&conn, &args.budget)?;
+    let account = account::with_budget_and_name(&conn, budget.id, &args.account)?;
+    account::set_amount_tolerance(&conn, account.id, args.milliunits as i64)?;
+    println!(
+        "Set amount tolerance of {} / {} to {} milliunits",
+        budget.name, account.name, args.milliunits
+    );
+    if let Some(days) = args.days {
+        account::set_date_tolerance(&conn, account.id, days as i64)?;
+        println!(
+            "Set date tolerance of {} / {} to {} days",
+            budget.name, account.name, days
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,43 @@
+use anyhow::{anyhow, Result};
+use chrono::format::{Item, StrftimeItems};
+use ynab_importer::db::{account, budget, open_db_and_migrate};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Name of the budget the account belongs to
+    #[arg(short, long)]
+    budget: String,
+
+    // Name of the account in YNAB
+    #[arg(short, long)]
+    account: String,
+
+    // chrono format the bank writes statement dates in, e.g. %Y-%m-%d. It's tried before the
+    // standard OFX formats. Leave it out to only use those again.
+    #[arg(short, long)]
+    format: Option<String>,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+
+    if let Some(format) = &args.format {
+        if StrftimeItems::new(format).any(|item| item == Item::Error) {
+            return Err(anyhow!("Invalid date format '{}'", format));
+        }
+    }
+    let budget = budget::with_name(&conn, &args.budget)?;
+    let account = account::with_budget_and_name(&conn, budget.id, &args.account)?;
+    account::set_date_format(&conn, account.id, args.format.as_deref())?;
+    match args.format {
+        Some(format) => println!(
+            "Set date format of {} / {} to {}",
+            budget.name, account.name, format
+        ),
+        None => println!(
+            "Removed the date format of {} / {}",
+            budget.name, account.name
+        ),
+    }
+    Ok(())
+}
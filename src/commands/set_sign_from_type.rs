@@ -0,0 +1,31 @@
+use anyhow::Result;
+use ynab_importer::db::{account, budget, open_db_and_migrate};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Name of the budget the account belongs to
+    #[arg(short, long)]
+    budget: String,
+
+    // Name of the account in YNAB
+    #[arg(short, long)]
+    account: String,
+
+    // Go back to using the sign of TRNAMT as is
+    #[arg(long)]
+    disable: bool,
+}
+
+pub fn run(args: Args) -> Result<()> {
+    let conn = open_db_and_migrate()?;
+
+    let budget = budget::with_name(&conn, &args.budget)?;
+    let account = account::with_budget_and_name(&conn, budget.id, &args.account)?;
+    account::set_sign_from_kind(&conn, account.id, !args.disable)?;
+    let state = if args.disable { "Disabled" } else { "Enabled" };
+    println!(
+        "{} taking the sign of {} / {} amounts from TRNTYPE",
+        state, budget.name, account.name
+    );
+    Ok(())
+}
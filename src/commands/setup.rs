@@ -0,0 +1,177 @@
+use super::OutputFormat;
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use serde_json::json;
+use std::ffi::OsString;
+use std::io;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use ynab_api::apis::budgets_api::get_budgets;
+use ynab_api::apis::configuration::Configuration;
+use ynab_api::apis::user_api::get_user;
+use ynab_api::models::BudgetSummary;
+use ynab_importer::auth;
+use ynab_importer::db::config::{self, FolderDepth};
+use ynab_importer::db::open_db_and_migrate;
+use ynab_importer::setup::{check_transaction_dir, env_token, find_budget, read_token, run_setup};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Path to your personal access token. Not needed after signing in with the login command, or
+    // when the token is in the YNAB_ACCESS_TOKEN environment variable (which takes precedence).
+    #[arg(short, long)]
+    access_token: Option<PathBuf>,
+
+    // Name or id of the budget to set up. Without it you're asked to pick one if there are several.
+    #[arg(short, long)]
+    budget: Option<String>,
+
+    // Folder to monitor for transaction exports
+    #[arg(short, long)]
+    transaction_dir: OsString,
+
+    // Whether to create account subfolders or route statements by ACCTID within budget folders
+    #[arg(short, long, value_enum, default_value_t)]
+    folder_depth: FolderDepth,
+
+    // Skip downloading existing YNAB transactions and categories. Statements overlapping history
+    // from before setup may then create duplicates, and category rules can't be added.
+    #[arg(long)]
+    no_sync: bool,
+
+    // Only sync transactions from this date (YYYY-MM-DD) on, rather than each account's whole
+    // history
+    #[arg(long)]
+    since: Option<NaiveDate>,
+
+    // File extensions the service imports, e.g. qfx,ofx,qbo. Leaves the current setting (qfx,ofx by
+    // default) alone if not given.
+    #[arg(long, value_delimiter = ',')]
+    import_extensions: Option<Vec<String>>,
+
+    // With json, the progress messages and any error are printed as a single object once setup is
+    // done. The budget isn't prompted for, so pass --budget if there are several.
+    #[arg(long, value_enum, default_value_t)]
+    format: OutputFormat,
+}
+
+pub fn read_prompt_int(options: &[usize]) -> usize {
+    loop {
+        io::stdout().flush().expect("stdout flush failed");
+        let mut input = String::new();
+        io::stdin()
+            .read_line(&mut input)
+            .expect("Failed to read line");
+        match input.trim().parse::<usize>() {
+            Ok(n) => {
+                if !options.contains(&n) {
+                    print!("Invalid selection. Please try again: ");
+                    continue;
+                }
+                return n;
+            }
+            Err(error) => {
+                println!("Error: {error}");
+                print!("Please try again: ");
+            }
+        }
+    }
+}
+
+pub fn prompt_budget(budgets: &[BudgetSummary]) -> &BudgetSummary {
+    println!("The following budgets were found for this account:");
+    for (i, b) in budgets.iter().enumerate() {
+        println!("[{}]: {}", i + 1, b.name);
+    }
+    print!("Enter the account you like to use [1-{}]: ", budgets.len());
+    let sel = read_prompt_int(&Vec::from_iter(1..=budgets.len()));
+    &budgets[sel - 1]
+}
+
+// Sets up the budget, passing each progress message to on_message
+async fn setup(args: Args, mut on_message: impl FnMut(String)) -> Result<()> {
+    let transaction_dir = check_transaction_dir(&PathBuf::from(args.transaction_dir))?;
+
+    let conn = open_db_and_migrate()?;
+    config::set_initial_sync(&conn, !args.no_sync)?;
+    config::set_sync_since(&conn, args.since)?;
+    if let Some(extensions) = &args.import_extensions {
+        config::set_import_extensions(&conn, extensions)?;
+    }
+
+    // Only a token file is stored for the service to use. One from the environment is used for this
+    // run alone, leaving whatever was stored before in place for when it's unset.
+    let (token, store_token) = match (env_token()?, &args.access_token) {
+        (Some(token), _) => (token, false),
+        (None, Some(path)) => (read_token(path)?, true),
+        (None, None) => {
+            let token = auth::access_token(&conn).await.context(
+                "Pass --access-token, set YNAB_ACCESS_TOKEN, or sign in with the login command \
+                first",
+            )?;
+            (token, false)
+        }
+    };
+
+    let mut api_config = Configuration::new();
+    api_config.bearer_access_token = Some(token.clone());
+
+    let user_id = get_user(&api_config).await?.data.user.id;
+    // Stored once YNAB has accepted it. A PAT replaces any OAuth login, which would otherwise
+    // overwrite it on refresh.
+    if store_token {
+        auth::logout(&conn)?;
+        config::set(&conn, config::ACCESS_TOKEN, &token)?;
+    }
+    let budget_response = get_budgets(&api_config, Some(true)).await?;
+    let budgets = budget_response.data.budgets;
+    if budgets.is_empty() {
+        return Err(anyhow!("Account has no budgets"));
+    }
+
+    let budget = match &args.budget {
+        Some(budget) => find_budget(&budgets, budget)?.clone(),
+        None if budgets.len() == 1 => budgets[0].clone(),
+        None if args.format == OutputFormat::Text && io::stdin().is_terminal() => {
+            prompt_budget(&budgets).clone()
+        }
+        // Nobody to answer the prompt, e.g. when scripted
+        None => {
+            return Err(anyhow!(
+                "Found {} budgets, pass --budget with the name of the one to use",
+                budgets.len()
+            ))
+        }
+    };
+
+    let (sx, rx) = mpsc::channel();
+    let handle = tokio::task::spawn_blocking(move || {
+        run_setup(
+            conn,
+            &api_config,
+            user_id,
+            &transaction_dir,
+            vec![budget],
+            args.folder_depth,
+            sx,
+        )
+    });
+    for msg in rx {
+        on_message(msg);
+    }
+    handle.await?
+}
+
+pub async fn run(args: Args) -> Result<()> {
+    match args.format {
+        OutputFormat::Text => setup(args, |msg| println!("{}", msg)).await,
+        OutputFormat::Json => {
+            let mut messages = Vec::new();
+            let result = setup(args, |msg| messages.push(msg)).await;
+            let error = result.as_ref().err().map(|err| format!("{:#}", err));
+            println!("{}", json!({ "messages": messages, "error": error }));
+            result
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::Result;
+use chrono::DateTime;
+use ynab_importer::db::{account, budget, open_db_and_migrate, transaction};
+
+// Prints a table of the transactions recorded for each account: how many, the dates they cover and
+// when one was last imported. Only reads from the DB.
+pub fn run() -> Result<()> {
+    let conn = open_db_and_migrate()?;
+    let mut budgets = budget::get_all(&conn)?;
+    budgets.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut accounts = account::get_all(&conn)?;
+    accounts.sort_by(|a, b| a.name.cmp(&b.name));
+
+    println!(
+        "{:<24}  {:<24}  {:>8}  {:<10}  {:<10}  {}",
+        "Budget", "Account", "Count", "From", "To", "Last import"
+    );
+    for budget in budgets.iter() {
+        for acc in accounts.iter().filter(|a| a.budget_id == budget.id) {
+            let counts = transaction::count_and_range_for_account(&conn, acc.id)?;
+            let last_import = counts
+                .last_imported_at
+                .as_deref()
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+                .map_or("-".into(), |t| t.format("%Y-%m-%d %H:%M").to_string());
+            println!(
+                "{:<24}  {:<24}  {:>8}  {:<10}  {:<10}  {}",
+                budget.name,
+                acc.name,
+                counts.count,
+                counts.first_date.as_deref().unwrap_or("-"),
+                counts.last_date.as_deref().unwrap_or("-"),
+                last_import
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,56 @@
+use anyhow::{anyhow, Result};
+use std::fs;
+use std::path::PathBuf;
+use ynab_importer::db::{config, open_db_and_migrate};
+use ynab_importer::ofx::{load_transactions, ParseOptions};
+
+#[derive(clap::Args, Debug)]
+pub struct Args {
+    // Directory of sample statements to parse
+    #[arg(default_value = "test_files")]
+    dir: PathBuf,
+}
+
+// Statements are parsed as they would be on import, with any extra entities from the config
+pub fn run(args: Args) -> Result<()> {
+    let options = ParseOptions {
+        entities: config::get_ofx_entities(&open_db_and_migrate()?)?,
+        ..Default::default()
+    };
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(&args.dir)? {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let rows: Vec<(String, String)> = paths
+        .iter()
+        .map(|p| {
+            let name = p
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let result = match load_transactions(p, &options) {
+                Ok(transactions) => format!("{} transactions", transactions.len()),
+                Err(err) => format!("ERROR: {:#}", err),
+            };
+            (name, result)
+        })
+        .collect();
+
+    let width = rows.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    println!("{:<width$}  RESULT", "FILE");
+    for (name, result) in rows.iter() {
+        println!("{:<width$}  {}", name, result);
+    }
+
+    let failed = rows.iter().filter(|(_, r)| r.starts_with("ERROR")).count();
+    println!("\n{} files, {} failed to parse", rows.len(), failed);
+    if failed > 0 {
+        return Err(anyhow!("{} files failed to parse", failed));
+    }
+    Ok(())
+}
@@ -1,20 +1,80 @@
 use anyhow::Result;
-use rusqlite::types::{FromSql, FromSqlError};
+use rusqlite::types::{FromSql, FromSqlError, Type};
 use rusqlite::{self, ToSql};
 use rusqlite::{params, Connection, OptionalExtension};
-use std::env::current_exe;
+use std::env::{self, current_exe};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use uuid::Uuid;
 use ynab_api::models::Account;
 use ynab_api::models::BudgetSummary;
+use ynab_api::models::TransactionClearedStatus;
+
+// How long a connection waits for another one (e.g. the service while the GUI is open) to finish
+// writing before giving up with "database is locked"
+const BUSY_TIMEOUT: Duration = Duration::from_millis(5000);
+
+// Overrides where the DB is stored. The --db argument is passed on through this too, so that every
+// connection a command opens uses the same file.
+pub const DB_PATH_VAR: &str = "YNAB_IMPORTER_DB";
+
+const DB_FILE_NAME: &str = "db.sqlite";
+
+// Where the DB lives: $YNAB_IMPORTER_DB (or --db, which takes precedence by setting it) if set,
+// otherwise db.sqlite next to the executable
+pub fn db_path() -> Result<PathBuf> {
+    Ok(resolve_db_path(env::var_os(DB_PATH_VAR), &current_exe()?))
+}
+
+fn resolve_db_path(override_path: Option<OsString>, exe: &Path) -> PathBuf {
+    match override_path {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => exe.with_file_name(DB_FILE_NAME),
+    }
+}
 
 pub fn get_sqlite_conn() -> Result<Connection> {
-    let mut pb = current_exe()?;
-    pb.pop();
-    pb.push("db.sqlite");
-    let conn = Connection::open(pb.as_path())?;
+    open(&db_path()?)
+}
+
+// WAL lets readers carry on while another connection writes, and the busy timeout makes
+// overlapping writes wait their turn instead of failing. SQLite leaves foreign keys unenforced unless
+// asked to.
+fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)?;
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    Ok(conn)
+}
+
+mod embedded {
+    refinery::embed_migrations!("migrations");
+}
+
+// Opens the DB and brings its schema up to date. Every command goes through this rather than
+// get_sqlite_conn, so none of them can run against an old schema.
+pub fn open_db_and_migrate() -> Result<Connection> {
+    let mut conn = get_sqlite_conn()?;
+    embedded::migrations::runner().run(&mut conn)?;
     Ok(conn)
 }
 
+// Brings a DB opened by a test up to date
+#[cfg(test)]
+pub(crate) fn migrate(conn: &mut Connection) {
+    embedded::migrations::runner().run(conn).unwrap();
+}
+
+// An empty in-memory DB with the current schema, for tests
+#[cfg(test)]
+pub(crate) fn test_conn() -> Connection {
+    let mut conn = Connection::open_in_memory().unwrap();
+    migrate(&mut conn);
+    conn
+}
+
 // Wrapper around Uuid that can be saved/loaded from sqlite db automatically
 struct DbUuid(pub Uuid);
 
@@ -48,15 +108,117 @@ impl ToSql for DbUuid {
 
 pub mod config {
     use std::{
+        collections::BTreeMap,
         ffi::OsString,
         path::{Path, PathBuf},
     };
 
     use super::*;
+    use anyhow::anyhow;
+    use chrono::{DateTime, NaiveDate, Utc};
+    use url::Url;
 
     pub const USER_ID: &str = "user_id";
     pub const ACCESS_TOKEN: &str = "access_token";
     pub const TRANSACTION_DIR: &str = "transaction_dir";
+    pub const FOLDER_DEPTH: &str = "folder_depth";
+    pub const INITIAL_SYNC: &str = "initial_sync";
+    pub const TOKEN_PATH: &str = "token_path";
+    pub const IMPORT_CONCURRENCY: &str = "import_concurrency";
+    pub const SYNC_CONCURRENCY: &str = "sync_concurrency";
+    pub const SCAN_ORDER: &str = "scan_order";
+    pub const THEME: &str = "theme";
+    pub const WINDOW_GEOMETRY: &str = "window_geometry";
+    pub const ARCHIVE_DIR: &str = "archive_dir";
+    pub const IMPORT_EXTENSIONS: &str = "import_extensions";
+    pub const MAX_RETRIES: &str = "max_retries";
+    pub const API_RETRIES: &str = "api_retries";
+    pub const LOG_DIR: &str = "log_dir";
+    pub const NOTIFICATIONS_ENABLED: &str = "notifications_enabled";
+    pub const DEBOUNCE_SECONDS: &str = "debounce_seconds";
+    pub const WATCH_RECURSIVE: &str = "watch_recursive";
+    pub const REPROCESS_FILES: &str = "reprocess_files";
+    pub const OAUTH_CLIENT_ID: &str = "oauth_client_id";
+    pub const TOKEN_EXPIRES_AT: &str = "token_expires_at";
+    pub const SYNC_SINCE: &str = "sync_since";
+    pub const BUDGET_CACHE: &str = "budget_cache";
+    pub const OFX_ENTITIES: &str = "ofx_entities";
+    pub const WEBHOOK_URL: &str = "webhook_url";
+
+    // Folder (relative to the transaction dir) imported statements are moved into by default
+    pub const DEFAULT_ARCHIVE_DIR_NAME: &str = ".imported";
+    pub const DEFAULT_LOG_DIR_NAME: &str = ".ynab-importer";
+
+    // Layout of the monitored folder. With BudgetAccount statements are filed under
+    // <budget>/<account>/, with BudgetOnly they go directly in <budget>/ and are routed to an
+    // account using the ACCTID in the file.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+    pub enum FolderDepth {
+        #[default]
+        BudgetAccount,
+        BudgetOnly,
+    }
+
+    impl FolderDepth {
+        fn as_str(&self) -> &'static str {
+            match self {
+                FolderDepth::BudgetAccount => "budget_account",
+                FolderDepth::BudgetOnly => "budget_only",
+            }
+        }
+    }
+
+    // Order in which statements already in the monitored folder are imported on startup
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+    pub enum ScanOrder {
+        // File modification time, oldest first
+        #[default]
+        Mtime,
+        // Latest transaction date in the statement, oldest first
+        StatementDate,
+    }
+
+    impl ScanOrder {
+        fn as_str(&self) -> &'static str {
+            match self {
+                ScanOrder::Mtime => "mtime",
+                ScanOrder::StatementDate => "statement_date",
+            }
+        }
+    }
+
+    // Colour theme of the config GUI
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub enum GuiTheme {
+        #[default]
+        Dark,
+        Light,
+    }
+
+    impl GuiTheme {
+        fn as_str(&self) -> &'static str {
+            match self {
+                GuiTheme::Dark => "dark",
+                GuiTheme::Light => "light",
+            }
+        }
+    }
+
+    // Size and (if known) outer position of the config GUI window, in points
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct WindowGeometry {
+        pub size: [f32; 2],
+        pub position: Option<[f32; 2]>,
+    }
+
+    // The budgets (with their accounts) last fetched for the config GUI, so that it can show them
+    // straight away on the next launch while fresh ones are fetched
+    #[derive(Clone, Debug)]
+    pub struct BudgetCache {
+        pub user_id: Uuid,
+        pub budgets: Vec<BudgetSummary>,
+        pub fetched_at: DateTime<Utc>,
+    }
 
     // Set the key value pair in configuration table
     pub fn set(conn: &Connection, key: &str, value: &str) -> Result<usize> {
@@ -68,14 +230,31 @@ pub mod config {
         Ok(id)
     }
 
-    // Get a value from configuration table
+    // Get a value from configuration table. Fails if the key was never set, for values setup is
+    // expected to have stored.
     pub fn get(conn: &Connection, key: &str) -> Result<String> {
+        get_opt(conn, key)?.ok_or_else(|| anyhow!("{} is not configured, run setup first", key))
+    }
+
+    // Get a value from configuration table, or None if the key was never set
+    pub fn get_opt(conn: &Connection, key: &str) -> Result<Option<String>> {
         let s = conn
             .prepare("SELECT value FROM configuration WHERE key=?1;")?
-            .query_row(params![key], |row| row.get(0))?;
+            .query_row(params![key], |row| row.get(0))
+            .optional()?;
         Ok(s)
     }
 
+    // Remove a key from configuration table, so it reads as never set
+    pub fn unset(conn: &Connection, key: &str) -> Result<usize> {
+        Ok(conn.execute("DELETE FROM configuration WHERE key=?1;", params![key])?)
+    }
+
+    // Get a value from configuration table, or default if the key was never set
+    pub fn get_or_default(conn: &Connection, key: &str, default: &str) -> Result<String> {
+        Ok(get_opt(conn, key)?.unwrap_or_else(|| default.into()))
+    }
+
     pub fn set_transaction_dir(conn: &Connection, path: &Path) -> Result<usize> {
         set(
             conn,
@@ -89,9 +268,310 @@ pub mod config {
         let path = PathBuf::from(serde_json::from_str::<OsString>(&ser)?);
         Ok(path)
     }
+
+    pub fn set_archive_dir(conn: &Connection, path: &Path) -> Result<usize> {
+        set(conn, ARCHIVE_DIR, &serde_json::to_string(path.as_os_str())?)
+    }
+
+    // Defaults to <transaction_dir>/.imported
+    pub fn get_archive_dir(conn: &Connection) -> Result<PathBuf> {
+        match get_opt(conn, ARCHIVE_DIR)? {
+            None => Ok(get_transaction_dir(conn)?.join(DEFAULT_ARCHIVE_DIR_NAME)),
+            Some(ser) => Ok(PathBuf::from(serde_json::from_str::<OsString>(&ser)?)),
+        }
+    }
+
+    pub fn set_log_dir(conn: &Connection, path: &Path) -> Result<usize> {
+        set(conn, LOG_DIR, &serde_json::to_string(path.as_os_str())?)
+    }
+
+    // Where the service writes its log files. Defaults to <transaction_dir>/.ynab-importer
+    pub fn get_log_dir(conn: &Connection) -> Result<PathBuf> {
+        match get_opt(conn, LOG_DIR)? {
+            None => Ok(get_transaction_dir(conn)?.join(DEFAULT_LOG_DIR_NAME)),
+            Some(ser) => Ok(PathBuf::from(serde_json::from_str::<OsString>(&ser)?)),
+        }
+    }
+
+    pub fn set_import_extensions(conn: &Connection, extensions: &[String]) -> Result<usize> {
+        set(conn, IMPORT_EXTENSIONS, &extensions.join(","))
+    }
+
+    // File extensions (lowercase, without the dot) the service imports. Stored comma-separated and
+    // defaults to qfx,ofx.
+    pub fn get_import_extensions(conn: &Connection) -> Result<Vec<String>> {
+        match get_opt(conn, IMPORT_EXTENSIONS)? {
+            None => Ok(vec!["qfx".into(), "ofx".into()]),
+            Some(value) => Ok(parse_extensions(&value)),
+        }
+    }
+
+    fn parse_extensions(value: &str) -> Vec<String> {
+        value
+            .split(',')
+            .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect()
+    }
+
+    pub fn set_max_retries(conn: &Connection, max_retries: usize) -> Result<usize> {
+        set(conn, MAX_RETRIES, &max_retries.to_string())
+    }
+
+    // How many times an import re-posts transactions YNAB rejected as duplicate import ids.
+    // Defaults to 10.
+    pub fn get_max_retries(conn: &Connection) -> Result<usize> {
+        Ok(get_or_default(conn, MAX_RETRIES, "10")?.parse()?)
+    }
+
+    pub fn set_api_retries(conn: &Connection, retries: usize) -> Result<usize> {
+        set(conn, API_RETRIES, &retries.to_string())
+    }
+
+    // How many times a YNAB request which was rate limited or hit a server error is retried before
+    // giving up. Defaults to 5.
+    pub fn get_api_retries(conn: &Connection) -> Result<usize> {
+        Ok(get_or_default(conn, API_RETRIES, "5")?.parse()?)
+    }
+
+    pub fn set_notifications_enabled(conn: &Connection, enabled: bool) -> Result<usize> {
+        set(conn, NOTIFICATIONS_ENABLED, &enabled.to_string())
+    }
+
+    // Whether the service shows a desktop notification after each import. Defaults to true
+    pub fn get_notifications_enabled(conn: &Connection) -> Result<bool> {
+        Ok(get_or_default(conn, NOTIFICATIONS_ENABLED, "true")?.parse()?)
+    }
+
+    pub fn set_debounce_seconds(conn: &Connection, seconds: u64) -> Result<usize> {
+        set(conn, DEBOUNCE_SECONDS, &seconds.to_string())
+    }
+
+    // How long the service waits for file events to stop before handling them. A longer window
+    // helps on network drives, where files take a while to be written. Defaults to 2.
+    pub fn get_debounce_seconds(conn: &Connection) -> Result<u64> {
+        Ok(get_or_default(conn, DEBOUNCE_SECONDS, "2")?.parse()?)
+    }
+
+    pub fn set_watch_recursive(conn: &Connection, recursive: bool) -> Result<usize> {
+        set(conn, WATCH_RECURSIVE, &recursive.to_string())
+    }
+
+    // Whether the service watches the whole tree below the transaction dir, or only files directly
+    // in it. Defaults to true, statements are usually filed in budget and account folders.
+    pub fn get_watch_recursive(conn: &Connection) -> Result<bool> {
+        Ok(get_or_default(conn, WATCH_RECURSIVE, "true")?.parse()?)
+    }
+
+    pub fn set_reprocess_files(conn: &Connection, enabled: bool) -> Result<usize> {
+        set(conn, REPROCESS_FILES, &enabled.to_string())
+    }
+
+    // Whether statements with the same contents as one already processed are imported again
+    // rather than skipped. Defaults to false
+    pub fn get_reprocess_files(conn: &Connection) -> Result<bool> {
+        Ok(get_or_default(conn, REPROCESS_FILES, "false")?.parse()?)
+    }
+
+    // Tokens stored before read_token started trimming may still end in a newline, which the API
+    // rejects
+    pub fn get_access_token(conn: &Connection) -> Result<String> {
+        Ok(get(conn, ACCESS_TOKEN)?.trim().into())
+    }
+
+    pub fn set_token_expires_at(conn: &Connection, expires_at: DateTime<Utc>) -> Result<usize> {
+        set(conn, TOKEN_EXPIRES_AT, &expires_at.to_rfc3339())
+    }
+
+    // When the access token from an OAuth login expires. None for personal access tokens, which
+    // don't.
+    pub fn get_token_expires_at(conn: &Connection) -> Result<Option<DateTime<Utc>>> {
+        match get_opt(conn, TOKEN_EXPIRES_AT)? {
+            None => Ok(None),
+            Some(s) => Ok(Some(DateTime::parse_from_rfc3339(&s)?.with_timezone(&Utc))),
+        }
+    }
+
+    // Only the location of the token file is stored here, never its contents
+    pub fn set_token_path(conn: &Connection, path: &Path) -> Result<usize> {
+        set(conn, TOKEN_PATH, &serde_json::to_string(path.as_os_str())?)
+    }
+
+    pub fn get_token_path(conn: &Connection) -> Result<Option<PathBuf>> {
+        match get_opt(conn, TOKEN_PATH)? {
+            None => Ok(None),
+            Some(ser) => Ok(Some(PathBuf::from(serde_json::from_str::<OsString>(&ser)?))),
+        }
+    }
+
+    pub fn set_folder_depth(conn: &Connection, depth: FolderDepth) -> Result<usize> {
+        set(conn, FOLDER_DEPTH, depth.as_str())
+    }
+
+    // Defaults to BudgetAccount for databases configured before the option existed
+    pub fn get_folder_depth(conn: &Connection) -> Result<FolderDepth> {
+        match get_opt(conn, FOLDER_DEPTH)?.as_deref() {
+            None | Some("budget_account") => Ok(FolderDepth::BudgetAccount),
+            Some("budget_only") => Ok(FolderDepth::BudgetOnly),
+            Some(other) => Err(anyhow!("invalid {} value '{}'", FOLDER_DEPTH, other)),
+        }
+    }
+
+    pub fn set_import_concurrency(conn: &Connection, concurrency: usize) -> Result<usize> {
+        set(conn, IMPORT_CONCURRENCY, &concurrency.to_string())
+    }
+
+    // Maximum number of accounts the service imports statements for at once, each account's own
+    // statements are always imported one at a time. Defaults to 1.
+    pub fn get_import_concurrency(conn: &Connection) -> Result<usize> {
+        Ok(get_or_default(conn, IMPORT_CONCURRENCY, "1")?
+            .parse::<usize>()?
+            .max(1))
+    }
+
+    pub fn set_sync_concurrency(conn: &Connection, concurrency: usize) -> Result<usize> {
+        set(conn, SYNC_CONCURRENCY, &concurrency.to_string())
+    }
+
+    // Maximum number of accounts whose transactions setup fetches at once, to stay clear of YNAB's
+    // rate limit. Defaults to 4.
+    pub fn get_sync_concurrency(conn: &Connection) -> Result<usize> {
+        Ok(get_or_default(conn, SYNC_CONCURRENCY, "4")?
+            .parse::<usize>()?
+            .max(1))
+    }
+
+    pub fn set_scan_order(conn: &Connection, order: ScanOrder) -> Result<usize> {
+        set(conn, SCAN_ORDER, order.as_str())
+    }
+
+    pub fn get_scan_order(conn: &Connection) -> Result<ScanOrder> {
+        match get_opt(conn, SCAN_ORDER)?.as_deref() {
+            None | Some("mtime") => Ok(ScanOrder::Mtime),
+            Some("statement_date") => Ok(ScanOrder::StatementDate),
+            Some(other) => Err(anyhow!("invalid {} value '{}'", SCAN_ORDER, other)),
+        }
+    }
+
+    pub fn set_theme(conn: &Connection, theme: GuiTheme) -> Result<usize> {
+        set(conn, THEME, theme.as_str())
+    }
+
+    pub fn get_theme(conn: &Connection) -> Result<GuiTheme> {
+        match get_opt(conn, THEME)?.as_deref() {
+            None | Some("dark") => Ok(GuiTheme::Dark),
+            Some("light") => Ok(GuiTheme::Light),
+            Some(other) => Err(anyhow!("invalid {} value '{}'", THEME, other)),
+        }
+    }
+
+    pub fn set_window_geometry(conn: &Connection, geometry: WindowGeometry) -> Result<usize> {
+        set(
+            conn,
+            WINDOW_GEOMETRY,
+            &serde_json::to_string(&(geometry.size, geometry.position))?,
+        )
+    }
+
+    pub fn get_window_geometry(conn: &Connection) -> Result<Option<WindowGeometry>> {
+        match get_opt(conn, WINDOW_GEOMETRY)? {
+            None => Ok(None),
+            Some(ser) => {
+                let (size, position) = serde_json::from_str(&ser)?;
+                Ok(Some(WindowGeometry { size, position }))
+            }
+        }
+    }
+
+    // Setting this to false skips downloading existing YNAB transactions during setup. Only files
+    // imported afterwards are recorded for deduplication, so a statement overlapping history from
+    // before setup may create duplicates.
+    pub fn set_initial_sync(conn: &Connection, enabled: bool) -> Result<usize> {
+        set(conn, INITIAL_SYNC, &enabled.to_string())
+    }
+
+    // Defaults to true
+    pub fn get_initial_sync(conn: &Connection) -> Result<bool> {
+        Ok(get_or_default(conn, INITIAL_SYNC, "true")?.parse()?)
+    }
+
+    // Limits the history fetched for newly set up accounts to transactions on or after since. None
+    // (the default) fetches all of it.
+    pub fn set_sync_since(conn: &Connection, since: Option<NaiveDate>) -> Result<usize> {
+        match since {
+            Some(date) => set(conn, SYNC_SINCE, &date.to_string()),
+            None => unset(conn, SYNC_SINCE),
+        }
+    }
+
+    pub fn get_sync_since(conn: &Connection) -> Result<Option<NaiveDate>> {
+        match get_opt(conn, SYNC_SINCE)? {
+            None => Ok(None),
+            Some(s) => Ok(Some(NaiveDate::parse_from_str(&s, "%Y-%m-%d")?)),
+        }
+    }
+
+    // Named entities (name without the & and ;, and the text it stands for) to expand in
+    // statements besides the built in ones, for banks using unusual ones
+    pub fn set_ofx_entities(
+        conn: &Connection,
+        entities: &BTreeMap<String, String>,
+    ) -> Result<usize> {
+        set(conn, OFX_ENTITIES, &serde_json::to_string(entities)?)
+    }
+
+    pub fn get_ofx_entities(conn: &Connection) -> Result<BTreeMap<String, String>> {
+        match get_opt(conn, OFX_ENTITIES)? {
+            None => Ok(BTreeMap::new()),
+            Some(ser) => Ok(serde_json::from_str(&ser)?),
+        }
+    }
+
+    pub fn set_webhook_url(conn: &Connection, url: Option<&Url>) -> Result<usize> {
+        match url {
+            Some(url) => set(conn, WEBHOOK_URL, url.as_str()),
+            None => unset(conn, WEBHOOK_URL),
+        }
+    }
+
+    // Where each successful import is posted to, if anywhere
+    pub fn get_webhook_url(conn: &Connection) -> Result<Option<Url>> {
+        match get_opt(conn, WEBHOOK_URL)? {
+            None => Ok(None),
+            Some(s) => Ok(Some(Url::parse(&s)?)),
+        }
+    }
+
+    pub fn set_budget_cache(conn: &Connection, cache: &BudgetCache) -> Result<usize> {
+        set(
+            conn,
+            BUDGET_CACHE,
+            &serde_json::to_string(&(
+                cache.user_id.to_string(),
+                &cache.budgets,
+                cache.fetched_at.to_rfc3339(),
+            ))?,
+        )
+    }
+
+    pub fn get_budget_cache(conn: &Connection) -> Result<Option<BudgetCache>> {
+        match get_opt(conn, BUDGET_CACHE)? {
+            None => Ok(None),
+            Some(ser) => {
+                let (user_id, budgets, fetched_at): (String, _, String) =
+                    serde_json::from_str(&ser)?;
+                Ok(Some(BudgetCache {
+                    user_id: Uuid::parse_str(&user_id)?,
+                    budgets,
+                    fetched_at: DateTime::parse_from_rfc3339(&fetched_at)?.with_timezone(&Utc),
+                }))
+            }
+        }
+    }
 }
 
 pub mod budget {
+    use anyhow::anyhow;
     use uuid::Uuid;
 
     use super::*;
@@ -135,6 +615,92 @@ pub mod budget {
         Ok(result)
     }
 
+    pub fn get_all(conn: &Connection) -> Result<Vec<BudgetRow>> {
+        let mut stmt = conn.prepare("SELECT id, uuid, name FROM budget;")?;
+        let result = stmt.query_map([], |row| {
+            Ok(BudgetRow {
+                id: row.get(0)?,
+                uuid: row.get::<usize, DbUuid>(1)?.into(),
+                name: row.get(2)?,
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        Ok(rows)
+    }
+
+    // Records the (sanitized) folder name created for the budget
+    pub fn set_dir_name(conn: &Connection, budget_id: i64, dir_name: &str) -> Result<()> {
+        conn.execute(
+            "UPDATE budget SET dir_name = ?1 WHERE id = ?2",
+            params![dir_name, budget_id],
+        )?;
+        Ok(())
+    }
+
+    // Budgets set up before folder names were recorded use their name as is
+    pub fn dir_name(conn: &Connection, budget_id: i64) -> Result<String> {
+        let mut stmt = conn.prepare("SELECT COALESCE(dir_name, name) FROM budget WHERE id = ?")?;
+        Ok(stmt.query_row([budget_id], |row| row.get(0))?)
+    }
+
+    // The folder names of every budget other than budget_id
+    pub fn other_dir_names(conn: &Connection, budget_id: i64) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare("SELECT COALESCE(dir_name, name) FROM budget WHERE id != ?")?;
+        let names = stmt
+            .query_map([budget_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(names)
+    }
+
+    // Looks up the budget whose folder is dir_name. Setup gives each budget its own folder, but
+    // budgets set up before it did may share one, in which case the statement can't be routed.
+    pub fn with_dir_name(conn: &Connection, dir_name: &str) -> Result<BudgetRow> {
+        let mut stmt =
+            conn.prepare("SELECT id, uuid, name FROM budget WHERE COALESCE(dir_name, name) = ?")?;
+        let result = stmt.query_map([&dir_name], |row| {
+            Ok(BudgetRow {
+                id: row.get(0)?,
+                uuid: row.get::<usize, DbUuid>(1)?.into(),
+                name: row.get(2)?,
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        match rows.len() {
+            0 => Err(anyhow!("no budget uses the folder '{}'", dir_name)),
+            1 => Ok(rows.remove(0)),
+            _ => Err(anyhow!(
+                "more than one budget uses the folder '{}', run setup again to give each its own",
+                dir_name
+            )),
+        }
+    }
+
+    // Removes the budget along with its accounts, their imported transaction and statement rows,
+    // and its cached categories and the rules using them
+    pub fn delete(conn: &Connection, budget_id: i64) -> Result<()> {
+        conn.execute(
+            "DELETE FROM category_rule WHERE category_id IN \
+            (SELECT id FROM category WHERE budget_id = ?)",
+            [budget_id],
+        )?;
+        conn.execute("DELETE FROM category WHERE budget_id = ?", [budget_id])?;
+        let mut stmt = conn.prepare("SELECT id FROM account WHERE budget_id = ?")?;
+        let account_ids = stmt
+            .query_map([budget_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        for account_id in account_ids {
+            account::delete(conn, account_id)?;
+        }
+        conn.execute("DELETE FROM budget WHERE id = ?", [budget_id])?;
+        Ok(())
+    }
+
     pub fn with_name(conn: &Connection, budget_name: &str) -> Result<BudgetRow> {
         let mut stmt = conn.prepare("SELECT id, uuid, name FROM budget WHERE name = ?")?;
         let result: BudgetRow = stmt.query_row([&budget_name], |row| {
@@ -152,6 +718,7 @@ pub mod account {
     use uuid::Uuid;
 
     use super::*;
+    use anyhow::anyhow;
 
     #[derive(Clone)]
     pub struct AccountRow {
@@ -169,9 +736,11 @@ pub mod account {
         for acc in accounts.iter() {
             let uuid = DbUuid(acc.id);
             conn.execute(
-                "INSERT INTO account(budget_id, uuid, name) VALUES (?1, ?2, ?3) \
-                ON CONFLICT(uuid) DO UPDATE SET name=?3;",
-                params![budget_id, uuid, acc.name],
+                "INSERT INTO account(budget_id, uuid, name, transfer_payee_id) \
+                VALUES (?1, ?2, ?3, ?4) \
+                ON CONFLICT(uuid) DO UPDATE SET name=?3, \
+                transfer_payee_id=COALESCE(?4, transfer_payee_id);",
+                params![budget_id, uuid, acc.name, acc.transfer_payee_id.map(DbUuid)],
             )?;
         }
         Ok(())
@@ -196,9 +765,87 @@ pub mod account {
         Ok(result)
     }
 
-    pub fn get_all(conn: &Connection) -> Result<Vec<AccountRow>> {
-        let mut stmt = conn.prepare("SELECT id, budget_id, uuid, name FROM account;")?;
-        let result = stmt.query_map([], |row| {
+    // Looks up the account YNAB knows by uuid
+    pub fn with_uuid(conn: &Connection, uuid: Uuid) -> Result<AccountRow> {
+        let mut stmt =
+            conn.prepare("SELECT id, budget_id, uuid, name FROM account WHERE uuid = ?")?;
+        let result = stmt
+            .query_row([DbUuid(uuid)], |row| {
+                Ok(AccountRow {
+                    id: row.get(0)?,
+                    budget_id: row.get(1)?,
+                    uuid: row.get::<usize, DbUuid>(2)?.into(),
+                    name: row.get(3)?,
+                })
+            })
+            .optional()?;
+        result.ok_or_else(|| anyhow!("no account with uuid {}", uuid))
+    }
+
+    // Looks up the account linked to the given OFX ACCTID in any budget. Fails if there's more than
+    // one, since the statement could belong to either.
+    pub fn with_external_id(conn: &Connection, external_id: &str) -> Result<AccountRow> {
+        let mut stmt =
+            conn.prepare("SELECT id, budget_id, uuid, name FROM account WHERE external_id = ?")?;
+        let mut rows = stmt
+            .query_map([external_id], |row| {
+                Ok(AccountRow {
+                    id: row.get(0)?,
+                    budget_id: row.get(1)?,
+                    uuid: row.get::<usize, DbUuid>(2)?.into(),
+                    name: row.get(3)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<AccountRow>>>()?;
+        match rows.len() {
+            0 => Err(anyhow!("no account is linked to ACCTID {}", external_id)),
+            1 => Ok(rows.remove(0)),
+            n => Err(anyhow!(
+                "{} accounts are linked to ACCTID {}",
+                n,
+                external_id
+            )),
+        }
+    }
+
+    // Records the (sanitized) folder name created for the account
+    pub fn set_dir_name(conn: &Connection, uuid: Uuid, dir_name: &str) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET dir_name = ?1 WHERE uuid = ?2",
+            params![dir_name, DbUuid(uuid)],
+        )?;
+        Ok(())
+    }
+
+    // Accounts set up before folder names were recorded use their name as is
+    pub fn dir_name(conn: &Connection, account_id: i64) -> Result<String> {
+        let mut stmt = conn.prepare("SELECT COALESCE(dir_name, name) FROM account WHERE id = ?")?;
+        Ok(stmt.query_row([account_id], |row| row.get(0))?)
+    }
+
+    // The folder names of the budget's accounts other than the one with the given uuid
+    pub fn other_dir_names(conn: &Connection, budget_id: i64, uuid: Uuid) -> Result<Vec<String>> {
+        let mut stmt = conn.prepare(
+            "SELECT COALESCE(dir_name, name) FROM account WHERE budget_id = ? AND uuid != ?",
+        )?;
+        let names = stmt
+            .query_map(params![budget_id, DbUuid(uuid)], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(names)
+    }
+
+    // Looks up the account within a budget whose folder is dir_name. As with budgets, an error if
+    // accounts set up before folder names were made unique share it.
+    pub fn with_budget_and_dir_name(
+        conn: &Connection,
+        budget_id: i64,
+        dir_name: &str,
+    ) -> Result<AccountRow> {
+        let mut stmt = conn.prepare(
+            "SELECT id, budget_id, uuid, name FROM account \
+            WHERE COALESCE(dir_name, name) = ? AND budget_id = ?",
+        )?;
+        let result = stmt.query_map(params![&dir_name, &budget_id], |row| {
             Ok(AccountRow {
                 id: row.get(0)?,
                 budget_id: row.get(1)?,
@@ -210,63 +857,1454 @@ pub mod account {
         for r in result {
             rows.push(r?);
         }
-        Ok(rows)
+        match rows.len() {
+            0 => Err(anyhow!("no account uses the folder '{}'", dir_name)),
+            1 => Ok(rows.remove(0)),
+            _ => Err(anyhow!(
+                "more than one account uses the folder '{}', run setup again to give each its own",
+                dir_name
+            )),
+        }
     }
-}
-
-pub mod transaction {
-    use chrono::NaiveDate;
-
-    use super::*;
 
-    pub struct TransactionRow {
-        // TODO make id optional for other row types?
-        pub id: Option<i64>,
-        pub amount_milli: i64,
-        pub date_posted: NaiveDate,
-        pub account_id: i64,
+    // Looks up the account within a budget that was linked to the given OFX ACCTID
+    pub fn with_budget_and_external_id(
+        conn: &Connection,
+        budget_id: i64,
+        external_id: &str,
+    ) -> Result<AccountRow> {
+        find_by_external_id(conn, budget_id, external_id)?
+            .ok_or_else(|| anyhow!("no account is linked to ACCTID {}", external_id))
     }
 
-    impl TransactionRow {
-        pub fn new(amount_milli: i64, date_str: String, account_id: i64) -> Result<Self> {
-            Ok(Self {
-                id: None,
-                amount_milli,
-                account_id,
+    // As with_budget_and_external_id, but None rather than an error if no account is linked
+    pub fn find_by_external_id(
+        conn: &Connection,
+        budget_id: i64,
+        external_id: &str,
+    ) -> Result<Option<AccountRow>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, budget_id, uuid, name FROM account WHERE external_id = ? AND budget_id = ?",
+        )?;
+        let result = stmt
+            .query_row(params![&external_id, &budget_id], |row| {
+                Ok(AccountRow {
+                    id: row.get(0)?,
+                    budget_id: row.get(1)?,
+                    uuid: row.get::<usize, DbUuid>(2)?.into(),
+                    name: row.get(3)?,
+                })
+            })
+            .optional()?;
+        Ok(result)
+    }
+
+    // The ACCTID the account was linked to, if any
+    pub fn get_external_id(conn: &Connection, account_id: i64) -> Result<Option<String>> {
+        let mut stmt = conn.prepare("SELECT external_id FROM account WHERE id = ?")?;
+        Ok(stmt.query_row([account_id], |row| row.get(0))?)
+    }
+
+    // Links an account to the ACCTID its bank uses in OFX statements
+    pub fn set_external_id(conn: &Connection, account_id: i64, external_id: &str) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET external_id = ?1 WHERE id = ?2",
+            params![external_id, account_id],
+        )?;
+        Ok(())
+    }
+
+    // Statement transactions within this many milliunits of an already imported transaction on the
+    // same date are treated as duplicates. 0 (the default) only matches exact amounts.
+    pub fn set_amount_tolerance(conn: &Connection, account_id: i64, milliunits: i64) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET amount_tolerance = ?1 WHERE id = ?2",
+            params![milliunits.max(0), account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_amount_tolerance(conn: &Connection, account_id: i64) -> Result<i64> {
+        let mut stmt = conn.prepare("SELECT amount_tolerance FROM account WHERE id = ?")?;
+        Ok(stmt.query_row([account_id], |row| row.get(0))?)
+    }
+
+    // Like the amount tolerance, for banks which post a pending transaction on one date and settle
+    // it on a later one. 0 (the default) only matches the same date.
+    pub fn set_date_tolerance(conn: &Connection, account_id: i64, days: i64) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET date_tolerance = ?1 WHERE id = ?2",
+            params![days.max(0), account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_date_tolerance(conn: &Connection, account_id: i64) -> Result<i64> {
+        let mut stmt = conn.prepare("SELECT date_tolerance FROM account WHERE id = ?")?;
+        Ok(stmt.query_row([account_id], |row| row.get(0))?)
+    }
+
+    // For banks which export every TRNAMT as positive, the sign of statement amounts is taken from
+    // TRNTYPE instead. Off by default.
+    pub fn set_sign_from_kind(conn: &Connection, account_id: i64, enabled: bool) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET sign_from_kind = ?1 WHERE id = ?2",
+            params![enabled, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_sign_from_kind(conn: &Connection, account_id: i64) -> Result<bool> {
+        let mut stmt = conn.prepare("SELECT sign_from_kind FROM account WHERE id = ?")?;
+        Ok(stmt.query_row([account_id], |row| row.get(0))?)
+    }
+
+    // A chrono format for banks which don't write statement dates the OFX way, tried before the
+    // standard formats. None (the default) only uses those.
+    pub fn set_date_format(conn: &Connection, account_id: i64, format: Option<&str>) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET date_format = ?1 WHERE id = ?2",
+            params![format, account_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_date_format(conn: &Connection, account_id: i64) -> Result<Option<String>> {
+        let mut stmt = conn.prepare("SELECT date_format FROM account WHERE id = ?")?;
+        Ok(stmt.query_row([account_id], |row| row.get(0))?)
+    }
+
+    // The cleared status transactions imported into the account are given
+    pub fn set_cleared_status(
+        conn: &Connection,
+        uuid: Uuid,
+        status: TransactionClearedStatus,
+    ) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET cleared_status = ?1 WHERE uuid = ?2",
+            params![status.to_string(), DbUuid(uuid)],
+        )?;
+        Ok(())
+    }
+
+    // Cleared unless changed with set_cleared_status, None if the account isn't set up
+    pub fn get_cleared_status(
+        conn: &Connection,
+        uuid: Uuid,
+    ) -> Result<Option<TransactionClearedStatus>> {
+        let mut stmt = conn.prepare("SELECT cleared_status FROM account WHERE uuid = ?")?;
+        let status: Option<String> = stmt
+            .query_row([DbUuid(uuid)], |row| row.get(0))
+            .optional()?;
+        Ok(status.map(|s| match s.as_str() {
+            "uncleared" => TransactionClearedStatus::Uncleared,
+            "reconciled" => TransactionClearedStatus::Reconciled,
+            _ => TransactionClearedStatus::Cleared,
+        }))
+    }
+
+    // The payee YNAB uses for transfers into the account
+    pub fn set_transfer_payee_id(conn: &Connection, uuid: Uuid, payee_id: Uuid) -> Result<()> {
+        conn.execute(
+            "UPDATE account SET transfer_payee_id = ?1 WHERE uuid = ?2",
+            params![DbUuid(payee_id), DbUuid(uuid)],
+        )?;
+        Ok(())
+    }
+
+    pub fn transfer_payee_id(conn: &Connection, account_id: i64) -> Result<Option<Uuid>> {
+        let mut stmt = conn.prepare("SELECT transfer_payee_id FROM account WHERE id = ?")?;
+        let payee_id: Option<DbUuid> = stmt.query_row([account_id], |row| row.get(0))?;
+        Ok(payee_id.map(Uuid::from))
+    }
+
+    pub fn get_all(conn: &Connection) -> Result<Vec<AccountRow>> {
+        let mut stmt = conn.prepare("SELECT id, budget_id, uuid, name FROM account;")?;
+        let result = stmt.query_map([], |row| {
+            Ok(AccountRow {
+                id: row.get(0)?,
+                budget_id: row.get(1)?,
+                uuid: row.get::<usize, DbUuid>(2)?.into(),
+                name: row.get(3)?,
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        Ok(rows)
+    }
+
+    // Removes the account along with all of its imported transaction and statement rows
+    pub fn delete(conn: &Connection, account_id: i64) -> Result<()> {
+        conn.execute(
+            "DELETE FROM transaction_import WHERE account_id = ?",
+            [account_id],
+        )?;
+        conn.execute(
+            "DELETE FROM statement_import WHERE account_id = ?",
+            [account_id],
+        )?;
+        conn.execute("DELETE FROM sync_state WHERE account_id = ?", [account_id])?;
+        conn.execute("DELETE FROM account WHERE id = ?", [account_id])?;
+        Ok(())
+    }
+}
+
+pub mod transaction {
+    use chrono::{NaiveDate, NaiveTime};
+
+    use super::*;
+
+    pub struct TransactionRow {
+        // TODO make id optional for other row types?
+        pub id: Option<i64>,
+        pub amount_milli: i64,
+        pub date_posted: NaiveDate,
+        // None where the statement only had a date, and for transactions synced from YNAB
+        pub time_posted: Option<NaiveTime>,
+        pub account_id: i64,
+
+        // As they appeared in the statement (or in YNAB, for synced transactions), to help track
+        // down which transaction a row was created for
+        pub payee_name: Option<String>,
+        pub memo: Option<String>,
+        // The import_id YNAB has for the transaction, if it was imported by either this or the
+        // built-in importer
+        pub import_id: Option<String>,
+        // When this importer created the transaction in YNAB (RFC 3339). None for transactions
+        // synced from YNAB, and ones recorded before this was.
+        pub imported_at: Option<String>,
+    }
+
+    impl TransactionRow {
+        pub fn new(amount_milli: i64, date_str: String, account_id: i64) -> Result<Self> {
+            Ok(Self {
+                id: None,
+                amount_milli,
+                account_id,
                 date_posted: NaiveDate::parse_from_str(&date_str, "%Y-%m-%d")?,
+                time_posted: None,
+                payee_name: None,
+                memo: None,
+                import_id: None,
+                imported_at: None,
             })
         }
     }
 
+    // Whether a transaction with an amount within tolerance_milli of amount_milli, posted within
+    // tolerance_days of date_posted (both inclusive) has already been imported into the account.
+    // With both tolerances 0 only exact matches count. Without a date tolerance, a row posted at a
+    // different time of day is a different transaction, unless either side has no time.
     pub fn exists(
         conn: &Connection,
         account_id: i64,
         amount_milli: i64,
         date_posted: NaiveDate,
+        time_posted: Option<NaiveTime>,
+        tolerance_milli: i64,
+        tolerance_days: i64,
     ) -> Result<bool> {
-        let mut stmt = conn.prepare(
-            "SELECT id FROM transaction_import \
+        let result: Option<i32> = if tolerance_days == 0 {
+            let mut stmt = conn.prepare(
+                "SELECT id FROM transaction_import \
+                WHERE account_id = ? AND ABS(amount - ?) <= ? AND date_posted = ? \
+                AND (time_posted = '' OR ?5 IN ('', time_posted)) LIMIT 1",
+            )?;
+            stmt.query_row(
+                params![
+                    account_id,
+                    amount_milli,
+                    tolerance_milli,
+                    date_posted.to_string(),
+                    format_time(time_posted)
+                ],
+                |row| row.get(0),
+            )
+            .optional()?
+        } else {
+            let mut stmt = conn.prepare(
+                "SELECT id FROM transaction_import \
+                WHERE account_id = ? AND ABS(amount - ?) <= ? \
+                AND ABS(JULIANDAY(date_posted) - JULIANDAY(?)) <= ? LIMIT 1",
+            )?;
+            stmt.query_row(
+                params![
+                    account_id,
+                    amount_milli,
+                    tolerance_milli,
+                    date_posted.to_string(),
+                    tolerance_days
+                ],
+                |row| row.get(0),
+            )
+            .optional()?
+        };
+        Ok(result.is_some())
+    }
+
+    // How many rows the account has with exactly this amount and date
+    pub fn count_matching(
+        conn: &Connection,
+        account_id: i64,
+        amount_milli: i64,
+        date_posted: NaiveDate,
+    ) -> Result<usize> {
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM transaction_import \
             WHERE account_id = ? AND amount = ? AND date_posted = ?",
+            params![account_id, amount_milli, date_posted.to_string()],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    // A transaction already in YNAB which is the other side of a transfer
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct TransferCounterpart {
+        // Row id in transaction_import
+        pub id: i64,
+        pub account_id: i64,
+        // What to find the transaction in YNAB by
+        pub import_id: String,
+    }
+
+    // A transaction of the opposite amount within window_days of date_posted, imported into another
+    // account in the same budget and not yet linked to anything, i.e. the other side of a
+    // transfer. The closest date wins. Only transactions with an import id can be found in YNAB to
+    // link them.
+    pub fn find_transfer_counterpart(
+        conn: &Connection,
+        account_id: i64,
+        amount_milli: i64,
+        date_posted: NaiveDate,
+        window_days: i64,
+    ) -> Result<Option<TransferCounterpart>> {
+        let mut stmt = conn.prepare(
+            "SELECT t.id, t.account_id, t.import_id \
+            FROM transaction_import t JOIN account a ON a.id = t.account_id \
+            WHERE t.account_id != ?1 \
+            AND a.budget_id = (SELECT budget_id FROM account WHERE id = ?1) \
+            AND t.amount = -?2 \
+            AND t.import_id IS NOT NULL AND t.transfer_linked = 0 \
+            AND ABS(JULIANDAY(t.date_posted) - JULIANDAY(?3)) <= ?4 \
+            ORDER BY ABS(JULIANDAY(t.date_posted) - JULIANDAY(?3)) LIMIT 1",
         )?;
-        let result: Option<i32> = stmt
+        Ok(stmt
             .query_row(
-                params![account_id, amount_milli, date_posted.to_string()],
-                |row| row.get(0),
+                params![
+                    account_id,
+                    amount_milli,
+                    date_posted.to_string(),
+                    window_days
+                ],
+                |row| {
+                    Ok(TransferCounterpart {
+                        id: row.get(0)?,
+                        account_id: row.get(1)?,
+                        import_id: row.get(2)?,
+                    })
+                },
             )
-            .optional()?;
-        Ok(result.is_some())
+            .optional()?)
+    }
+
+    // Marks the row as one side of a transfer in YNAB, so it isn't linked again
+    pub fn set_transfer_linked(conn: &Connection, id: i64) -> Result<()> {
+        conn.execute(
+            "UPDATE transaction_import SET transfer_linked = 1 WHERE id = ?",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    // Stored as '' when there's no time, so it can be part of the unique key
+    fn format_time(time: Option<NaiveTime>) -> String {
+        time.map(|t| t.format("%H:%M:%S").to_string())
+            .unwrap_or_default()
     }
 
     pub fn create_if_not_exists(conn: &Connection, row: TransactionRow) -> Result<()> {
         conn.execute(
-            "INSERT INTO transaction_import(account_id, amount, date_posted) VALUES (?, ?, ?) \
-            ON CONFLICT(amount, date_posted, account_id) DO NOTHING;",
+            "INSERT INTO transaction_import(account_id, amount, date_posted, time_posted, \
+            payee_name, memo, import_id, imported_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?) \
+            ON CONFLICT(amount, date_posted, time_posted, account_id) DO NOTHING;",
             params![
                 row.account_id,
                 row.amount_milli,
-                row.date_posted.to_string()
+                row.date_posted.to_string(),
+                format_time(row.time_posted),
+                row.payee_name,
+                row.memo,
+                row.import_id,
+                row.imported_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    // All rows recorded for the account, oldest first
+    pub fn list_for_account(conn: &Connection, account_id: i64) -> Result<Vec<TransactionRow>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, amount, date_posted, account_id, payee_name, memo, import_id, imported_at, \
+            time_posted FROM transaction_import WHERE account_id = ? ORDER BY date_posted, id",
+        )?;
+        let result = stmt.query_map([account_id], |row| {
+            Ok(TransactionRow {
+                id: row.get(0)?,
+                amount_milli: row.get(1)?,
+                date_posted: NaiveDate::parse_from_str(&row.get::<usize, String>(2)?, "%Y-%m-%d")
+                    .map_err(|err| {
+                    rusqlite::Error::FromSqlConversionFailure(2, Type::Text, Box::new(err))
+                })?,
+                account_id: row.get(3)?,
+                payee_name: row.get(4)?,
+                memo: row.get(5)?,
+                import_id: row.get(6)?,
+                imported_at: row.get(7)?,
+                time_posted: match row.get::<usize, String>(8)?.as_str() {
+                    "" => None,
+                    time => Some(NaiveTime::parse_from_str(time, "%H:%M:%S").map_err(|err| {
+                        rusqlite::Error::FromSqlConversionFailure(8, Type::Text, Box::new(err))
+                    })?),
+                },
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        Ok(rows)
+    }
+
+    // What transaction_import holds for an account
+    pub struct AccountCounts {
+        pub count: i64,
+        // Oldest and newest date_posted (YYYY-MM-DD), None without any rows
+        pub first_date: Option<String>,
+        pub last_date: Option<String>,
+        // Latest imported_at, None if nothing was imported by this importer yet
+        pub last_imported_at: Option<String>,
+    }
+
+    pub fn count_and_range_for_account(
+        conn: &Connection,
+        account_id: i64,
+    ) -> Result<AccountCounts> {
+        let mut stmt = conn.prepare(
+            "SELECT COUNT(*), MIN(date_posted), MAX(date_posted), MAX(imported_at) \
+            FROM transaction_import WHERE account_id = ?",
+        )?;
+        Ok(stmt.query_row([account_id], |row| {
+            Ok(AccountCounts {
+                count: row.get(0)?,
+                first_date: row.get(1)?,
+                last_date: row.get(2)?,
+                last_imported_at: row.get(3)?,
+            })
+        })?)
+    }
+
+    // A row along with the names of the budget and account it belongs to
+    pub struct ExportRow {
+        pub budget: String,
+        pub account: String,
+        // YYYY-MM-DD, as stored
+        pub date_posted: String,
+        pub amount_milli: i64,
+        pub payee_name: Option<String>,
+        pub memo: Option<String>,
+    }
+
+    // Every row of every account, oldest first
+    pub fn list_for_export(conn: &Connection) -> Result<Vec<ExportRow>> {
+        let mut stmt = conn.prepare(
+            "SELECT b.name, a.name, t.date_posted, t.amount, t.payee_name, t.memo \
+            FROM transaction_import t \
+            JOIN account a ON a.id = t.account_id \
+            JOIN budget b ON b.id = a.budget_id \
+            ORDER BY t.date_posted, b.name, a.name, t.id",
+        )?;
+        let result = stmt.query_map([], |row| {
+            Ok(ExportRow {
+                budget: row.get(0)?,
+                account: row.get(1)?,
+                date_posted: row.get(2)?,
+                amount_milli: row.get(3)?,
+                payee_name: row.get(4)?,
+                memo: row.get(5)?,
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        Ok(rows)
+    }
+}
+
+// Rules renaming the payees of imported transactions, see rules::PayeeRules
+pub mod payee_rules {
+    use super::*;
+
+    pub struct PayeeRuleRow {
+        pub id: i64,
+        pub pattern: String,
+        pub replacement: String,
+        pub is_regex: bool,
+    }
+
+    pub fn create(
+        conn: &Connection,
+        pattern: &str,
+        replacement: &str,
+        is_regex: bool,
+    ) -> Result<i64> {
+        conn.execute(
+            "INSERT INTO payee_rule(pattern, replacement, is_regex) VALUES (?1, ?2, ?3)",
+            params![pattern, replacement, is_regex],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    // In the order they were added, which is the order they're tried in
+    pub fn get_all(conn: &Connection) -> Result<Vec<PayeeRuleRow>> {
+        let mut stmt =
+            conn.prepare("SELECT id, pattern, replacement, is_regex FROM payee_rule ORDER BY id")?;
+        let result = stmt.query_map([], |row| {
+            Ok(PayeeRuleRow {
+                id: row.get(0)?,
+                pattern: row.get(1)?,
+                replacement: row.get(2)?,
+                is_regex: row.get(3)?,
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        Ok(rows)
+    }
+
+    pub fn update(conn: &Connection, rule: &PayeeRuleRow) -> Result<()> {
+        conn.execute(
+            "UPDATE payee_rule SET pattern = ?1, replacement = ?2, is_regex = ?3 WHERE id = ?4",
+            params![rule.pattern, rule.replacement, rule.is_regex, rule.id],
+        )?;
+        Ok(())
+    }
+
+    // Returns whether a rule with the id existed
+    pub fn delete(conn: &Connection, id: i64) -> Result<bool> {
+        Ok(conn.execute("DELETE FROM payee_rule WHERE id = ?", [id])? > 0)
+    }
+}
+
+// The categories of each budget as of the last setup, so category rules can be checked against
+// them without going to the API
+pub mod category {
+    use super::*;
+    use ynab_api::models::Category;
+
+    pub struct CategoryRow {
+        pub id: i64,
+        pub budget_id: i64,
+        pub uuid: Uuid,
+        pub name: String,
+        pub group_name: Option<String>,
+    }
+
+    // Makes the cached categories of the budget match the given ones. Categories missing from the
+    // list (or deleted in YNAB) are removed along with the rules using them, returns how many rules
+    // were removed.
+    pub fn replace_for_budget(
+        conn: &Connection,
+        budget_id: i64,
+        categories: &[Category],
+    ) -> Result<usize> {
+        let mut kept = Vec::new();
+        for c in categories.iter().filter(|c| !c.deleted) {
+            conn.execute(
+                "INSERT INTO category(budget_id, uuid, name, group_name) VALUES (?1, ?2, ?3, ?4) \
+                ON CONFLICT(uuid) DO UPDATE SET name = ?3, group_name = ?4",
+                params![budget_id, DbUuid(c.id), c.name, c.category_group_name],
+            )?;
+            kept.push(c.id);
+        }
+
+        let mut removed_rules = 0;
+        for row in get_for_budget(conn, budget_id)? {
+            if kept.contains(&row.uuid) {
+                continue;
+            }
+            removed_rules +=
+                conn.execute("DELETE FROM category_rule WHERE category_id = ?", [row.id])?;
+            conn.execute("DELETE FROM category WHERE id = ?", [row.id])?;
+        }
+        Ok(removed_rules)
+    }
+
+    pub fn get_for_budget(conn: &Connection, budget_id: i64) -> Result<Vec<CategoryRow>> {
+        let mut stmt = conn.prepare(
+            "SELECT id, budget_id, uuid, name, group_name FROM category WHERE budget_id = ? \
+            ORDER BY group_name, name",
+        )?;
+        let result = stmt.query_map([budget_id], |row| {
+            Ok(CategoryRow {
+                id: row.get(0)?,
+                budget_id: row.get(1)?,
+                uuid: row.get::<usize, DbUuid>(2)?.into(),
+                name: row.get(3)?,
+                group_name: row.get(4)?,
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        Ok(rows)
+    }
+}
+
+// Rules assigning a category to imported transactions by payee, see rules::CategoryRules
+pub mod category_rules {
+    use super::*;
+
+    pub struct CategoryRuleRow {
+        pub id: i64,
+        pub payee_pattern: String,
+        pub category_id: i64,
+        pub category_uuid: Uuid,
+        pub category_name: String,
+        pub budget_id: i64,
+    }
+
+    pub fn create(conn: &Connection, payee_pattern: &str, category_id: i64) -> Result<i64> {
+        conn.execute(
+            "INSERT INTO category_rule(payee_pattern, category_id) VALUES (?1, ?2)",
+            params![payee_pattern, category_id],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    // Rules for the categories of every budget, in the order they were added
+    pub fn get_all(conn: &Connection) -> Result<Vec<CategoryRuleRow>> {
+        query(conn, "", [])
+    }
+
+    // In the order they were added, which is the order they're tried in
+    pub fn get_for_budget(conn: &Connection, budget_id: i64) -> Result<Vec<CategoryRuleRow>> {
+        query(conn, "WHERE c.budget_id = ?", [budget_id])
+    }
+
+    fn query<P: rusqlite::Params>(
+        conn: &Connection,
+        filter: &str,
+        params: P,
+    ) -> Result<Vec<CategoryRuleRow>> {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT r.id, r.payee_pattern, r.category_id, c.uuid, c.name, c.budget_id \
+            FROM category_rule r JOIN category c ON c.id = r.category_id {} ORDER BY r.id",
+            filter
+        ))?;
+        let result = stmt.query_map(params, |row| {
+            Ok(CategoryRuleRow {
+                id: row.get(0)?,
+                payee_pattern: row.get(1)?,
+                category_id: row.get(2)?,
+                category_uuid: row.get::<usize, DbUuid>(3)?.into(),
+                category_name: row.get(4)?,
+                budget_id: row.get(5)?,
+            })
+        })?;
+        let mut rows = Vec::new();
+        for r in result {
+            rows.push(r?);
+        }
+        Ok(rows)
+    }
+
+    // Returns whether a rule with the id existed
+    pub fn delete(conn: &Connection, id: i64) -> Result<bool> {
+        Ok(conn.execute("DELETE FROM category_rule WHERE id = ?", [id])? > 0)
+    }
+}
+
+// Statements which have been imported, by the SHA-256 of their contents, so a re-saved or
+// re-downloaded copy can be skipped without parsing it
+pub mod processed_file {
+    use super::*;
+
+    pub struct ProcessedFileRow {
+        pub hash: String,
+        pub path: String,
+        // RFC 3339, local time
+        pub imported_at: String,
+        pub transaction_count: i64,
+    }
+
+    // Replaces any earlier record of the same contents
+    pub fn record(conn: &Connection, row: &ProcessedFileRow) -> Result<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO processed_file(hash, path, imported_at, transaction_count) \
+            VALUES (?1, ?2, ?3, ?4)",
+            params![row.hash, row.path, row.imported_at, row.transaction_count],
+        )?;
+        Ok(())
+    }
+
+    pub fn get(conn: &Connection, hash: &str) -> Result<Option<ProcessedFileRow>> {
+        let mut stmt = conn.prepare(
+            "SELECT hash, path, imported_at, transaction_count FROM processed_file WHERE hash = ?",
+        )?;
+        Ok(stmt
+            .query_row([hash], |row| {
+                Ok(ProcessedFileRow {
+                    hash: row.get(0)?,
+                    path: row.get(1)?,
+                    imported_at: row.get(2)?,
+                    transaction_count: row.get(3)?,
+                })
+            })
+            .optional()?)
+    }
+}
+
+// The period (DTSTART to DTEND) of each statement imported into an account, to notice when a new
+// one covers some of the same dates
+pub mod statement_import {
+    use chrono::NaiveDate;
+
+    use super::*;
+
+    pub struct StatementImportRow {
+        pub account_id: i64,
+        pub path: String,
+        pub start: NaiveDate,
+        pub end: NaiveDate,
+        // RFC 3339, local time
+        pub imported_at: String,
+    }
+
+    pub fn record(conn: &Connection, row: &StatementImportRow) -> Result<()> {
+        conn.execute(
+            "INSERT INTO statement_import(account_id, path, start_date, end_date, imported_at) \
+            VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                row.account_id,
+                row.path,
+                row.start.to_string(),
+                row.end.to_string(),
+                row.imported_at
             ],
         )?;
         Ok(())
     }
+
+    // The most recently imported statement of the account sharing at least one day with start to
+    // end (inclusive)
+    pub fn find_overlapping(
+        conn: &Connection,
+        account_id: i64,
+        start: NaiveDate,
+        end: NaiveDate,
+    ) -> Result<Option<StatementImportRow>> {
+        let mut stmt = conn.prepare(
+            "SELECT account_id, path, start_date, end_date, imported_at FROM statement_import \
+            WHERE account_id = ?1 AND start_date <= ?3 AND end_date >= ?2 \
+            ORDER BY id DESC LIMIT 1",
+        )?;
+        let row = stmt
+            .query_row(
+                params![account_id, start.to_string(), end.to_string()],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+        match row {
+            None => Ok(None),
+            Some((account_id, path, start, end, imported_at)) => Ok(Some(StatementImportRow {
+                account_id,
+                path,
+                start: NaiveDate::parse_from_str(&start, "%Y-%m-%d")?,
+                end: NaiveDate::parse_from_str(&end, "%Y-%m-%d")?,
+                imported_at,
+            })),
+        }
+    }
+}
+
+// The server_knowledge YNAB returned with the last transaction sync of each account, so the next
+// sync only has to request what changed since
+pub mod sync_state {
+    use super::*;
+
+    pub fn get(conn: &Connection, account_id: i64) -> Result<Option<i64>> {
+        let mut stmt =
+            conn.prepare("SELECT server_knowledge FROM sync_state WHERE account_id = ?")?;
+        Ok(stmt.query_row([account_id], |row| row.get(0)).optional()?)
+    }
+
+    pub fn set(conn: &Connection, account_id: i64, server_knowledge: i64) -> Result<()> {
+        conn.execute(
+            "INSERT INTO sync_state(account_id, server_knowledge) VALUES (?1, ?2) \
+            ON CONFLICT(account_id) DO UPDATE SET server_knowledge = ?2",
+            params![account_id, server_knowledge],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+
+    // With a budget and an account
+    fn test_conn() -> Connection {
+        let conn = super::test_conn();
+        conn.execute(
+            "INSERT INTO budget(uuid, name) VALUES ('00000000-0000-0000-0000-000000000001', 'b')",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO account(budget_id, uuid, name) \
+            VALUES (1, '00000000-0000-0000-0000-000000000002', 'a')",
+            [],
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_exists_amount_tolerance() {
+        let conn = test_conn();
+        let date = NaiveDate::from_ymd_opt(2024, 11, 8).unwrap();
+        transaction::create_if_not_exists(
+            &conn,
+            transaction::TransactionRow::new(-7350, date.to_string(), 1).unwrap(),
+        )
+        .unwrap();
+
+        assert!(transaction::exists(&conn, 1, -7350, date, None, 0, 0).unwrap());
+        assert!(!transaction::exists(&conn, 1, -7360, date, None, 0, 0).unwrap());
+
+        assert_eq!(account::get_amount_tolerance(&conn, 1).unwrap(), 0);
+        account::set_amount_tolerance(&conn, 1, 10).unwrap();
+        let tolerance = account::get_amount_tolerance(&conn, 1).unwrap();
+        assert_eq!(tolerance, 10);
+
+        assert!(transaction::exists(&conn, 1, -7360, date, None, tolerance, 0).unwrap());
+        assert!(transaction::exists(&conn, 1, -7340, date, None, tolerance, 0).unwrap());
+        assert!(!transaction::exists(&conn, 1, -7361, date, None, tolerance, 0).unwrap());
+        assert!(!transaction::exists(&conn, 1, -7339, date, None, tolerance, 0).unwrap());
+
+        let next_day = date.succ_opt().unwrap();
+        assert!(!transaction::exists(&conn, 1, -7350, next_day, None, tolerance, 0).unwrap());
+    }
+
+    #[test]
+    fn test_exists_time_posted() {
+        let conn = test_conn();
+        let date = NaiveDate::from_ymd_opt(2024, 11, 8).unwrap();
+        let morning = NaiveTime::from_hms_opt(9, 15, 0);
+        let evening = NaiveTime::from_hms_opt(18, 40, 0);
+        let row = |time_posted| transaction::TransactionRow {
+            time_posted,
+            ..transaction::TransactionRow::new(-7350, date.to_string(), 1).unwrap()
+        };
+        transaction::create_if_not_exists(&conn, row(morning)).unwrap();
+
+        assert!(transaction::exists(&conn, 1, -7350, date, morning, 0, 0).unwrap());
+        assert!(transaction::exists(&conn, 1, -7350, date, None, 0, 0).unwrap());
+        assert!(!transaction::exists(&conn, 1, -7350, date, evening, 0, 0).unwrap());
+        // The time only tells apart transactions on the same date
+        assert!(transaction::exists(&conn, 1, -7350, date, evening, 0, 1).unwrap());
+
+        // Same amount and date at another time is recorded rather than dropped as a conflict
+        transaction::create_if_not_exists(&conn, row(evening)).unwrap();
+        let rows = transaction::list_for_account(&conn, 1).unwrap();
+        let times: Vec<_> = rows.iter().map(|r| r.time_posted).collect();
+        assert_eq!(times, vec![morning, evening]);
+
+        // A row without a time matches any time
+        let other_date = date.succ_opt().unwrap();
+        transaction::create_if_not_exists(
+            &conn,
+            transaction::TransactionRow::new(-7350, other_date.to_string(), 1).unwrap(),
+        )
+        .unwrap();
+        assert!(transaction::exists(&conn, 1, -7350, other_date, evening, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_count_matching() {
+        let conn = test_conn();
+        let date = NaiveDate::from_ymd_opt(2024, 11, 8).unwrap();
+        assert_eq!(
+            transaction::count_matching(&conn, 1, -7350, date).unwrap(),
+            0
+        );
+        transaction::create_if_not_exists(
+            &conn,
+            transaction::TransactionRow::new(-7350, date.to_string(), 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            transaction::count_matching(&conn, 1, -7350, date).unwrap(),
+            1
+        );
+        // Exact matches only, regardless of any tolerance
+        assert_eq!(
+            transaction::count_matching(&conn, 1, -7360, date).unwrap(),
+            0
+        );
+        let next_day = date.succ_opt().unwrap();
+        assert_eq!(
+            transaction::count_matching(&conn, 1, -7350, next_day).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_find_overlapping_statement() {
+        let conn = test_conn();
+        let date = |day| NaiveDate::from_ymd_opt(2024, 11, day).unwrap();
+        statement_import::record(
+            &conn,
+            &statement_import::StatementImportRow {
+                account_id: 1,
+                path: "november.qfx".into(),
+                start: date(1),
+                end: date(15),
+                imported_at: "2024-11-15T12:00:00-05:00".into(),
+            },
+        )
+        .unwrap();
+
+        let overlapping = statement_import::find_overlapping(&conn, 1, date(15), date(30))
+            .unwrap()
+            .unwrap();
+        assert_eq!(overlapping.path, "november.qfx");
+        assert_eq!(overlapping.start, date(1));
+        assert_eq!(overlapping.end, date(15));
+        assert!(
+            statement_import::find_overlapping(&conn, 1, date(16), date(30))
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_exists_date_tolerance() {
+        let conn = test_conn();
+        let date = NaiveDate::from_ymd_opt(2024, 11, 8).unwrap();
+        transaction::create_if_not_exists(
+            &conn,
+            transaction::TransactionRow::new(-40000, date.to_string(), 1).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(account::get_date_tolerance(&conn, 1).unwrap(), 0);
+        account::set_date_tolerance(&conn, 1, 2).unwrap();
+        let days = account::get_date_tolerance(&conn, 1).unwrap();
+        assert_eq!(days, 2);
+
+        let settled = NaiveDate::from_ymd_opt(2024, 11, 10).unwrap();
+        assert!(!transaction::exists(&conn, 1, -40000, settled, None, 0, 0).unwrap());
+        assert!(transaction::exists(&conn, 1, -40000, settled, None, 0, days).unwrap());
+        let earlier = NaiveDate::from_ymd_opt(2024, 11, 6).unwrap();
+        assert!(transaction::exists(&conn, 1, -40000, earlier, None, 0, days).unwrap());
+        let too_late = NaiveDate::from_ymd_opt(2024, 11, 11).unwrap();
+        assert!(!transaction::exists(&conn, 1, -40000, too_late, None, 0, days).unwrap());
+
+        // Both windows apply together, e.g. a tip added when a card payment settles
+        assert!(!transaction::exists(&conn, 1, -45000, settled, None, 0, days).unwrap());
+        assert!(transaction::exists(&conn, 1, -45000, settled, None, 5000, days).unwrap());
+    }
+
+    #[test]
+    fn test_window_geometry_round_trip() {
+        let conn = test_conn();
+        assert_eq!(config::get_window_geometry(&conn).unwrap(), None);
+
+        let geometry = config::WindowGeometry {
+            size: [800.0, 600.0],
+            position: Some([10.0, 20.5]),
+        };
+        config::set_window_geometry(&conn, geometry).unwrap();
+        assert_eq!(config::get_window_geometry(&conn).unwrap(), Some(geometry));
+    }
+
+    #[test]
+    fn test_import_extensions() {
+        let conn = test_conn();
+        assert_eq!(
+            config::get_import_extensions(&conn).unwrap(),
+            vec!["qfx", "ofx"]
+        );
+
+        config::set(&conn, config::IMPORT_EXTENSIONS, " QBO, .txt,,ofx ").unwrap();
+        assert_eq!(
+            config::get_import_extensions(&conn).unwrap(),
+            vec!["qbo", "txt", "ofx"]
+        );
+    }
+
+    #[test]
+    fn test_sync_state() {
+        let conn = test_conn();
+        assert_eq!(sync_state::get(&conn, 1).unwrap(), None);
+        sync_state::set(&conn, 1, 100).unwrap();
+        assert_eq!(sync_state::get(&conn, 1).unwrap(), Some(100));
+        sync_state::set(&conn, 1, 250).unwrap();
+        assert_eq!(sync_state::get(&conn, 1).unwrap(), Some(250));
+
+        account::delete(&conn, 1).unwrap();
+        assert_eq!(sync_state::get(&conn, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_dir_name_lookup() {
+        let conn = test_conn();
+        assert_eq!(budget::dir_name(&conn, 1).unwrap(), "b");
+        assert_eq!(account::dir_name(&conn, 1).unwrap(), "a");
+        assert_eq!(
+            account::with_budget_and_dir_name(&conn, 1, "a").unwrap().id,
+            1
+        );
+
+        budget::set_dir_name(&conn, 1, "b_c").unwrap();
+        let uuid = uuid::Uuid::from_u128(2);
+        account::set_dir_name(&conn, uuid, "Checking _ Savings").unwrap();
+        assert_eq!(budget::with_dir_name(&conn, "b_c").unwrap().id, 1);
+        assert!(budget::with_dir_name(&conn, "b").is_err());
+        let acc = account::with_budget_and_dir_name(&conn, 1, "Checking _ Savings").unwrap();
+        assert_eq!(acc.name, "a");
+        assert_eq!(account::dir_name(&conn, 1).unwrap(), "Checking _ Savings");
+    }
+
+    #[test]
+    fn test_dir_name_collision() {
+        let conn = test_conn();
+        conn.execute(
+            "INSERT INTO account (id, budget_id, uuid, name) VALUES (2, 1, ?, 'Checking')",
+            [DbUuid(uuid::Uuid::from_u128(3))],
+        )
+        .unwrap();
+        account::set_dir_name(&conn, uuid::Uuid::from_u128(2), "Checking").unwrap();
+        assert_eq!(
+            account::other_dir_names(&conn, 1, uuid::Uuid::from_u128(2)).unwrap(),
+            vec!["Checking".to_string()]
+        );
+
+        let err = account::with_budget_and_dir_name(&conn, 1, "Checking").unwrap_err();
+        assert!(err.to_string().contains("more than one account"));
+        assert!(account::with_budget_and_dir_name(&conn, 1, "Savings").is_err());
+        assert_eq!(
+            budget::other_dir_names(&conn, 1).unwrap(),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_missing_config_keys() {
+        let conn = test_conn();
+        assert_eq!(config::get_opt(&conn, config::ACCESS_TOKEN).unwrap(), None);
+        assert_eq!(
+            config::get_or_default(&conn, config::ACCESS_TOKEN, "none").unwrap(),
+            "none"
+        );
+        let err = config::get(&conn, config::ACCESS_TOKEN).unwrap_err();
+        assert!(err.to_string().contains(config::ACCESS_TOKEN));
+
+        config::set(&conn, config::ACCESS_TOKEN, "token").unwrap();
+        assert_eq!(
+            config::get_opt(&conn, config::ACCESS_TOKEN).unwrap(),
+            Some("token".into())
+        );
+        assert_eq!(
+            config::get_or_default(&conn, config::ACCESS_TOKEN, "none").unwrap(),
+            "token"
+        );
+        assert_eq!(config::get(&conn, config::ACCESS_TOKEN).unwrap(), "token");
+
+        config::set(&conn, config::ACCESS_TOKEN, "token\n").unwrap();
+        assert_eq!(config::get_access_token(&conn).unwrap(), "token");
+
+        config::unset(&conn, config::ACCESS_TOKEN).unwrap();
+        assert_eq!(config::get_opt(&conn, config::ACCESS_TOKEN).unwrap(), None);
+    }
+
+    #[test]
+    fn test_sync_concurrency() {
+        let conn = test_conn();
+        assert_eq!(config::get_sync_concurrency(&conn).unwrap(), 4);
+        config::set_sync_concurrency(&conn, 10).unwrap();
+        assert_eq!(config::get_sync_concurrency(&conn).unwrap(), 10);
+        // At least one request has to be allowed
+        config::set_sync_concurrency(&conn, 0).unwrap();
+        assert_eq!(config::get_sync_concurrency(&conn).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_sync_since() {
+        let conn = test_conn();
+        assert_eq!(config::get_sync_since(&conn).unwrap(), None);
+        let date = NaiveDate::from_ymd_opt(2024, 8, 1).unwrap();
+        config::set_sync_since(&conn, Some(date)).unwrap();
+        assert_eq!(config::get_sync_since(&conn).unwrap(), Some(date));
+        config::set_sync_since(&conn, None).unwrap();
+        assert_eq!(config::get_sync_since(&conn).unwrap(), None);
+    }
+
+    #[test]
+    fn test_budget_cache() {
+        let conn = test_conn();
+        assert!(config::get_budget_cache(&conn).unwrap().is_none());
+        let user_id = Uuid::from_u128(7);
+        let budget_id = Uuid::from_u128(1);
+        let fetched_at = DateTime::parse_from_rfc3339("2024-08-01T12:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let cache = config::BudgetCache {
+            user_id,
+            budgets: vec![BudgetSummary::new(budget_id, "Budget".to_string())],
+            fetched_at,
+        };
+        config::set_budget_cache(&conn, &cache).unwrap();
+        let cached = config::get_budget_cache(&conn).unwrap().unwrap();
+        assert_eq!(cached.user_id, user_id);
+        assert_eq!(cached.budgets, cache.budgets);
+        assert_eq!(cached.fetched_at, fetched_at);
+    }
+
+    #[test]
+    fn test_concurrent_connections() {
+        let path =
+            std::env::temp_dir().join(format!("ynab-importer-wal-{}.sqlite", std::process::id()));
+        let mut first = open(&path).unwrap();
+        migrate(&mut first);
+        let second = open(&path).unwrap();
+
+        let journal_mode: String = second
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(journal_mode, "wal");
+
+        // A read transaction on one connection doesn't block writes from the other
+        let tx = first.transaction().unwrap();
+        assert_eq!(config::get_opt(&tx, config::THEME).unwrap(), None);
+        config::set_theme(&second, config::GuiTheme::Light).unwrap();
+        tx.commit().unwrap();
+
+        config::set_max_retries(&first, 3).unwrap();
+        assert_eq!(config::get_max_retries(&second).unwrap(), 3);
+        assert_eq!(config::get_theme(&first).unwrap(), config::GuiTheme::Light);
+
+        drop(first);
+        drop(second);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn test_resolve_db_path() {
+        let exe = Path::new("/opt/ynab-importer/ynab-importer");
+        assert_eq!(
+            resolve_db_path(None, exe),
+            PathBuf::from("/opt/ynab-importer/db.sqlite")
+        );
+        assert_eq!(
+            resolve_db_path(Some("".into()), exe),
+            PathBuf::from("/opt/ynab-importer/db.sqlite")
+        );
+        assert_eq!(
+            resolve_db_path(Some("/data/budget.sqlite".into()), exe),
+            PathBuf::from("/data/budget.sqlite")
+        );
+    }
+
+    #[test]
+    fn test_delete_budget() {
+        let conn = test_conn();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 11, 8).unwrap();
+        transaction::create_if_not_exists(
+            &conn,
+            transaction::TransactionRow::new(-7350, date.to_string(), 1).unwrap(),
+        )
+        .unwrap();
+        sync_state::set(&conn, 1, 100).unwrap();
+
+        // Foreign keys are enforced, so the budget can't be removed out from under its accounts
+        assert!(conn.execute("DELETE FROM budget WHERE id = 1", []).is_err());
+
+        budget::delete(&conn, 1).unwrap();
+        assert!(budget::get_all(&conn).unwrap().is_empty());
+        assert!(account::get_all(&conn).unwrap().is_empty());
+        assert!(!transaction::exists(&conn, 1, -7350, date, None, 0, 0).unwrap());
+        assert_eq!(sync_state::get(&conn, 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_list_for_account() {
+        let conn = test_conn();
+        let date = NaiveDate::from_ymd_opt(2024, 11, 8).unwrap();
+        let row = |amount_milli, date: NaiveDate, payee_name: &str| transaction::TransactionRow {
+            payee_name: Some(payee_name.into()),
+            memo: Some("memo".into()),
+            import_id: Some(format!("YNAB:{}:{}:1", date, amount_milli)),
+            ..transaction::TransactionRow::new(amount_milli, date.to_string(), 1).unwrap()
+        };
+        transaction::create_if_not_exists(&conn, row(-500, date.succ_opt().unwrap(), "b")).unwrap();
+        transaction::create_if_not_exists(&conn, row(-7350, date, "a")).unwrap();
+
+        let rows = transaction::list_for_account(&conn, 1).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].id, Some(2));
+        assert_eq!(rows[0].amount_milli, -7350);
+        assert_eq!(rows[0].date_posted, date);
+        assert_eq!(rows[0].payee_name.as_deref(), Some("a"));
+        assert_eq!(rows[0].memo.as_deref(), Some("memo"));
+        assert_eq!(
+            rows[0].import_id.as_deref(),
+            Some("YNAB:2024-11-08:-7350:1")
+        );
+        assert_eq!(rows[1].payee_name.as_deref(), Some("b"));
+        assert!(transaction::list_for_account(&conn, 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_account_with_uuid() {
+        let conn = test_conn();
+        let account = account::with_uuid(&conn, Uuid::from_u128(2)).unwrap();
+        assert_eq!((account.id, account.budget_id), (1, 1));
+        assert_eq!(account.name, "a");
+
+        let err = account::with_uuid(&conn, Uuid::from_u128(3)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("00000000-0000-0000-0000-000000000003"));
+    }
+
+    #[test]
+    fn test_account_with_external_id() {
+        let conn = test_conn();
+        let err = account::with_external_id(&conn, "1111").unwrap_err();
+        assert!(err.to_string().contains("1111"));
+
+        account::set_external_id(&conn, 1, "1111").unwrap();
+        assert_eq!(account::with_external_id(&conn, "1111").unwrap().name, "a");
+
+        conn.execute(
+            "INSERT INTO account(budget_id, uuid, name, external_id) \
+            VALUES (1, '00000000-0000-0000-0000-000000000003', 'b', '1111')",
+            [],
+        )
+        .unwrap();
+        assert!(account::with_external_id(&conn, "1111").is_err());
+    }
+
+    #[test]
+    fn test_count_and_range_for_account() {
+        let conn = test_conn();
+        let counts = transaction::count_and_range_for_account(&conn, 1).unwrap();
+        assert_eq!(counts.count, 0);
+        assert_eq!(counts.first_date, None);
+
+        let rows = [
+            (-500, "2024-11-16", Some("2024-11-20T09:00:00-05:00")),
+            (-7350, "2024-11-08", Some("2024-11-10T09:00:00-05:00")),
+            (152980, "2024-11-01", None),
+        ];
+        for (amount, date, imported_at) in rows {
+            let row = transaction::TransactionRow {
+                imported_at: imported_at.map(String::from),
+                ..transaction::TransactionRow::new(amount, date.into(), 1).unwrap()
+            };
+            transaction::create_if_not_exists(&conn, row).unwrap();
+        }
+
+        let counts = transaction::count_and_range_for_account(&conn, 1).unwrap();
+        assert_eq!(counts.count, 3);
+        assert_eq!(counts.first_date.as_deref(), Some("2024-11-01"));
+        assert_eq!(counts.last_date.as_deref(), Some("2024-11-16"));
+        assert_eq!(
+            counts.last_imported_at.as_deref(),
+            Some("2024-11-20T09:00:00-05:00")
+        );
+    }
+
+    #[test]
+    fn test_payee_rules() {
+        let conn = test_conn();
+        let first = payee_rules::create(&conn, "icecream", "Ice Cream Shop", false).unwrap();
+        let second = payee_rules::create(&conn, "^SQ", "Square", true).unwrap();
+
+        let mut rules = payee_rules::get_all(&conn).unwrap();
+        assert_eq!(
+            rules.iter().map(|r| r.id).collect::<Vec<_>>(),
+            vec![first, second]
+        );
+        assert!(!rules[0].is_regex);
+        assert!(rules[1].is_regex);
+
+        rules[0].replacement = "Ice Cream".into();
+        payee_rules::update(&conn, &rules[0]).unwrap();
+        assert!(payee_rules::delete(&conn, second).unwrap());
+        assert!(!payee_rules::delete(&conn, second).unwrap());
+
+        let rules = payee_rules::get_all(&conn).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].replacement, "Ice Cream");
+    }
+
+    #[test]
+    fn test_category_rules() {
+        let conn = test_conn();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        let ynab_category = |id, name: &str| ynab_api::models::Category {
+            id: Uuid::from_u128(id),
+            name: name.into(),
+            category_group_name: Some("Bills".into()),
+            ..Default::default()
+        };
+        let categories = [ynab_category(10, "Groceries"), ynab_category(11, "Phone")];
+        assert_eq!(
+            category::replace_for_budget(&conn, 1, &categories).unwrap(),
+            0
+        );
+
+        let cached = category::get_for_budget(&conn, 1).unwrap();
+        assert_eq!(cached.len(), 2);
+        assert_eq!(cached[0].name, "Groceries");
+        assert_eq!(cached[0].group_name.as_deref(), Some("Bills"));
+        let groceries = category_rules::create(&conn, "grocer", cached[0].id).unwrap();
+        category_rules::create(&conn, "telco", cached[1].id).unwrap();
+
+        // Phone was deleted in YNAB, its rule goes with it
+        let categories = [
+            ynab_category(10, "Food"),
+            ynab_api::models::Category {
+                deleted: true,
+                ..ynab_category(11, "Phone")
+            },
+        ];
+        assert_eq!(
+            category::replace_for_budget(&conn, 1, &categories).unwrap(),
+            1
+        );
+        let rules = category_rules::get_for_budget(&conn, 1).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].id, groceries);
+        assert_eq!(rules[0].category_uuid, Uuid::from_u128(10));
+        assert_eq!(rules[0].category_name, "Food");
+
+        budget::delete(&conn, 1).unwrap();
+        assert!(category::get_for_budget(&conn, 1).unwrap().is_empty());
+        assert!(category_rules::get_all(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_transfer_counterpart() {
+        let conn = test_conn();
+        conn.execute(
+            "INSERT INTO budget(uuid, name) VALUES ('00000000-0000-0000-0000-000000000003', 'c')",
+            [],
+        )
+        .unwrap();
+        for (budget_id, uuid, name) in [(1, 4, "card"), (2, 5, "other")] {
+            conn.execute(
+                "INSERT INTO account(budget_id, uuid, name) VALUES (?1, ?2, ?3)",
+                params![budget_id, DbUuid(Uuid::from_u128(uuid)), name],
+            )
+            .unwrap();
+        }
+        let date = NaiveDate::from_ymd_opt(2024, 11, 8).unwrap();
+        let add = |account_id, amount, days, import_id: Option<&str>| {
+            let date = date + chrono::Days::new(days);
+            let row = transaction::TransactionRow {
+                import_id: import_id.map(String::from),
+                ..transaction::TransactionRow::new(amount, date.to_string(), account_id).unwrap()
+            };
+            transaction::create_if_not_exists(&conn, row).unwrap();
+        };
+        add(1, -50000, 0, Some("YNAB:2024-11-08:-50000:1"));
+        add(3, 50000, 0, Some("YNAB:2024-11-08:50000:1"));
+        add(2, 20000, 5, Some("YNAB:2024-11-13:20000:1"));
+        add(2, 30000, 0, None);
+
+        let find = |account_id, amount| {
+            transaction::find_transfer_counterpart(&conn, account_id, amount, date, 3)
+                .unwrap()
+                .map(|counterpart| (counterpart.account_id, counterpart.import_id))
+        };
+        assert_eq!(find(2, 50000), Some((1, "YNAB:2024-11-08:-50000:1".into())));
+        // Only accounts in the same budget, and not the account itself
+        assert_eq!(find(2, -50000), None);
+        assert_eq!(find(1, 50000), None);
+        // Outside the window
+        assert_eq!(find(1, -20000), None);
+        // Without an import id it can't be found in YNAB
+        assert_eq!(find(1, -30000), None);
+
+        // Each transaction is only one side of one transfer
+        transaction::set_transfer_linked(&conn, 1).unwrap();
+        assert_eq!(find(2, 50000), None);
+
+        assert_eq!(account::transfer_payee_id(&conn, 1).unwrap(), None);
+        account::set_transfer_payee_id(&conn, Uuid::from_u128(2), Uuid::from_u128(9)).unwrap();
+        assert_eq!(
+            account::transfer_payee_id(&conn, 1).unwrap(),
+            Some(Uuid::from_u128(9))
+        );
+    }
+
+    #[test]
+    fn test_cleared_status() {
+        let conn = test_conn();
+        let uuid = Uuid::from_u128(2);
+        assert_eq!(
+            account::get_cleared_status(&conn, uuid).unwrap(),
+            Some(TransactionClearedStatus::Cleared)
+        );
+        account::set_cleared_status(&conn, uuid, TransactionClearedStatus::Uncleared).unwrap();
+        assert_eq!(
+            account::get_cleared_status(&conn, uuid).unwrap(),
+            Some(TransactionClearedStatus::Uncleared)
+        );
+        assert_eq!(
+            account::get_cleared_status(&conn, Uuid::from_u128(3)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_processed_file() {
+        let conn = test_conn();
+        let row = |path: &str, transaction_count| processed_file::ProcessedFileRow {
+            hash: "abc".into(),
+            path: path.into(),
+            imported_at: "2024-11-08T13:05:09-05:00".into(),
+            transaction_count,
+        };
+        assert!(processed_file::get(&conn, "abc").unwrap().is_none());
+
+        processed_file::record(&conn, &row("a.qfx", 3)).unwrap();
+        processed_file::record(&conn, &row("b.qfx", 0)).unwrap();
+        let processed = processed_file::get(&conn, "abc").unwrap().unwrap();
+        assert_eq!(processed.path, "b.qfx");
+        assert_eq!(processed.transaction_count, 0);
+    }
 }
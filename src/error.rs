@@ -8,6 +8,45 @@ pub enum ImportError {
     #[error("failed to parse QFX file")]
     FileParsingError(#[from] sgmlish::Error),
 
+    #[error("failed to parse <{tag}> near byte {offset} of QFX file: ...{context}...")]
+    TransactionParsingError {
+        tag: String,
+        offset: usize,
+        context: String,
+        source: sgmlish::Error,
+    },
+
+    #[error("no <OFX> element found")]
+    MissingOfxError,
+
     #[error("no paths provided with event")]
     NoPathError,
+
+    #[error("no ACCTID found in '{0}'")]
+    MissingAccountIdError(String),
+
+    #[error(
+        "ACCTID {acct_id} doesn't match the {linked} {account} is linked to, use link-account to \
+        link it to the right account"
+    )]
+    AccountIdMismatchError {
+        acct_id: String,
+        account: String,
+        linked: String,
+    },
+
+    #[error("'{0}' doesn't contain a personal access token (expected 64 hex characters)")]
+    InvalidTokenError(String),
+
+    #[error(
+        "YNAB rejected the access token, it may have expired or been revoked. Re-run setup \
+        with a new token, or sign in again with login"
+    )]
+    TokenRejectedError,
+
+    #[error("failed to decompress '{0}'")]
+    DecompressionError(String, #[source] std::io::Error),
+
+    #[error("failed to sync the transactions of {}, the other accounts were synced", .0.join(", "))]
+    SyncError(Vec<String>),
 }
@@ -1,24 +1,63 @@
 use super::error::ImportError;
 use super::{
-    db::{account, budget, config},
-    ofx::load_transactions,
+    db::{account, budget, config, config::FolderDepth},
+    ofx::{is_gzip, load_account_id, load_statement, LedgerBalance, ParseOptions},
 };
-use crate::db::transaction::{self, TransactionRow};
+use crate::auth;
+use crate::client::YnabClient;
+use crate::db::account::AccountRow;
+use crate::db::budget::BudgetRow;
+use crate::db::processed_file::{self, ProcessedFileRow};
+use crate::db::statement_import::{self, StatementImportRow};
+use crate::db::transaction::{self, TransactionRow, TransferCounterpart};
+use crate::export::format_amount;
+use crate::health::Metrics;
+use crate::notification;
 use crate::ofx::OfxTransaction;
+use crate::retry::{backoff_delay, is_retryable, is_retryable_create};
+use crate::rules::{CategoryRules, PayeeRules};
+use crate::setup::env_token;
+use crate::webhook;
 use anyhow::{anyhow, Context, Result};
-use chrono::NaiveDate;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
+use futures::future::BoxFuture;
 use notify_debouncer_full::notify::{event::CreateKind, EventKind::Create};
 use notify_debouncer_full::DebouncedEvent;
 use rusqlite::Connection;
-use std::collections::HashMap;
-use std::fmt::Write;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Write};
+use std::fs;
+use std::future::Future;
+use std::io;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, field::Empty, info, instrument, warn, Span};
+use url::Url;
+use uuid::Uuid;
 use ynab_api::apis::configuration::Configuration;
-use ynab_api::apis::transactions_api::create_transaction;
-use ynab_api::models::{NewTransaction, PostTransactionsWrapper, TransactionClearedStatus};
+use ynab_api::apis::Error as ApiError;
+use ynab_api::models::{
+    NewTransaction, PatchTransactionsWrapper, PostTransactionsWrapper,
+    SaveTransactionWithIdOrImportId, SaveTransactionsResponseData, TransactionClearedStatus,
+};
+
+// How long apart a new file is checked to tell whether it's still being written
+const SETTLE_INTERVAL: Duration = Duration::from_millis(500);
+
+// How many days apart the two sides of a transfer can post, banks don't always settle them on the
+// same day
+const TRANSFER_WINDOW_DAYS: i64 = 3;
 
-fn milli_dollar_amount(amount: f64) -> i64 {
-    (amount * 1000.0).round() as i64
+// Appends the check number (if any) to the memo so paper checks can be reconciled in YNAB
+fn memo_with_check_number(memo: Option<String>, check_number: Option<String>) -> Option<String> {
+    match (memo, check_number) {
+        (Some(memo), Some(n)) => Some(format!("{} Check #{}", memo, n)),
+        (None, Some(n)) => Some(format!("Check #{}", n)),
+        (memo, None) => memo,
+    }
 }
 
 impl From<OfxTransaction> for NewTransaction {
@@ -26,11 +65,11 @@ impl From<OfxTransaction> for NewTransaction {
         NewTransaction {
             account_id: None,
             date: Some(value.date_posted.to_string()),
-            amount: Some(milli_dollar_amount(value.amount)),
+            amount: Some(value.amount_milli),
             payee_id: None,
             payee_name: Some(value.name.clone()),
             category_id: None,
-            memo: Some(value.memo.clone()),
+            memo: Some(memo_with_check_number(value.memo, value.check_number)),
             cleared: Some(TransactionClearedStatus::Cleared),
             approved: None,
             flag_color: None,
@@ -40,59 +79,133 @@ impl From<OfxTransaction> for NewTransaction {
     }
 }
 
+// Hex SHA-256 of the file's contents, as they are on disk (i.e. before decompressing)
+pub fn file_hash(path: &Path) -> Result<String> {
+    Ok(format!("{:x}", Sha256::digest(fs::read(path)?)))
+}
+
+// Whether the file's size and modification time are the same across two checks SETTLE_INTERVAL
+// apart. Events can fire while a file is still being downloaded or copied (Create in particular
+// usually comes before the contents are written), and parsing it then fails on the truncated
+// contents.
+pub async fn file_settled(path: &Path) -> Result<bool> {
+    let before = fs::metadata(path)?;
+    tokio::time::sleep(SETTLE_INTERVAL).await;
+    let after = fs::metadata(path)?;
+    Ok(before.len() == after.len() && before.modified()? == after.modified()?)
+}
+
+// Returns the budget name and, when the folder layout includes account folders, the account name.
+// The budget is the first folder below basedir_path and the account the second. Statements can be
+// organized into any number of subfolders beneath that, e.g. basedir/budget/account/2024/file.qfx.
 fn get_budget_and_account_from_path(
     basedir_path: &PathBuf,
     path: &Path,
-) -> Result<(String, String)> {
-    let mut display_path = String::new();
-    write!(&mut display_path, "{}", path.display())?;
-
-    let mut display_basedir = String::new();
-    write!(&mut display_basedir, "{}", path.display())?;
-
+    depth: FolderDepth,
+) -> Result<(String, Option<String>)> {
     let mut new_path = PathBuf::new();
-    let mut level_count = 0;
-    let mut account_name = None;
-    let mut budget_name = None;
+    let mut in_basedir = false;
+    let mut folders = Vec::new();
 
-    for comp in path.components() {
+    // The last component is the statement itself, only the folders above it are named
+    let parent = path.parent().unwrap_or(path);
+    for comp in parent.components() {
         match comp {
             std::path::Component::Prefix(_) => (),
             std::path::Component::RootDir => {
                 new_path.push(comp.as_os_str());
-                new_path = new_path.canonicalize().unwrap();
+                new_path = new_path.canonicalize()?;
             }
             _ => {
                 new_path.push(comp.as_os_str());
             }
         }
-        if level_count == 0 {
-            if &new_path == basedir_path {
-                level_count += 1;
-            }
-        } else if level_count == 1 {
-            budget_name = comp.as_os_str().to_str();
-            level_count += 1;
-        } else {
-            account_name = comp.as_os_str().to_str();
-            break;
+        if in_basedir {
+            folders.push(comp.as_os_str().to_str());
+        } else if &new_path == basedir_path {
+            in_basedir = true;
         }
     }
-    if budget_name.is_none() || account_name.is_none() {
-        return Err(ImportError::PathParsingError(display_path).into());
+    match (depth, folders.as_slice()) {
+        (FolderDepth::BudgetOnly, [Some(budget), ..]) => Ok((budget.to_string(), None)),
+        (FolderDepth::BudgetAccount, [Some(budget), Some(account), ..]) => {
+            Ok((budget.to_string(), Some(account.to_string())))
+        }
+        _ => Err(ImportError::PathParsingError(path.display().to_string()).into()),
+    }
+}
+
+// The lowercased extension of the statement format, looking through a .gz suffix
+fn statement_extension(path: &Path) -> Option<String> {
+    let ext = if is_gzip(path) {
+        Path::new(path.file_stem()?).extension()?
+    } else {
+        path.extension()?
+    };
+    Some(ext.to_string_lossy().to_lowercase())
+}
+
+// Whether the file has one of the given extensions (see config::get_import_extensions)
+pub fn is_statement_file(path: &Path, extensions: &[String]) -> bool {
+    statement_extension(path).is_some_and(|ext| extensions.contains(&ext))
+}
+
+// Where an imported statement is archived: the same location relative to the archive dir as it had
+// relative to the transaction dir, with the import time prefixed to the file name
+fn archive_path(
+    base_dir: &Path,
+    archive_dir: &Path,
+    path: &Path,
+    imported_at: DateTime<Local>,
+) -> Result<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| ImportError::PathParsingError(path.display().to_string()))?;
+    let mut dest = archive_dir.to_path_buf();
+    if let Some(parent) = path
+        .strip_prefix(base_dir)
+        .ok()
+        .and_then(|rel| rel.parent())
+    {
+        dest.push(parent);
+    }
+    dest.push(format!(
+        "{}_{}",
+        imported_at.format("%Y%m%d%H%M%S"),
+        file_name.to_string_lossy()
+    ));
+    Ok(dest)
+}
+
+// Renames the file, falling back to copying and deleting it when the destination is on a different
+// file system
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        res => res,
     }
-    Ok((budget_name.unwrap().into(), account_name.unwrap().into()))
 }
 
+// YNAB rejects longer import ids
+const MAX_IMPORT_ID_LEN: usize = 36;
+
 #[derive(Hash, Clone, PartialEq, Eq, Copy, Debug)]
 struct TransactionKey {
     date: NaiveDate,
     amount_millis: i64,
     occurrence: usize,
+    // Not part of the import id, which has to match YNAB's, but recorded so transactions of the
+    // same amount on the same day can be told apart on later imports
+    time: Option<NaiveTime>,
 }
 
 impl TransactionKey {
-    // Recreates the YNAB import id as to avoid duplicates if also using the built-in importer
+    // Recreates the YNAB import id as to avoid duplicates if also using the built-in importer. An
+    // id too long for YNAB (only possible with amounts in the hundreds of trillions) is replaced by
+    // a digest of it, so it still identifies the same transaction on every import.
     fn get_id(&self) -> String {
         let mut s = String::new();
         write!(
@@ -101,170 +214,1666 @@ impl TransactionKey {
             self.date, self.amount_millis, self.occurrence
         )
         .unwrap();
-        s
+        if s.len() <= MAX_IMPORT_ID_LEN {
+            return s;
+        }
+        let digest = format!("{:x}", Sha256::digest(&s));
+        format!("YNAB:{}", &digest[..MAX_IMPORT_ID_LEN - "YNAB:".len()])
+    }
+}
+
+// A transaction sent to YNAB, along with the statement's original name and memo, which are
+// recorded once it's saved
+#[derive(Debug, Clone)]
+struct PendingTransaction {
+    key: TransactionKey,
+    transaction: NewTransaction,
+    name: Option<String>,
+    memo: Option<String>,
+}
+
+// A statement transaction which is the other side of a transfer already imported into another
+// account. Turning that one into a transfer has YNAB add this side, so this one isn't posted.
+#[derive(Debug, Clone)]
+pub struct PendingTransfer {
+    counterpart: TransferCounterpart,
+    pending: PendingTransaction,
+}
+
+// A statement transaction which wasn't sent to YNAB because it was already imported
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedTransaction {
+    pub date: NaiveDate,
+    pub amount_milli: i64,
+    // NAME from the statement, before payee rules
+    pub payee: Option<String>,
+}
+
+// The transactions of a statement which haven't been imported yet, ready to send to YNAB
+pub struct PreparedImport {
+    pub budget: BudgetRow,
+    pub account: AccountRow,
+    pub transactions: Vec<NewTransaction>,
+
+    // Transactions whose other side is already in YNAB, which are linked to it instead of created
+    pub transfers: Vec<PendingTransfer>,
+
+    // Transactions skipped because they were already imported, in statement order
+    pub skipped_existing: Vec<SkippedTransaction>,
+
+    // The most recent statement imported into the account which covers some of the same dates
+    pub overlapping: Option<StatementImportRow>,
+
+    transaction_map: HashMap<String, PendingTransaction>,
+    ledger_balance: Option<LedgerBalance>,
+    // The statement's DTSTART and DTEND, recorded once it's imported
+    period: Option<(NaiveDate, NaiveDate)>,
+}
+
+// What create_transactions_with_retry did with a statement's transactions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportSummary {
+    // Names of the budget and account the statement was imported into
+    pub budget: String,
+    pub account: String,
+
+    // New transactions created in YNAB
+    pub created: usize,
+
+    // Sum of the created transactions' amounts, in milliunits
+    pub total_milli: i64,
+
+    // Transactions whose import id YNAB already had, and which were re-posted with a new one
+    pub duplicates: usize,
+
+    // Transactions skipped because they were already imported, in statement order
+    pub skipped_existing: Vec<SkippedTransaction>,
+
+    // Path of an earlier statement whose period overlaps this one's
+    pub overlapping: Option<String>,
+}
+
+// What handle did with an event
+#[derive(Debug, PartialEq, Eq)]
+pub enum Handled {
+    // Imported, or there was nothing to do
+    Done,
+
+    // The file was still being written. It should be passed to handle_path again a little later.
+    Unsettled(PathBuf),
+}
+
+// Gives each transaction YNAB reported as a duplicate the next occurrence no other transaction in
+// the statement has, returning them to be posted again
+fn requeue_duplicates(
+    transaction_map: &mut HashMap<String, PendingTransaction>,
+    ids: &[String],
+) -> Result<Vec<NewTransaction>> {
+    let mut requeued = Vec::new();
+    for import_id in ids {
+        let pending = transaction_map
+            .get(import_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("YNAB reported unknown duplicate import id {}", import_id))?;
+        let mut key = pending.key;
+        let mut new_id = import_id.clone();
+        while transaction_map.contains_key(&new_id) {
+            key.occurrence += 1;
+            new_id = key.get_id();
+        }
+
+        let new_transaction = NewTransaction {
+            import_id: Some(Some(new_id.clone())),
+            ..pending.transaction
+        };
+        transaction_map.insert(
+            new_id,
+            PendingTransaction {
+                key,
+                transaction: new_transaction.clone(),
+                ..pending
+            },
+        );
+        requeued.push(new_transaction);
+    }
+    Ok(requeued)
+}
+
+// Posts transactions until YNAB has saved all of them. Any it reports as duplicate import ids are
+// requeued with a new id and posted again, up to max_retries times. on_saved is called with the
+// import id of each transaction YNAB saved, so that none of them are sent again on the next import.
+// Returns how many were saved, and how many were duplicates. Duplicates still left once out of
+// retries are an error. They aren't recorded, since YNAB's transaction with that id may not be the
+// same one, so they're tried again on the next import.
+async fn post_transactions<P, Fut, S>(
+    transaction_map: &mut HashMap<String, PendingTransaction>,
+    mut transactions: Vec<NewTransaction>,
+    max_retries: usize,
+    mut post: P,
+    mut on_saved: S,
+) -> Result<(usize, usize)>
+where
+    P: FnMut(Vec<NewTransaction>) -> Fut,
+    Fut: Future<Output = Result<SaveTransactionsResponseData>>,
+    S: FnMut(&str, &PendingTransaction) -> Result<()>,
+{
+    let mut imported = 0;
+    let mut duplicates = 0;
+
+    let mut retry = 0;
+    loop {
+        if transactions.is_empty() {
+            return Ok((imported, duplicates));
+        }
+        if retry > 0 {
+            tokio::time::sleep(backoff_delay(retry)).await;
+        }
+        let data = post(std::mem::take(&mut transactions)).await?;
+
+        if let Some(saved) = data.transactions {
+            info!(count = saved.len(), "Imported transactions");
+            imported += saved.len();
+            for saved_transaction in saved.iter() {
+                let import_id = saved_transaction
+                    .import_id
+                    .clone()
+                    .flatten()
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "Did not find import_id in saved transaction response. Found {:?}",
+                            saved_transaction.import_id
+                        )
+                    })?;
+                let pending = transaction_map.get(&import_id).ok_or_else(|| {
+                    anyhow!(
+                        "Transaction map does not contain {}:\n{:#?}",
+                        import_id,
+                        transaction_map
+                    )
+                })?;
+                on_saved(&import_id, pending)?;
+            }
+        }
+
+        let ids = match data.duplicate_import_ids {
+            Some(ids) if !ids.is_empty() => ids,
+            _ => return Ok((imported, duplicates)),
+        };
+        if retry == max_retries {
+            return Err(anyhow!(
+                "YNAB still reported duplicate import ids after {} retries, {:#?}",
+                max_retries,
+                ids
+            ));
+        }
+        warn!(
+            count = ids.len(),
+            "Duplicate import ids, retrying with new ids"
+        );
+        duplicates += ids.len();
+        transactions = requeue_duplicates(transaction_map, &ids)?;
+        retry += 1;
     }
 }
 
 pub struct EventHandler {
     pub db_conn: Connection,
-    pub api_config: Configuration,
+    client: Box<dyn YnabClient>,
+    pub extensions: Vec<String>,
+    // How to parse statements as configured, with the date format filled in per account
+    pub parse_options: ParseOptions,
     max_retries: usize,
+    api_retries: usize,
+    notifications: bool,
+    reprocess_files: bool,
+    webhook_url: Option<Url>,
+    pub metrics: Arc<Metrics>,
+
+    // Budgets whose transfer payees were fetched, so that they're looked up at most once for
+    // accounts YNAB doesn't list one for
+    payees_fetched: Mutex<HashSet<i64>>,
 }
 
 impl EventHandler {
+    // Uses the token from YNAB_ACCESS_TOKEN if set, otherwise the one in the database
     pub fn new(db_conn: Connection) -> Result<Self> {
-        let access_token = config::get(&db_conn, config::ACCESS_TOKEN)?;
+        let access_token = match env_token()? {
+            Some(token) => token,
+            None => config::get_access_token(&db_conn)?,
+        };
         let mut api_config = Configuration::new();
         api_config.bearer_access_token = Some(access_token);
+        Self::with_client(db_conn, Box::new(api_config))
+    }
+
+    // Makes every API call through client, e.g. a fake one in tests
+    pub fn with_client(db_conn: Connection, client: Box<dyn YnabClient>) -> Result<Self> {
+        let extensions = config::get_import_extensions(&db_conn)?;
+        let max_retries = config::get_max_retries(&db_conn)?;
+        let api_retries = config::get_api_retries(&db_conn)?;
+        let notifications = config::get_notifications_enabled(&db_conn)?;
+        let reprocess_files = config::get_reprocess_files(&db_conn)?;
+        let webhook_url = config::get_webhook_url(&db_conn)?;
+        let parse_options = ParseOptions {
+            entities: config::get_ofx_entities(&db_conn)?,
+            ..Default::default()
+        };
         Ok({
             EventHandler {
                 db_conn,
-                api_config,
-                max_retries: 10,
+                client,
+                extensions,
+                parse_options,
+                max_retries,
+                api_retries,
+                notifications,
+                reprocess_files,
+                webhook_url,
+                metrics: Arc::default(),
+                payees_fetched: Mutex::default(),
             }
         })
     }
 
-    pub async fn handle(&self, event: &DebouncedEvent) -> Result<()> {
+    // Makes an API call with a current access token. The service outlives OAuth tokens, so they are
+    // refreshed when about to expire or when YNAB rejects them.
+    pub(crate) async fn call_api<T, E, F>(&self, call: F) -> Result<T>
+    where
+        E: Debug + Send + Sync + 'static,
+        F: for<'a> FnMut(&'a dyn YnabClient) -> BoxFuture<'a, Result<T, ApiError<E>>>,
+    {
+        let (conn, client) = (&self.db_conn, self.client.as_ref());
+        auth::with_token_refresh(conn, client, self.api_retries, is_retryable, call).await
+    }
+
+    // As call_api, for requests which aren't safe to repeat once YNAB may have acted on them
+    async fn call_api_create<T, E, F>(&self, call: F) -> Result<T>
+    where
+        E: Debug + Send + Sync + 'static,
+        F: for<'a> FnMut(&'a dyn YnabClient) -> BoxFuture<'a, Result<T, ApiError<E>>>,
+    {
+        let (conn, client) = (&self.db_conn, self.client.as_ref());
+        auth::with_token_refresh(conn, client, self.api_retries, is_retryable_create, call).await
+    }
+
+    #[instrument(skip_all, fields(kind = ?event.kind))]
+    pub async fn handle(&self, event: &DebouncedEvent) -> Result<Handled> {
         match event.kind {
             Create(CreateKind::File) => {
                 if event.paths.is_empty() {
                     return Err(ImportError::NoPathError.into());
                 }
-                let path = &event.paths[0];
-                if let Some(ext) = path.extension() {
-                    let ext = ext.to_ascii_lowercase();
-                    if (ext != "qfx") && (ext != "ofx") {
-                        println!("Ignoring non qfx file {:?}", path.display());
-                        return Ok(());
+                self.handle_path(&event.paths[0]).await
+            }
+            _ => {
+                debug!(?event, "Ignored event");
+                Ok(Handled::Done)
+            }
+        }
+    }
+
+    // Imports a newly created file, once it's done being written. Also used to retry files handle
+    // returned as Unsettled.
+    pub async fn handle_path(&self, path: &PathBuf) -> Result<Handled> {
+        if path.starts_with(config::get_archive_dir(&self.db_conn)?)
+            || path.starts_with(config::get_log_dir(&self.db_conn)?)
+        {
+            return Ok(Handled::Done);
+        }
+        if path.extension().is_some() && !is_statement_file(path, &self.extensions) {
+            debug!(path = %path.display(), "Ignoring non statement file");
+            return Ok(Handled::Done);
+        }
+        if !file_settled(path).await? {
+            info!(path = %path.display(), "Waiting for file to settle");
+            return Ok(Handled::Unsettled(path.clone()));
+        }
+        self.import_file(path).await?;
+        Ok(Handled::Done)
+    }
+
+    // The earlier import of a statement with the same contents, if there was one and reprocessing
+    // isn't turned on
+    pub fn already_processed(&self, hash: &str) -> Result<Option<ProcessedFileRow>> {
+        match self.reprocess_files {
+            true => Ok(None),
+            false => processed_file::get(&self.db_conn, hash),
+        }
+    }
+
+    // Records the statement's contents as imported, so copies of it are skipped
+    pub fn record_processed(
+        &self,
+        hash: String,
+        path: &Path,
+        summary: &ImportSummary,
+    ) -> Result<()> {
+        processed_file::record(
+            &self.db_conn,
+            &ProcessedFileRow {
+                hash,
+                path: path.display().to_string(),
+                imported_at: Local::now().to_rfc3339(),
+                transaction_count: summary.created as i64,
+            },
+        )
+    }
+
+    // Imports the statement and, only if that succeeds, moves it into the archive dir. The outcome
+    // is logged either way so the service log has a line for every file it picked up, and shown as
+    // a desktop notification unless they're turned off. Successful imports are also posted to the
+    // webhook if one is configured. A statement with the same contents as one imported before isn't
+    // parsed at all, it goes straight to the archive.
+    pub async fn import_file(&self, path: &PathBuf) -> Result<()> {
+        let hash = file_hash(path)?;
+        if let Some(processed) = self.already_processed(&hash)? {
+            info!(
+                path = %path.display(),
+                first_path = %processed.path,
+                imported_at = %processed.imported_at,
+                "Statement already processed"
+            );
+        } else {
+            match self.create_transactions_with_retry(path).await {
+                Ok(summary) => {
+                    match &summary.overlapping {
+                        None => info!(
+                            path = %path.display(),
+                            created = summary.created,
+                            duplicates = summary.duplicates,
+                            skipped_existing = summary.skipped_existing.len(),
+                            "Imported statement"
+                        ),
+                        Some(earlier) => info!(
+                            path = %path.display(),
+                            earlier = %earlier,
+                            duplicates = summary.duplicates,
+                            "Statement overlaps an earlier import, {}/{} transactions are new",
+                            summary.created,
+                            summary.created + summary.skipped_existing.len()
+                        ),
                     }
+                    if !summary.skipped_existing.is_empty() {
+                        debug!(skipped = ?summary.skipped_existing, "Already imported");
+                    }
+                    if self.notifications {
+                        notification::import_succeeded(
+                            &summary.account,
+                            summary.created,
+                            summary.skipped_existing.len(),
+                            summary.overlapping.is_some(),
+                        );
+                    }
+                    if let Some(url) = &self.webhook_url {
+                        webhook::post_import(url, &summary).await;
+                    }
+                    self.record_processed(hash, path, &summary)?;
+                }
+                Err(err) => {
+                    error!(path = %path.display(), "Failed to import statement: {:?}", err);
+                    if self.notifications {
+                        notification::import_failed(path, &err);
+                    }
+                    return Err(err);
                 }
-                self.create_transactions_with_retry(path).await
             }
-            _ => {
-                println!("Ignored event {:?}", event);
-                Ok(())
+        }
+
+        let base_dir = config::get_transaction_dir(&self.db_conn)?;
+        let archive_dir = config::get_archive_dir(&self.db_conn)?;
+        let dest = archive_path(&base_dir, &archive_dir, path, Local::now())?;
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_file(path, &dest).with_context(|| format!("failed to archive {}", path.display()))?;
+        info!(path = %path.display(), dest = %dest.display(), "Archived statement");
+        Ok(())
+    }
+
+    // Checks the ACCTID of a statement found in account's folder, if it has one. A statement filed
+    // in the wrong folder is imported into the account its ACCTID is linked to instead. Accounts
+    // are only linked with link-account, so the folder decides for one that isn't, and a statement
+    // that matches neither the folder's account nor any other is refused.
+    fn check_account_id(&self, path: &PathBuf, account: AccountRow) -> Result<AccountRow> {
+        let Some(external_id) = load_account_id(path)? else {
+            return Ok(account);
+        };
+        let linked = account::get_external_id(&self.db_conn, account.id)?;
+        if linked.as_deref() == Some(external_id.as_str()) {
+            return Ok(account);
+        }
+        match account::find_by_external_id(&self.db_conn, account.budget_id, &external_id)? {
+            Some(linked_account) => {
+                warn!(
+                    folder = %account.name,
+                    account = %linked_account.name,
+                    acct_id = %external_id,
+                    "Statement is in another account's folder, importing it into the account \
+                    linked to its ACCTID"
+                );
+                Ok(linked_account)
             }
+            None => match linked {
+                None => Ok(account),
+                Some(linked) => Err(ImportError::AccountIdMismatchError {
+                    acct_id: external_id,
+                    account: account.name,
+                    linked,
+                }
+                .into()),
+            },
         }
     }
 
-    async fn create_transactions_with_retry(&self, path: &PathBuf) -> Result<()> {
+    // The budget and account the statement at path is imported into, from its folders and, for a
+    // statement without an account folder or filed in another account's, its ACCTID
+    pub fn resolve_account(&self, path: &PathBuf) -> Result<(BudgetRow, AccountRow)> {
         let base_dir = config::get_transaction_dir(&self.db_conn)?;
-        let (budget_name, account_name) = get_budget_and_account_from_path(&base_dir, path)?;
+        let depth = config::get_folder_depth(&self.db_conn)?;
+        let (budget_name, account_name) =
+            get_budget_and_account_from_path(&base_dir, path, depth)?;
 
-        let budget = budget::with_name(&self.db_conn, &budget_name)
+        let budget = budget::with_dir_name(&self.db_conn, &budget_name)
             .with_context(|| format!("failed to load budget row for {}", budget_name))?;
 
-        let account = account::with_budget_and_name(&self.db_conn, budget.id, &account_name)
-            .with_context(|| format!("failed to load account for {}", account_name))?;
+        let account = match account_name {
+            Some(account_name) => {
+                let folder_account =
+                    account::with_budget_and_dir_name(&self.db_conn, budget.id, &account_name)
+                        .with_context(|| format!("failed to load account for {}", account_name))?;
+                self.check_account_id(path, folder_account)?
+            }
+            None => {
+                let external_id = load_account_id(path)?.ok_or_else(|| {
+                    ImportError::MissingAccountIdError(path.display().to_string())
+                })?;
+                account::with_budget_and_external_id(&self.db_conn, budget.id, &external_id)
+                    .with_context(|| {
+                        format!(
+                            "no account in {} is linked to ACCTID {}, use link-account to set it",
+                            budget_name, external_id
+                        )
+                    })?
+            }
+        };
+        Ok((budget, account))
+    }
+
+    // Works out which account the statement belongs to and builds the transactions which haven't
+    // been imported yet. Only reads from the DB, so it's also used to preview an import.
+    pub fn prepare_import(&self, path: &PathBuf) -> Result<PreparedImport> {
+        let (budget, account) = self.resolve_account(path)?;
+
+        Span::current()
+            .record("budget", budget.name.as_str())
+            .record("account", account.name.as_str());
 
+        let tolerance = account::get_amount_tolerance(&self.db_conn, account.id)?;
+        let tolerance_days = account::get_date_tolerance(&self.db_conn, account.id)?;
+        let sign_from_kind = account::get_sign_from_kind(&self.db_conn, account.id)?;
+        let cleared = account::get_cleared_status(&self.db_conn, account.uuid)?.unwrap_or_default();
+        let payee_rules = PayeeRules::load(&self.db_conn)?;
+        let category_rules = CategoryRules::load(&self.db_conn, budget.id)?;
         let mut transaction_map = HashMap::new();
         let mut new_transactions = Vec::new();
+        let mut transfers: Vec<PendingTransfer> = Vec::new();
+        let mut skipped_existing = Vec::new();
 
-        for t in load_transactions(path)?.into_iter() {
-            let amount_millis = milli_dollar_amount(t.amount);
-            let mut key = TransactionKey {
-                date: t.date_posted,
-                amount_millis,
-                occurrence: 1,
+        let parse_options = ParseOptions {
+            date_format: account::get_date_format(&self.db_conn, account.id)?,
+            ..self.parse_options.clone()
+        };
+        let statement = load_statement(path, &parse_options)?;
+        debug!(start = ?statement.start, end = ?statement.end, "Statement period");
+        let period = statement.start.zip(statement.end);
+        let overlapping = match period {
+            Some((start, end)) => {
+                statement_import::find_overlapping(&self.db_conn, account.id, start, end)?
+            }
+            None => None,
+        };
+        for t in statement.transactions.into_iter() {
+            let t = match sign_from_kind {
+                true => t.with_sign_from_kind(),
+                false => t,
             };
-            if transaction::exists(&self.db_conn, account.id, amount_millis, key.date)? {
-                println!(
-                    "Transaction with amount ${} on {} already imported.",
-                    t.amount, key.date
-                );
+            let amount_millis = t.amount_milli;
+            let date = t.date_posted;
+            let time = t.datetime.map(|dt| dt.time());
+            if transaction::exists(
+                &self.db_conn,
+                account.id,
+                amount_millis,
+                date,
+                time,
+                tolerance,
+                tolerance_days,
+            )? {
+                skipped_existing.push(SkippedTransaction {
+                    date,
+                    amount_milli: amount_millis,
+                    payee: t.name,
+                });
                 continue;
             }
+            // Numbered on from the matching transactions already recorded, so an earlier import's
+            // ids aren't reused, then past the ones already taken in this statement
+            let mut key = TransactionKey {
+                date,
+                amount_millis,
+                occurrence: transaction::count_matching(
+                    &self.db_conn,
+                    account.id,
+                    amount_millis,
+                    date,
+                )? + 1,
+                time,
+            };
             let mut import_id = key.get_id();
             while transaction_map.contains_key(&import_id) {
                 key.occurrence += 1;
                 import_id = key.get_id();
             }
 
+            let (name, memo) = (t.name.clone(), t.memo.clone());
             let mut new_transaction = NewTransaction::from(t);
             new_transaction.account_id = Some(account.uuid);
             new_transaction.import_id = Some(Some(import_id.clone()));
+            new_transaction.cleared = Some(cleared);
+            if let Some(renamed) = name.as_deref().and_then(|name| payee_rules.rename(name)) {
+                new_transaction.payee_name = Some(Some(renamed));
+            }
+            // Matched against the payee as it will appear in YNAB, i.e. after any renaming. Without
+            // a match the transaction is left uncategorized.
+            let payee = new_transaction.payee_name.clone().flatten();
+            if let Some(category) = payee.as_deref().and_then(|p| category_rules.category(p)) {
+                new_transaction.category_id = Some(Some(category));
+            }
+            // The other side of a transfer from a monitored account was already imported there, so
+            // that one becomes a transfer to this account rather than this being a second,
+            // unlinked entry
+            let counterpart = match amount_millis {
+                0 => None,
+                _ => transaction::find_transfer_counterpart(
+                    &self.db_conn,
+                    account.id,
+                    amount_millis,
+                    key.date,
+                    TRANSFER_WINDOW_DAYS,
+                )?,
+            };
 
-            transaction_map.insert(import_id, (key, new_transaction.clone()));
-            new_transactions.push(new_transaction);
+            let pending = PendingTransaction {
+                key,
+                transaction: new_transaction.clone(),
+                name,
+                memo,
+            };
+            transaction_map.insert(import_id, pending.clone());
+            match counterpart {
+                Some(counterpart) if !transfers.iter().any(|t| t.counterpart == counterpart) => {
+                    debug!(amount_millis, date = %key.date, "Found the other side of a transfer");
+                    transfers.push(PendingTransfer {
+                        counterpart,
+                        pending,
+                    });
+                }
+                _ => new_transactions.push(new_transaction),
+            }
         }
 
-        let mut retry = 0;
-        loop {
-            let resp = create_transaction(
-                &self.api_config,
-                &budget.uuid.hyphenated().to_string(),
-                PostTransactionsWrapper {
-                    transaction: None,
-                    transactions: Some(new_transactions.clone()),
-                },
-            )
+        Ok(PreparedImport {
+            budget,
+            account,
+            transactions: new_transactions,
+            transfers,
+            skipped_existing,
+            overlapping,
+            transaction_map,
+            ledger_balance: statement.ledger_balance,
+            period,
+        })
+    }
+
+    // Imports the statement's transactions, counting the outcome in the handler's metrics
+    #[instrument(skip_all, fields(path = %path.display(), budget = Empty, account = Empty))]
+    pub async fn create_transactions_with_retry(&self, path: &PathBuf) -> Result<ImportSummary> {
+        let result = self.create_transactions(path).await;
+        match &result {
+            Ok(summary) => self
+                .metrics
+                .record_import(summary.created, summary.duplicates),
+            Err(_) => self.metrics.record_error(),
+        }
+        result
+    }
+
+    async fn create_transactions(&self, path: &PathBuf) -> Result<ImportSummary> {
+        let PreparedImport {
+            budget,
+            account,
+            transactions: mut new_transactions,
+            transfers,
+            mut transaction_map,
+            ledger_balance,
+            skipped_existing,
+            overlapping,
+            period,
+        } = self.prepare_import(path)?;
+        let budget_uuid = &budget.uuid.hyphenated().to_string();
+        let total_milli = new_transactions
+            .iter()
+            .chain(transfers.iter().map(|t| &t.pending.transaction))
+            .filter_map(|t| t.amount)
+            .sum();
+        let (linked, unlinked) = self
+            .link_transfers(budget_uuid, &account, transfers)
             .await?;
-            println!("{:?}", resp);
-            new_transactions.clear();
-
-            if let Some(transactions) = resp.data.transactions {
-                for saved_transaction in transactions.iter() {
-                    let import_id =
-                        saved_transaction
-                            .import_id
-                            .clone()
-                            .flatten()
-                            .ok_or_else(|| {
-                                anyhow!(
-                                    "Did not find import_id in saved transaction \
-                                        response. Found {:?}",
-                                    saved_transaction.import_id
-                                )
-                            })?;
-                    let (key, _) = transaction_map.get(&import_id).ok_or_else(|| {
-                        anyhow!(
-                            "Transaction map does not contain {}:\n{:#?}",
-                            import_id,
-                            transaction_map
-                        )
-                    })?;
+        new_transactions.extend(unlinked);
+        let (imported, duplicates) = post_transactions(
+            &mut transaction_map,
+            new_transactions,
+            self.max_retries,
+            |transactions| async move {
+                let resp = self
+                    .call_api_create(|client| {
+                        let wrapper = PostTransactionsWrapper {
+                            transaction: None,
+                            transactions: Some(transactions.clone()),
+                        };
+                        client.create_transaction(budget_uuid.clone(), wrapper)
+                    })
+                    .await?;
+                debug!(?resp, "Created transactions");
+                Ok(*resp.data)
+            },
+            |import_id, pending| {
+                transaction::create_if_not_exists(
+                    &self.db_conn,
+                    TransactionRow {
+                        id: None,
+                        account_id: account.id,
+                        amount_milli: pending.key.amount_millis,
+                        date_posted: pending.key.date,
+                        time_posted: pending.key.time,
+                        payee_name: pending.name.clone(),
+                        memo: pending.memo.clone(),
+                        import_id: Some(import_id.into()),
+                        imported_at: Some(Local::now().to_rfc3339()),
+                    },
+                )
+            },
+        )
+        .await?;
 
+        if let Some((start, end)) = period {
+            statement_import::record(
+                &self.db_conn,
+                &StatementImportRow {
+                    account_id: account.id,
+                    path: path.display().to_string(),
+                    start,
+                    end,
+                    imported_at: Local::now().to_rfc3339(),
+                },
+            )?;
+        }
+        if let Some(ledger_balance) = ledger_balance {
+            if let Err(err) = self
+                .check_ledger_balance(budget_uuid, &account, &ledger_balance)
+                .await
+            {
+                warn!("Failed to check ledger balance: {:?}", err);
+            }
+        }
+        Ok(ImportSummary {
+            budget: budget.name,
+            account: account.name,
+            created: imported + linked,
+            total_milli,
+            duplicates,
+            skipped_existing,
+            overlapping: overlapping.map(|row| row.path),
+        })
+    }
+
+    // Links each transfer by giving the other side, already in YNAB, this account's transfer payee.
+    // YNAB then adds this side itself, which is recorded as imported. Returns how many were linked,
+    // along with the transactions of the ones which couldn't be, to be created as usual instead.
+    async fn link_transfers(
+        &self,
+        budget_uuid: &str,
+        account: &AccountRow,
+        transfers: Vec<PendingTransfer>,
+    ) -> Result<(usize, Vec<NewTransaction>)> {
+        if transfers.is_empty() {
+            return Ok((0, Vec::new()));
+        }
+        let payee_id = match self.transfer_payee_id(account).await {
+            Ok(payee_id) => payee_id,
+            Err(err) => {
+                warn!(
+                    "Failed to fetch transfer payees, transfers won't be linked: {:?}",
+                    err
+                );
+                None
+            }
+        };
+        let Some(payee_id) = payee_id else {
+            warn!(account = %account.name, "No transfer payee, transfers won't be linked");
+            let unlinked = transfers.into_iter().map(|t| t.pending.transaction);
+            return Ok((0, unlinked.collect()));
+        };
+
+        let mut linked = 0;
+        let mut unlinked = Vec::new();
+        for PendingTransfer {
+            counterpart,
+            pending,
+        } in transfers
+        {
+            let update = SaveTransactionWithIdOrImportId {
+                import_id: Some(Some(counterpart.import_id.clone())),
+                payee_id: Some(Some(payee_id)),
+                // Transfers between budget accounts aren't categorized
+                category_id: Some(None),
+                ..SaveTransactionWithIdOrImportId::new()
+            };
+            let result = self
+                .call_api(|client| {
+                    let wrapper = PatchTransactionsWrapper::new(vec![update.clone()]);
+                    client.update_transactions(budget_uuid.into(), wrapper)
+                })
+                .await;
+            match result {
+                Ok(resp)
+                    if resp
+                        .data
+                        .transactions
+                        .as_ref()
+                        .is_some_and(|t| !t.is_empty()) =>
+                {
+                    debug!(import_id = %counterpart.import_id, "Linked transfer");
+                    transaction::set_transfer_linked(&self.db_conn, counterpart.id)?;
                     transaction::create_if_not_exists(
                         &self.db_conn,
                         TransactionRow {
                             id: None,
                             account_id: account.id,
-                            amount_milli: key.amount_millis,
-                            date_posted: key.date,
+                            amount_milli: pending.key.amount_millis,
+                            date_posted: pending.key.date,
+                            time_posted: pending.key.time,
+                            payee_name: pending.name,
+                            memo: pending.memo,
+                            import_id: None,
+                            imported_at: Some(Local::now().to_rfc3339()),
                         },
                     )?;
+                    linked += 1;
                 }
-            }
-
-            match resp.data.duplicate_import_ids {
-                None => {
-                    break;
+                Ok(_) => {
+                    warn!(import_id = %counterpart.import_id, "Other side of transfer not in YNAB");
+                    unlinked.push(pending.transaction);
                 }
-                Some(ids) => {
-                    if retry == self.max_retries {
-                        return Err(anyhow!(
-                            "One or more transactions were not succesfully imported, {:#?}",
-                            ids
-                        ));
-                    }
-                    for import_id in ids {
-                        let (key, transaction) = transaction_map.get(&import_id).unwrap();
-                        let mut new_key = *key;
-                        new_key.occurrence += 1;
-                        let import_id = new_key.get_id();
-
-                        let new_transaction = NewTransaction {
-                            import_id: Some(Some(import_id.clone())),
-                            ..transaction.clone()
-                        };
-                        transaction_map.insert(import_id, (new_key, new_transaction.clone()));
-                        new_transactions.push(new_transaction);
-                    }
-                    retry += 1;
+                Err(err) => {
+                    warn!("Failed to link transfer, creating it unlinked: {:?}", err);
+                    unlinked.push(pending.transaction);
                 }
             }
         }
+        Ok((linked, unlinked))
+    }
+
+    // The payee YNAB uses for transfers into the account. Accounts set up before these were
+    // recorded get theirs from the payees API, which lists the transfer payee of every account in
+    // the budget. That's only asked once per budget, even if it doesn't list the account.
+    async fn transfer_payee_id(&self, account: &AccountRow) -> Result<Option<Uuid>> {
+        if let Some(payee_id) = account::transfer_payee_id(&self.db_conn, account.id)? {
+            return Ok(Some(payee_id));
+        }
+        if self
+            .payees_fetched
+            .lock()
+            .unwrap()
+            .contains(&account.budget_id)
+        {
+            return Ok(None);
+        }
+        let budget = budget::get(&self.db_conn, account.budget_id)?;
+        let budget_uuid = &budget.uuid.hyphenated().to_string();
+        let resp = self
+            .call_api(|client| client.get_payees(budget_uuid.clone()))
+            .await?;
+        for payee in resp.data.payees.into_iter().filter(|p| !p.deleted) {
+            if let Some(Some(account_uuid)) = payee.transfer_account_id {
+                let account_uuid = Uuid::parse_str(&account_uuid)?;
+                account::set_transfer_payee_id(&self.db_conn, account_uuid, payee.id)?;
+            }
+        }
+        self.payees_fetched
+            .lock()
+            .unwrap()
+            .insert(account.budget_id);
+        account::transfer_payee_id(&self.db_conn, account.id)
+    }
+
+    // Compares the statement's LEDGERBAL with the YNAB account balance after an import. A mismatch
+    // of more than a cent usually means a transaction was missed or double-counted.
+    async fn check_ledger_balance(
+        &self,
+        budget_uuid: &str,
+        account: &AccountRow,
+        ledger_balance: &LedgerBalance,
+    ) -> Result<()> {
+        let account_uuid = &account.uuid.hyphenated().to_string();
+        let resp = self
+            .call_api(|client| client.get_account_by_id(budget_uuid.into(), account_uuid.clone()))
+            .await?;
+        let expected = ledger_balance.amount_milli;
+        let actual = resp.data.account.balance;
+        if (expected - actual).abs() > 10 {
+            warn!(
+                account = %account.name,
+                "Balance in YNAB is ${} but the statement ledger balance as of {} is ${}",
+                format_amount(actual),
+                ledger_balance.as_of,
+                format_amount(expected)
+            );
+        }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::fake::FakeClient;
+    use crate::db::test_conn;
+    use chrono::TimeZone;
+    use pretty_assertions::assert_eq;
+    use std::env::temp_dir;
+    use std::ops::Deref;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use ynab_api::models::{Payee, TransactionDetail};
+
+    #[test]
+    fn test_archive_path() {
+        let imported_at = Local.with_ymd_and_hms(2024, 11, 8, 13, 5, 9).unwrap();
+        assert_eq!(
+            archive_path(
+                Path::new("/tx"),
+                Path::new("/tx/.imported"),
+                Path::new("/tx/Budget/Checking/statement.qfx"),
+                imported_at
+            )
+            .unwrap(),
+            PathBuf::from("/tx/.imported/Budget/Checking/20241108130509_statement.qfx")
+        );
+        assert_eq!(
+            archive_path(
+                Path::new("/tx"),
+                Path::new("/archive"),
+                Path::new("/elsewhere/statement.qfx"),
+                imported_at
+            )
+            .unwrap(),
+            PathBuf::from("/archive/20241108130509_statement.qfx")
+        );
+    }
+
+    #[test]
+    fn test_move_file() {
+        let dir = TestDir::new("move");
+        let from = dir.join("a.qfx");
+        let to = dir.join("b.qfx");
+        fs::write(&from, "contents").unwrap();
+
+        move_file(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&to).unwrap(), "contents");
+    }
+
+    #[test]
+    fn test_import_id_length() {
+        let key = TransactionKey {
+            date: NaiveDate::from_ymd_opt(2024, 11, 15).unwrap(),
+            amount_millis: -7350,
+            occurrence: 1,
+            time: None,
+        };
+        assert_eq!(key.get_id(), "YNAB:2024-11-15:-7350:1");
+
+        let key = TransactionKey {
+            date: NaiveDate::from_ymd_opt(9999, 12, 31).unwrap(),
+            amount_millis: i64::MIN,
+            occurrence: 1000,
+            time: None,
+        };
+        let id = key.get_id();
+        assert_eq!(id.len(), MAX_IMPORT_ID_LEN);
+        assert!(id.starts_with("YNAB:"));
+        assert_eq!(id, key.get_id());
+        let next = TransactionKey {
+            occurrence: 1001,
+            ..key
+        };
+        assert_ne!(id, next.get_id());
+    }
+
+    fn pending(amount_millis: i64, occurrence: usize) -> (String, PendingTransaction) {
+        let key = TransactionKey {
+            date: NaiveDate::from_ymd_opt(2024, 11, 15).unwrap(),
+            amount_millis,
+            occurrence,
+            time: None,
+        };
+        let transaction = NewTransaction {
+            import_id: Some(Some(key.get_id())),
+            ..NewTransaction::new()
+        };
+        let pending = PendingTransaction {
+            key,
+            transaction,
+            name: None,
+            memo: None,
+        };
+        (key.get_id(), pending)
+    }
+
+    // A stand-in for YNAB which already has transactions with the given import ids
+    fn respond(
+        existing: &[String],
+        transactions: Vec<NewTransaction>,
+    ) -> SaveTransactionsResponseData {
+        let (duplicate, saved): (Vec<_>, Vec<_>) = transactions
+            .into_iter()
+            .map(|t| t.import_id.flatten().unwrap())
+            .partition(|id| existing.contains(id));
+        let saved = saved
+            .into_iter()
+            .map(|id| TransactionDetail {
+                import_id: Some(Some(id)),
+                ..TransactionDetail::new(
+                    String::new(),
+                    String::new(),
+                    -7350,
+                    TransactionClearedStatus::Cleared,
+                    false,
+                    Uuid::nil(),
+                    false,
+                    String::new(),
+                    Vec::new(),
+                )
+            })
+            .collect();
+        SaveTransactionsResponseData {
+            transactions: Some(saved),
+            duplicate_import_ids: Some(duplicate),
+            ..SaveTransactionsResponseData::new(Vec::new(), 0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_post_transactions_duplicates() {
+        // The same transaction is reported as a duplicate twice before it gets a free id
+        let existing = [pending(-7350, 1).0, pending(-7350, 2).0];
+        let (id, first) = pending(-7350, 1);
+        let mut transaction_map = HashMap::from([(id, first.clone())]);
+        let mut posts = 0;
+        let mut saved = Vec::new();
+        let result = post_transactions(
+            &mut transaction_map,
+            vec![first.transaction.clone()],
+            2,
+            |transactions| {
+                posts += 1;
+                let data = respond(&existing, transactions);
+                async move { Ok(data) }
+            },
+            |_, pending| {
+                saved.push(pending.key.occurrence);
+                Ok(())
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), (1, 2));
+        assert_eq!(posts, 3);
+        assert_eq!(saved, vec![3]);
+
+        // One retry short, it gives up instead of posting again
+        let (id, first) = pending(-7350, 1);
+        let mut transaction_map = HashMap::from([(id, first.clone())]);
+        let mut posts = 0;
+        let result = post_transactions(
+            &mut transaction_map,
+            vec![first.transaction.clone()],
+            1,
+            |transactions| {
+                posts += 1;
+                let data = respond(&existing, transactions);
+                async move { Ok(data) }
+            },
+            |_, _| Ok(()),
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(posts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_post_transactions_records_duplicates() {
+        // YNAB saves the first transaction straight away, but already has the second's id
+        let existing = [pending(-7350, 1).0];
+        let (new_id, new) = pending(-500, 1);
+        let (dup_id, dup) = pending(-7350, 1);
+        let transactions = vec![new.transaction.clone(), dup.transaction.clone()];
+        let map = HashMap::from([(new_id.clone(), new), (dup_id.clone(), dup)]);
+
+        let mut transaction_map = map.clone();
+        let mut recorded = Vec::new();
+        let result = post_transactions(
+            &mut transaction_map,
+            transactions.clone(),
+            1,
+            |transactions| {
+                let data = respond(&existing, transactions);
+                async move { Ok(data) }
+            },
+            |import_id, _| {
+                recorded.push(import_id.to_string());
+                Ok(())
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), (2, 1));
+        assert_eq!(recorded, vec![new_id.clone(), pending(-7350, 2).0]);
+
+        // Without retries the duplicate is left unrecorded, to be tried again on the next import
+        let mut transaction_map = map.clone();
+        let mut recorded = Vec::new();
+        let result = post_transactions(
+            &mut transaction_map,
+            transactions,
+            0,
+            |transactions| {
+                let data = respond(&existing, transactions);
+                async move { Ok(data) }
+            },
+            |import_id, _| {
+                recorded.push(import_id.to_string());
+                Ok(())
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(recorded, vec![new_id]);
+    }
+
+    // A fresh directory under the system temp dir, removed when it's dropped even if the test
+    // fails. The counter keeps the ones from tests running at the same time apart.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = temp_dir().canonicalize().unwrap().join(format!(
+                "ynab-importer-{}-{}-{}",
+                name,
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl Deref for TestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // A monitored folder at dir with a single Budget/Checking account, and a handler for it making
+    // its API calls through client
+    fn test_handler(dir: &Path, client: &FakeClient) -> EventHandler {
+        fs::create_dir_all(dir.join("Budget").join("Checking")).unwrap();
+        let conn = test_conn();
+        config::set_transaction_dir(&conn, dir).unwrap();
+        config::set_notifications_enabled(&conn, false).unwrap();
+        config::set(&conn, config::ACCESS_TOKEN, "token").unwrap();
+        conn.execute_batch(
+            "INSERT INTO budget(uuid, name) \
+                VALUES ('00000000-0000-0000-0000-000000000001', 'Budget');
+            INSERT INTO account(budget_id, uuid, name) \
+                VALUES (1, '00000000-0000-0000-0000-000000000002', 'Checking');",
+        )
+        .unwrap();
+        EventHandler::with_client(conn, Box::new(client.clone())).unwrap()
+    }
+
+    fn create_event(paths: Vec<PathBuf>) -> DebouncedEvent {
+        let mut event = notify_debouncer_full::notify::Event::new(Create(CreateKind::File));
+        event.paths = paths;
+        DebouncedEvent::new(event, std::time::Instant::now())
+    }
+
+    #[tokio::test]
+    async fn test_import_with_fake_client() {
+        let dir = TestDir::new("fake-client");
+        let client = FakeClient::default();
+        let handler = test_handler(&dir, &client);
+        let path = dir.join("Budget").join("Checking").join("statement.qfx");
+        fs::write(
+            &path,
+            "<OFX><BANKTRANLIST><DTSTART>20241101<DTEND>20241120\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-7.35<NAME>PIZZA</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-7.35<NAME>PIZZA</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241116120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            </BANKTRANLIST></OFX>",
+        )
+        .unwrap();
+
+        // The first pizza was already imported by YNAB's own importer
+        client.state.lock().unwrap().import_ids = vec!["YNAB:2024-11-15:-7350:1".into()];
+
+        let summary = handler.create_transactions_with_retry(&path).await.unwrap();
+        assert_eq!(summary.created, 3);
+        assert_eq!(summary.duplicates, 1);
+        let metrics = handler.metrics.render();
+        assert!(metrics.contains("\ntransactions_created_total 3\n"));
+        assert!(metrics.contains("\ntransactions_duplicate_total 1\n"));
+        assert!(summary.skipped_existing.is_empty());
+        assert_eq!(summary.overlapping, None);
+        {
+            let state = client.state.lock().unwrap();
+            assert_eq!(state.posted.len(), 2);
+            assert_eq!(
+                state.posted[1][0].import_id,
+                Some(Some("YNAB:2024-11-15:-7350:3".into()))
+            );
+        }
+
+        // Everything is recorded locally, so nothing is sent the second time
+        let summary = handler.create_transactions_with_retry(&path).await.unwrap();
+        assert_eq!(summary.created, 0);
+        assert_eq!(
+            summary.skipped_existing,
+            vec![
+                SkippedTransaction {
+                    date: NaiveDate::from_ymd_opt(2024, 11, 15).unwrap(),
+                    amount_milli: -7350,
+                    payee: Some("PIZZA".into()),
+                },
+                SkippedTransaction {
+                    date: NaiveDate::from_ymd_opt(2024, 11, 15).unwrap(),
+                    amount_milli: -7350,
+                    payee: Some("PIZZA".into()),
+                },
+                SkippedTransaction {
+                    date: NaiveDate::from_ymd_opt(2024, 11, 16).unwrap(),
+                    amount_milli: -500,
+                    payee: Some("PARKING".into()),
+                },
+            ]
+        );
+        // The same period again is reported once for the whole statement
+        assert_eq!(summary.overlapping, Some(path.display().to_string()));
+        assert_eq!(client.state.lock().unwrap().posted.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_occurrence_after_recorded_transactions() {
+        let dir = TestDir::new("occurrence");
+        let client = FakeClient::default();
+        let handler = test_handler(&dir, &client);
+        let write_statement = |name: &str, time: &str| {
+            let path = dir.join("Budget").join("Checking").join(name);
+            let statement = format!(
+                "<OFX><BANKTRANLIST>\
+                <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115{}<TRNAMT>-7.35<NAME>PIZZA</STMTTRN>\
+                </BANKTRANLIST></OFX>",
+                time
+            );
+            fs::write(&path, statement).unwrap();
+            path
+        };
+
+        let lunch = write_statement("lunch.qfx", "120000");
+        let summary = handler
+            .create_transactions_with_retry(&lunch)
+            .await
+            .unwrap();
+        assert_eq!(summary.created, 1);
+
+        // Another pizza later that day, from a later statement, is numbered after the first
+        let dinner = write_statement("dinner.qfx", "190000");
+        let summary = handler
+            .create_transactions_with_retry(&dinner)
+            .await
+            .unwrap();
+        assert_eq!(summary.created, 1);
+        assert_eq!(summary.duplicates, 0);
+        let state = client.state.lock().unwrap();
+        assert_eq!(
+            state.posted[1][0].import_id,
+            Some(Some("YNAB:2024-11-15:-7350:2".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_link_transfer() {
+        let dir = TestDir::new("transfer");
+        let client = FakeClient::default();
+        let handler = test_handler(&dir, &client);
+        handler
+            .db_conn
+            .execute(
+                "INSERT INTO account(budget_id, uuid, name) \
+                VALUES (1, '00000000-0000-0000-0000-000000000003', 'Card')",
+                [],
+            )
+            .unwrap();
+        fs::create_dir_all(dir.join("Budget").join("Card")).unwrap();
+        let card_payee = Uuid::from_u128(9);
+        client.state.lock().unwrap().payees = vec![Payee {
+            transfer_account_id: Some(Some("00000000-0000-0000-0000-000000000003".into())),
+            ..Payee::new(card_payee, "Transfer : Card".into(), false)
+        }];
+
+        let checking = dir.join("Budget").join("Checking").join("statement.qfx");
+        fs::write(
+            &checking,
+            "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115<TRNAMT>-50.00<NAME>CARD PAYMENT</STMTTRN>\
+            </BANKTRANLIST></OFX>",
+        )
+        .unwrap();
+        let summary = handler
+            .create_transactions_with_retry(&checking)
+            .await
+            .unwrap();
+        assert_eq!(summary.created, 1);
+        // Nothing to link, so the payees weren't needed
+        assert_eq!(client.state.lock().unwrap().payee_requests, 0);
+
+        let card = dir.join("Budget").join("Card").join("statement.qfx");
+        fs::write(
+            &card,
+            "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20241116<TRNAMT>50.00<NAME>PAYMENT</STMTTRN>\
+            </BANKTRANLIST></OFX>",
+        )
+        .unwrap();
+        let summary = handler.create_transactions_with_retry(&card).await.unwrap();
+        assert_eq!(summary.created, 1);
+        {
+            // The checking side became the transfer, rather than posting a second one for YNAB to
+            // pair with a new checking transaction
+            let state = client.state.lock().unwrap();
+            assert_eq!(state.posted.len(), 1);
+            assert_eq!(state.payee_requests, 1);
+            assert_eq!(state.updated.len(), 1);
+            assert_eq!(
+                state.updated[0].import_id,
+                Some(Some("YNAB:2024-11-15:-50000:1".into()))
+            );
+            assert_eq!(state.updated[0].payee_id, Some(Some(card_payee)));
+        }
+
+        // Both sides are recorded, and neither is linked again
+        let rows = transaction::list_for_account(&handler.db_conn, 2).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].import_id, None);
+        let counterpart = transaction::find_transfer_counterpart(
+            &handler.db_conn,
+            2,
+            50000,
+            NaiveDate::from_ymd_opt(2024, 11, 16).unwrap(),
+            TRANSFER_WINDOW_DAYS,
+        )
+        .unwrap();
+        assert_eq!(counterpart, None);
+    }
+
+    #[tokio::test]
+    async fn test_handle_create_event() {
+        let dir = TestDir::new("handle");
+        let client = FakeClient::default();
+        let handler = test_handler(&dir, &client);
+        let path = dir.join("Budget").join("Checking").join("statement.qfx");
+        fs::write(
+            &path,
+            "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-7.35<NAME>PIZZA\
+            <MEMO>LARGE</STMTTRN>\
+            <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20241116120000.000<TRNAMT>1529.80<NAME>PAYROLL\
+            </STMTTRN>\
+            </BANKTRANLIST></OFX>",
+        )
+        .unwrap();
+
+        let handled = handler.handle(&create_event(vec![path.clone()])).await;
+        assert_eq!(handled.unwrap(), Handled::Done);
+
+        let posted = client.state.lock().unwrap().posted.clone();
+        assert_eq!(posted.len(), 1);
+        let account_uuid = Uuid::from_u128(2);
+        let summary: Vec<_> = posted[0]
+            .iter()
+            .map(|t| {
+                (
+                    t.account_id,
+                    t.amount,
+                    t.date.clone(),
+                    t.payee_name.clone().flatten(),
+                    t.import_id.clone().flatten(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    Some(account_uuid),
+                    Some(-7350),
+                    Some("2024-11-15".to_string()),
+                    Some("PIZZA".to_string()),
+                    Some("YNAB:2024-11-15:-7350:1".to_string()),
+                ),
+                (
+                    Some(account_uuid),
+                    Some(1529800),
+                    Some("2024-11-16".to_string()),
+                    Some("PAYROLL".to_string()),
+                    Some("YNAB:2024-11-16:1529800:1".to_string()),
+                ),
+            ]
+        );
+
+        let rows = transaction::list_for_account(&handler.db_conn, 1).unwrap();
+        let rows: Vec<_> = rows
+            .iter()
+            .map(|r| (r.amount_milli, r.memo.as_deref(), r.import_id.as_deref()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                (-7350, Some("LARGE"), Some("YNAB:2024-11-15:-7350:1")),
+                (1529800, None, Some("YNAB:2024-11-16:1529800:1")),
+            ]
+        );
+
+        // Archived once imported
+        assert!(!path.exists());
+        assert!(dir
+            .join(".imported")
+            .join("Budget")
+            .join("Checking")
+            .is_dir());
+    }
+
+    #[test]
+    fn test_prepare_import_checks_account_id() {
+        let dir = TestDir::new("account-id");
+        let handler = test_handler(&dir, &FakeClient::default());
+        handler
+            .db_conn
+            .execute(
+                "INSERT INTO account(budget_id, uuid, name) \
+                VALUES (1, '00000000-0000-0000-0000-000000000003', 'Savings')",
+                [],
+            )
+            .unwrap();
+        let path = dir.join("Budget").join("Checking").join("statement.qfx");
+        let write_statement = |acct_id: &str| {
+            let statement = format!(
+                "<OFX><BANKACCTFROM><ACCTID>{}</BANKACCTFROM><BANKTRANLIST>\
+                <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115<TRNAMT>-7.35<NAME>PIZZA</STMTTRN>\
+                </BANKTRANLIST></OFX>",
+                acct_id
+            );
+            fs::write(&path, statement).unwrap();
+        };
+
+        // The folder decides for an account that isn't linked, without linking it
+        write_statement("1111");
+        let prepared = handler.prepare_import(&path).unwrap();
+        assert_eq!(prepared.account.name, "Checking");
+        assert_eq!(account::get_external_id(&handler.db_conn, 1).unwrap(), None);
+        account::set_external_id(&handler.db_conn, 1, "1111").unwrap();
+
+        // Misfiled statements go to the account linked to their ACCTID
+        account::set_external_id(&handler.db_conn, 2, "2222").unwrap();
+        write_statement("2222");
+        let prepared = handler.prepare_import(&path).unwrap();
+        assert_eq!(prepared.account.name, "Savings");
+
+        // Matching neither, it's up to link-account to say which account it belongs to
+        write_statement("3333");
+        let err = handler.prepare_import(&path).unwrap_err();
+        assert!(err.to_string().contains("link-account"));
+    }
+
+    #[test]
+    fn test_group_existing_by_resolved_account() {
+        let dir = TestDir::new("group-accounts");
+        let handler = test_handler(&dir, &FakeClient::default());
+        handler
+            .db_conn
+            .execute(
+                "INSERT INTO account(budget_id, uuid, name) \
+                VALUES (1, '00000000-0000-0000-0000-000000000003', 'Savings')",
+                [],
+            )
+            .unwrap();
+        let write_statement = |path: &PathBuf, acct_id: &str| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            let statement = format!(
+                "<OFX><BANKACCTFROM><ACCTID>{}</BANKACCTFROM></OFX>",
+                acct_id
+            );
+            fs::write(path, statement).unwrap();
+        };
+        let group = |files: &[PathBuf]| {
+            crate::scan::group_by_account(files, |path| {
+                handler
+                    .resolve_account(path)
+                    .ok()
+                    .map(|(_, account)| account.id)
+            })
+        };
+
+        // Statements in an account's subfolders are imported into that account
+        let checking = dir.join("Budget").join("Checking");
+        let files = vec![
+            checking.join("2024").join("11").join("a.qfx"),
+            dir.join("Budget").join("Savings").join("a.qfx"),
+            checking.join("b.qfx"),
+            checking.join("2024").join("12").join("c.qfx"),
+        ];
+        for path in &files {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "<OFX></OFX>").unwrap();
+        }
+        assert_eq!(
+            group(&files),
+            vec![vec![&files[0], &files[2], &files[3]], vec![&files[1]]]
+        );
+
+        // Without account folders, a budget's statements are told apart by their ACCTID
+        config::set_folder_depth(&handler.db_conn, FolderDepth::BudgetOnly).unwrap();
+        account::set_external_id(&handler.db_conn, 1, "1111").unwrap();
+        account::set_external_id(&handler.db_conn, 2, "2222").unwrap();
+        let files = vec![
+            dir.join("Budget").join("a.qfx"),
+            dir.join("Budget").join("b.qfx"),
+            dir.join("Budget").join("2024").join("c.qfx"),
+        ];
+        write_statement(&files[0], "1111");
+        write_statement(&files[1], "2222");
+        write_statement(&files[2], "1111");
+        assert_eq!(
+            group(&files),
+            vec![vec![&files[0], &files[2]], vec![&files[1]]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_skips_other_files() {
+        let dir = TestDir::new("handle-skip");
+        let client = FakeClient::default();
+        let handler = test_handler(&dir, &client);
+        let path = dir.join("Budget").join("Checking").join("notes.txt");
+        fs::write(&path, "not a statement").unwrap();
+
+        let handled = handler.handle(&create_event(vec![path.clone()])).await;
+        assert_eq!(handled.unwrap(), Handled::Done);
+        assert!(client.state.lock().unwrap().posted.is_empty());
+        assert!(path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_handle_event_without_path() {
+        let dir = TestDir::new("handle-empty");
+        let client = FakeClient::default();
+        let handler = test_handler(&dir, &client);
+
+        let err = handler.handle(&create_event(Vec::new())).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ImportError>(),
+            Some(ImportError::NoPathError)
+        ));
+    }
+
+    #[test]
+    fn test_requeue_duplicates() {
+        // Occurrence 2 already belongs to another transaction in the statement
+        let mut transaction_map = HashMap::from([pending(-7350, 1), pending(-7350, 2)]);
+        let requeued = requeue_duplicates(&mut transaction_map, &[pending(-7350, 1).0]).unwrap();
+        assert_eq!(requeued.len(), 1);
+        assert_eq!(requeued[0].import_id, Some(Some(pending(-7350, 3).0)));
+        assert_eq!(transaction_map[&pending(-7350, 3).0].key.occurrence, 3);
+
+        assert!(requeue_duplicates(&mut transaction_map, &["YNAB:unknown".into()]).is_err());
+    }
+
+    #[test]
+    fn test_file_hash() {
+        let dir = TestDir::new("hash");
+        let path = dir.join("a.qfx");
+        fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            file_hash(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_file_settled() {
+        let dir = TestDir::new("settled");
+        let path = dir.join("a.qfx");
+        fs::write(&path, "<OFX>").unwrap();
+        assert!(file_settled(&path).await.unwrap());
+
+        // Appended to partway through the check, as a download would be
+        let writer = {
+            let path = path.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(SETTLE_INTERVAL / 2).await;
+                let contents = fs::read_to_string(&path).unwrap();
+                fs::write(&path, contents + "<STMTTRN>").unwrap();
+            })
+        };
+        assert!(!file_settled(&path).await.unwrap());
+        writer.await.unwrap();
+        assert!(file_settled(&path).await.unwrap());
+
+        assert!(file_settled(&dir.join("missing.qfx")).await.is_err());
+    }
+
+    #[test]
+    fn test_memo_with_check_number() {
+        assert_eq!(
+            memo_with_check_number(Some("Rent".into()), Some("1042".into())),
+            Some("Rent Check #1042".into())
+        );
+        assert_eq!(
+            memo_with_check_number(None, Some("1042".into())),
+            Some("Check #1042".into())
+        );
+        assert_eq!(
+            memo_with_check_number(Some("Rent".into()), None),
+            Some("Rent".into())
+        );
+        assert_eq!(memo_with_check_number(None, None), None);
+    }
+
+    #[test]
+    fn test_statement_extension() {
+        assert_eq!(statement_extension(Path::new("a.QFX")), Some("qfx".into()));
+        assert_eq!(statement_extension(Path::new("a.ofx.gz")), Some("ofx".into()));
+        assert_eq!(statement_extension(Path::new("a.csv.gz")), Some("csv".into()));
+        assert_eq!(statement_extension(Path::new("a.gz")), None);
+    }
+
+    #[test]
+    fn test_budget_and_account_from_path() {
+        let base = temp_dir().canonicalize().unwrap();
+        let path = base.join("Budget").join("Checking").join("statement.qfx");
+        assert_eq!(
+            get_budget_and_account_from_path(&base, &path, FolderDepth::BudgetAccount).unwrap(),
+            ("Budget".into(), Some("Checking".into()))
+        );
+
+        let path = base
+            .join("Budget")
+            .join("Checking")
+            .join("2024")
+            .join("12")
+            .join("statement.qfx");
+        assert_eq!(
+            get_budget_and_account_from_path(&base, &path, FolderDepth::BudgetAccount).unwrap(),
+            ("Budget".into(), Some("Checking".into()))
+        );
+
+        let path = base.join("Budget").join("statement.qfx");
+        assert!(
+            get_budget_and_account_from_path(&base, &path, FolderDepth::BudgetAccount).is_err()
+        );
+
+        let path = temp_dir().join("elsewhere").join("a").join("statement.qfx");
+        assert!(get_budget_and_account_from_path(
+            &base.join("x"),
+            &path,
+            FolderDepth::BudgetAccount
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_budget_only_from_path() {
+        let base = temp_dir().canonicalize().unwrap();
+        let path = base.join("Budget").join("statement.qfx");
+        assert_eq!(
+            get_budget_and_account_from_path(&base, &path, FolderDepth::BudgetOnly).unwrap(),
+            ("Budget".into(), None)
+        );
+
+        let path = base.join("Budget").join("2024").join("statement.qfx");
+        assert_eq!(
+            get_budget_and_account_from_path(&base, &path, FolderDepth::BudgetOnly).unwrap(),
+            ("Budget".into(), None)
+        );
+
+        let path = base.join("statement.qfx");
+        assert!(get_budget_and_account_from_path(&base, &path, FolderDepth::BudgetOnly).is_err());
+    }
+}
@@ -0,0 +1,81 @@
+use crate::db::transaction;
+use anyhow::Result;
+use rusqlite::Connection;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+// Formats milliunits as a dollar amount, e.g. -7350 -> -7.35
+pub fn format_amount(milliunits: i64) -> String {
+    let sign = if milliunits < 0 { "-" } else { "" };
+    let cents = (milliunits.abs() + 5) / 10;
+    format!("{}{}.{:02}", sign, cents / 100, cents % 100)
+}
+
+// Writes every transaction the importer has recorded as CSV, oldest first, and returns how many
+// there were. Payee and memo are empty for rows recorded before they were stored.
+pub fn write_csv<W: Write>(conn: &Connection, writer: W) -> Result<usize> {
+    let mut csv = csv::Writer::from_writer(writer);
+    csv.write_record(["budget", "account", "date", "amount", "payee", "memo"])?;
+    let rows = transaction::list_for_export(conn)?;
+    for row in rows.iter() {
+        csv.write_record([
+            row.budget.as_str(),
+            row.account.as_str(),
+            row.date_posted.as_str(),
+            format_amount(row.amount_milli).as_str(),
+            row.payee_name.as_deref().unwrap_or_default(),
+            row.memo.as_deref().unwrap_or_default(),
+        ])?;
+    }
+    csv.flush()?;
+    Ok(rows.len())
+}
+
+pub fn export_csv(conn: &Connection, path: &Path) -> Result<usize> {
+    write_csv(conn, File::create(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_conn;
+    use crate::db::transaction::TransactionRow;
+
+    #[test]
+    fn test_write_csv() {
+        let conn = test_conn();
+        conn.execute_batch(
+            "INSERT INTO budget(uuid, name) VALUES ('00000000-0000-0000-0000-000000000001', 'b');
+            INSERT INTO account(budget_id, uuid, name) VALUES
+                (1, '00000000-0000-0000-0000-000000000002', 'Checking'),
+                (1, '00000000-0000-0000-0000-000000000003', 'Visa');",
+        )
+        .unwrap();
+        let rows = [
+            (2, -7350, "2024-11-16", Some("PIZZA, RESTAURANT")),
+            (1, 152980, "2024-11-15", Some("PAYROLL")),
+            (2, -500, "2024-11-15", None),
+        ];
+        for (account_id, amount, date, payee) in rows {
+            transaction::create_if_not_exists(
+                &conn,
+                TransactionRow {
+                    payee_name: payee.map(String::from),
+                    ..TransactionRow::new(amount, date.into(), account_id).unwrap()
+                },
+            )
+            .unwrap();
+        }
+
+        let mut out = Vec::new();
+        assert_eq!(write_csv(&conn, &mut out).unwrap(), 3);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "budget,account,date,amount,payee,memo\n\
+            b,Checking,2024-11-15,152.98,PAYROLL,\n\
+            b,Visa,2024-11-15,-0.50,,\n\
+            b,Visa,2024-11-16,-7.35,\"PIZZA, RESTAURANT\",\n"
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::db::get_sqlite_conn;
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use std::io::Cursor;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use tiny_http::{Header, Method, Response, Server};
+use tracing::{info, warn};
+
+// What the service has done since it started. Shared between the watcher, which updates it, and
+// the HTTP listener, which reports it.
+#[derive(Default)]
+pub struct Metrics {
+    pub files_processed_total: AtomicU64,
+    pub transactions_created_total: AtomicU64,
+    pub transactions_duplicate_total: AtomicU64,
+    pub import_errors_total: AtomicU64,
+
+    // Unix time of the last successful import, 0 until there is one
+    pub last_import_timestamp: AtomicI64,
+
+    // Set while the watcher is handling file system events
+    watching: AtomicBool,
+}
+
+impl Metrics {
+    pub fn record_import(&self, created: usize, duplicates: usize) {
+        self.files_processed_total.fetch_add(1, Ordering::Relaxed);
+        self.transactions_created_total
+            .fetch_add(created as u64, Ordering::Relaxed);
+        self.transactions_duplicate_total
+            .fetch_add(duplicates as u64, Ordering::Relaxed);
+        self.last_import_timestamp
+            .store(Local::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn set_watching(&self, watching: bool) {
+        self.watching.store(watching, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.import_errors_total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Prometheus' text exposition format
+    pub fn render(&self) -> String {
+        let counters = [
+            (
+                "files_processed_total",
+                "Statements imported",
+                &self.files_processed_total,
+            ),
+            (
+                "transactions_created_total",
+                "Transactions created in YNAB",
+                &self.transactions_created_total,
+            ),
+            (
+                "transactions_duplicate_total",
+                "Transactions YNAB already had",
+                &self.transactions_duplicate_total,
+            ),
+            (
+                "import_errors_total",
+                "Statements which failed to import",
+                &self.import_errors_total,
+            ),
+        ];
+        let mut out = String::new();
+        for (name, help, counter) in counters {
+            let value = counter.load(Ordering::Relaxed);
+            out += &format!(
+                "# HELP {0} {1}\n# TYPE {0} counter\n{0} {2}\n",
+                name, help, value
+            );
+        }
+        let last_import = self.last_import_timestamp.load(Ordering::Relaxed);
+        out += &format!(
+            "# HELP last_import_timestamp Unix time of the last successful import\n\
+            # TYPE last_import_timestamp gauge\n\
+            last_import_timestamp {}\n",
+            last_import
+        );
+        out
+    }
+}
+
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+fn db_reachable() -> bool {
+    get_sqlite_conn()
+        .and_then(|conn| Ok(conn.query_row("SELECT 1", [], |row| row.get::<_, i64>(0))?))
+        .is_ok()
+}
+
+fn health(metrics: &Metrics) -> Response<Cursor<Vec<u8>>> {
+    if !metrics.watching.load(Ordering::Relaxed) {
+        Response::from_string("watcher is not running").with_status_code(503)
+    } else if !db_reachable() {
+        Response::from_string("database is unreachable").with_status_code(503)
+    } else {
+        Response::from_string("ok")
+    }
+}
+
+// Serves /healthz and /metrics on addr from a background thread, which runs until the process
+// exits. Fails straight away if addr can't be bound.
+pub fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> Result<()> {
+    let server =
+        Server::http(addr).map_err(|err| anyhow!("failed to listen on {}: {}", addr, err))?;
+    info!(%addr, "Serving health checks and metrics");
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let path = request.url().split('?').next().unwrap_or_default();
+            let response = match (request.method(), path) {
+                (Method::Get, "/healthz") => health(&metrics),
+                (Method::Get, "/metrics") => Response::from_string(metrics.render()).with_header(
+                    Header::from_bytes("Content-Type", PROMETHEUS_CONTENT_TYPE)
+                        .expect("Invalid header"),
+                ),
+                _ => Response::from_string("not found").with_status_code(404),
+            };
+            if let Err(err) = request.respond(response) {
+                warn!("Failed to respond to HTTP request: {:?}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = Metrics::default();
+        assert!(metrics.render().ends_with("\nlast_import_timestamp 0\n"));
+
+        metrics.record_import(3, 1);
+        metrics.record_import(2, 0);
+        metrics.record_error();
+        let rendered = metrics.render();
+        for line in [
+            "# TYPE files_processed_total counter",
+            "files_processed_total 2",
+            "transactions_created_total 5",
+            "transactions_duplicate_total 1",
+            "import_errors_total 1",
+            "# TYPE last_import_timestamp gauge",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {}", line);
+        }
+        assert!(!rendered.contains("last_import_timestamp 0\n"));
+    }
+}
@@ -0,0 +1,54 @@
+use anyhow::{anyhow, Result};
+use std::env::current_exe;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+// Held for the lifetime of the service. The OS drops the lock when the file is closed, including
+// when the process dies without shutting down cleanly, so a crash never leaves a stale lock behind.
+pub struct InstanceLock {
+    _file: File,
+}
+
+pub fn service_lock_path() -> Result<PathBuf> {
+    let mut pb = current_exe()?;
+    pb.pop();
+    pb.push("service.lock");
+    Ok(pb)
+}
+
+// Takes an exclusive lock on the given file, failing straight away if another process holds it
+pub fn acquire(path: &Path) -> Result<InstanceLock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(InstanceLock { _file: file }),
+        Err(TryLockError::WouldBlock) => Err(anyhow!(
+            "ynab-importer service is already running (lock held on {})",
+            path.display()
+        )),
+        Err(TryLockError::Error(err)) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+    use std::fs;
+
+    #[test]
+    fn test_second_acquire_fails_until_released() {
+        let path = temp_dir().join(format!("ynab-importer-lock-{}", std::process::id()));
+
+        let lock = acquire(&path).unwrap();
+        let err = acquire(&path).err().unwrap();
+        assert!(err.to_string().contains("already running"));
+
+        drop(lock);
+        assert!(acquire(&path).is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+}
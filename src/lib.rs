@@ -1,6 +1,19 @@
+pub mod auth;
+pub mod client;
 pub mod db;
 pub mod error;
 pub mod event;
+pub mod export;
+pub mod health;
+pub mod instance;
+pub mod logging;
+pub mod notification;
 pub mod ofx;
+pub mod prune;
+pub mod reconcile;
+pub mod retry;
+pub mod rules;
+pub mod scan;
 pub mod setup;
 pub mod ui;
+pub mod webhook;
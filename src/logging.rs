@@ -0,0 +1,73 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::fs;
+use std::path::Path;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+// Number of daily log files kept by init_with_file
+const MAX_LOG_FILES: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Trace => LevelFilter::TRACE,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Error => LevelFilter::ERROR,
+        }
+    }
+}
+
+// An explicit --log-level replaces RUST_LOG entirely, otherwise RUST_LOG is used if set and
+// everything at info and above is logged.
+fn env_filter(level: Option<LogLevel>) -> EnvFilter {
+    match level {
+        Some(level) => EnvFilter::default().add_directive(LevelFilter::from(level).into()),
+        None => EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy(),
+    }
+}
+
+// Sets up the global tracing subscriber, logging to stderr so that stdout only has the command's
+// output (e.g. with --format json)
+pub fn init(level: Option<LogLevel>) {
+    tracing_subscriber::fmt()
+        .with_env_filter(env_filter(level))
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+// Like init, but also writes to service.log in log_dir, rotated daily with the last 7 days kept.
+// Lines are written from a background thread, so the returned guard has to be held until exit or
+// the last ones may be lost.
+pub fn init_with_file(level: Option<LogLevel>, log_dir: &Path) -> Result<WorkerGuard> {
+    fs::create_dir_all(log_dir)?;
+    let appender = RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("service")
+        .filename_suffix("log")
+        .max_log_files(MAX_LOG_FILES)
+        .build(log_dir)?;
+    let (writer, guard) = tracing_appender::non_blocking(appender);
+
+    tracing_subscriber::registry()
+        .with(env_filter(level))
+        .with(fmt::layer())
+        .with(fmt::layer().with_ansi(false).with_writer(writer))
+        .init();
+    Ok(guard)
+}
@@ -0,0 +1,139 @@
+// Release builds on Windows don't open a console window, which the GUI would otherwise come with.
+// The command line commands write to the console they're run from instead, see attach_console.
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use std::env;
+use std::path::PathBuf;
+use tokio::runtime::Runtime;
+use ynab_importer::db;
+use ynab_importer::logging::{self, LogLevel};
+
+mod commands;
+
+use commands::{
+    category_rules, dump_account, entities, export, gui, import_file, link_account, login,
+    payee_rules, prune, service, set_amount_tolerance, set_date_format, set_sign_from_type, setup,
+    status, validate_fixtures,
+};
+
+#[derive(Parser, Debug)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+
+    // Overrides RUST_LOG. Defaults to info when neither is set.
+    #[arg(long, value_enum, global = true)]
+    log_level: Option<LogLevel>,
+
+    // Database file to use. Overrides YNAB_IMPORTER_DB, which in turn overrides the default of
+    // db.sqlite next to the executable.
+    #[arg(long, global = true)]
+    db: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    // Set up the monitored folder for a budget from the command line
+    Setup(setup::Args),
+
+    // Set up the monitored folder from the setup window
+    Gui,
+
+    // Sign in to YNAB through the browser instead of using a personal access token
+    Login(login::Args),
+
+    // Watch the monitored folder and import statements as they appear
+    Service(service::Args),
+
+    // Import a single statement once
+    ImportFile(import_file::Args),
+
+    // Remove accounts which no longer exist in YNAB
+    Prune(prune::Args),
+
+    // Link an account to the ACCTID in its statements
+    LinkAccount(link_account::Args),
+
+    // Set how far apart amounts (and dates) can be and still count as duplicates
+    SetAmountTolerance(set_amount_tolerance::Args),
+
+    // Set the format an account's statement dates are in, for banks which don't follow OFX
+    SetDateFormat(set_date_format::Args),
+
+    // Take the sign of an account's statement amounts from TRNTYPE, for banks which export every
+    // amount as positive
+    SetSignFromType(set_sign_from_type::Args),
+
+    // Manage the rules renaming payees on import
+    PayeeRules(payee_rules::Args),
+
+    // Manage the rules assigning categories to imported transactions by payee
+    CategoryRules(category_rules::Args),
+
+    // Manage the named entities (like &eacute;) expanded in statements besides the built in ones
+    Entities(entities::Args),
+
+    // Print the transactions YNAB has for an account
+    DumpAccount(dump_account::Args),
+
+    // Write every imported transaction to a CSV file
+    Export(export::Args),
+
+    // Check that every sample statement in a folder parses
+    ValidateFixtures(validate_fixtures::Args),
+
+    // Show how many transactions have been imported into each account, and when
+    Status,
+}
+
+// A windows subsystem executable has no console of its own, so output (including --help and
+// argument errors) goes to the one of the terminal it was started from. Started any other way, e.g.
+// the GUI from Explorer, there's none to attach to and output is dropped as before.
+#[cfg(windows)]
+fn attach_console() {
+    use windows_sys::Win32::System::Console::{AttachConsole, ATTACH_PARENT_PROCESS};
+    // SAFETY: only fails (harmlessly) if there's no parent console or one is already attached
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+fn main() -> Result<()> {
+    #[cfg(windows)]
+    attach_console();
+    let cli = Cli::parse();
+    // Set before any threads are started, every connection opened from here on picks it up
+    if let Some(path) = &cli.db {
+        env::set_var(db::DB_PATH_VAR, path);
+    }
+    // The service also logs to a file in a folder stored in the DB, so it sets up logging itself
+    if !matches!(cli.command, Command::Service(_)) {
+        logging::init(cli.log_level);
+    }
+
+    let runtime = Runtime::new()?;
+    match cli.command {
+        Command::Setup(args) => runtime.block_on(setup::run(args)),
+        Command::Gui => {
+            let _guard = runtime.enter();
+            gui::run()
+        }
+        Command::Login(args) => runtime.block_on(login::run(args)),
+        Command::Service(args) => service::run(args, cli.log_level, runtime),
+        Command::ImportFile(args) => runtime.block_on(import_file::run(args)),
+        Command::Prune(args) => runtime.block_on(prune::run(args)),
+        Command::LinkAccount(args) => link_account::run(args),
+        Command::SetAmountTolerance(args) => set_amount_tolerance::run(args),
+        Command::SetDateFormat(args) => set_date_format::run(args),
+        Command::SetSignFromType(args) => set_sign_from_type::run(args),
+        Command::PayeeRules(args) => payee_rules::run(args),
+        Command::CategoryRules(args) => category_rules::run(args),
+        Command::Entities(args) => entities::run(args),
+        Command::DumpAccount(args) => runtime.block_on(dump_account::run(args)),
+        Command::Export(args) => export::run(args),
+        Command::ValidateFixtures(args) => validate_fixtures::run(args),
+        Command::Status => status::run(),
+    }
+}
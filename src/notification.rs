@@ -0,0 +1,47 @@
+use anyhow::Error;
+use notify_rust::Notification;
+use std::path::Path;
+use tracing::debug;
+
+const APP_NAME: &str = "YNAB Importer";
+
+// Shows a desktop notification without waiting on it. Where there's nothing to show it with (an
+// unsupported platform, or no notification server in a headless session) it's only logged at debug.
+fn show(summary: String, body: String) {
+    tokio::task::spawn_blocking(move || {
+        let result = Notification::new()
+            .appname(APP_NAME)
+            .summary(&summary)
+            .body(&body)
+            .show();
+        if let Err(err) = result {
+            debug!("Failed to show notification: {:?}", err);
+        }
+    });
+}
+
+// overlapping: whether the statement covers some of the same dates as one imported before, in
+// which case the transactions already imported are expected
+pub fn import_succeeded(account: &str, created: usize, skipped_existing: usize, overlapping: bool) {
+    let body = match (skipped_existing, overlapping) {
+        (0, _) => String::new(),
+        (n, false) => format!("{} already imported", n),
+        (n, true) => format!(
+            "Overlaps an earlier statement, {}/{} transactions are new",
+            created,
+            created + n
+        ),
+    };
+    show(
+        format!("Imported {} transactions into {}", created, account),
+        body,
+    );
+}
+
+pub fn import_failed(path: &Path, err: &Error) {
+    let name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    show(format!("Failed to import {}", name), format!("{:#}", err));
+}
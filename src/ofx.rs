@@ -1,40 +1,88 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 use super::error::ImportError;
 use anyhow::Result;
 use chrono::{self, NaiveDateTime, ParseResult};
 use chrono::{DateTime, NaiveDate};
+use flate2::read::GzDecoder;
 use regex::{Captures, Regex};
-use serde::{Deserialize, Deserializer, de};
-use sgmlish::{self, SgmlFragment};
+use serde::{Deserialize, Deserializer, de, de::DeserializeOwned};
+use sgmlish::{self, SgmlEvent, SgmlFragment};
+use tracing::warn;
 
-#[derive(Debug, Deserialize)]
-struct Ofx {
-    #[serde(rename = "STMTTRN")]
-    transactions: Vec<OfxTransaction>,
+#[derive(Debug, Default, Deserialize)]
+pub struct Ofx {
+    // Missing from statements without any transactions
+    #[serde(rename = "STMTTRN", default)]
+    pub transactions: Vec<OfxTransaction>,
+
+    // Parsed separately from the LEDGERBAL aggregate, which sits outside BANKTRANLIST
+    #[serde(skip)]
+    pub ledger_balance: Option<LedgerBalance>,
+
+    // The period the statement covers (DTSTART/DTEND) and when the bank generated it (DTSERVER).
+    // None where the file leaves them out or they don't parse, as importing doesn't need them.
+    #[serde(skip)]
+    pub start: Option<NaiveDate>,
+    #[serde(skip)]
+    pub end: Option<NaiveDate>,
+    #[serde(skip)]
+    pub generated: Option<NaiveDate>,
+}
+
+// How to read statements which stray from the OFX spec, set per account and in the config
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    // The account's date format (see account::set_date_format), tried with or without a time
+    // before the standard formats
+    pub date_format: Option<String>,
+
+    // Entities added (or redefined) in the config (see config::get_ofx_entities), by name without
+    // the & and ;. They take precedence over the built in ones.
+    pub entities: BTreeMap<String, String>,
 }
 
 fn parse_date(s: &str) -> ParseResult<NaiveDate> {
+    parse_timestamp(s).map(|(date, _)| date)
+}
+
+// The date along with the full timestamp, if s has a time of day. A time with an offset is kept as
+// the local time it was given in, which is also what the date is taken from.
+fn parse_timestamp(s: &str) -> ParseResult<(NaiveDate, Option<NaiveDateTime>)> {
     let re = Regex::new(r"(\.\d+)?\[([\+-])(\d+):[a-zA-Z]+\]").unwrap();
     let s = re
         .replace(s, |caps: &Captures| format!("{}{:0>2}", &caps[2], &caps[3]))
         .to_string();
 
     DateTime::parse_from_str(&s, r"%Y%m%d%H%M%S%#z")
-        .map(|dt| dt.date_naive())
-        .or_else(|_| NaiveDateTime::parse_from_str(&s, r"%Y%m%d%H%M%S%.3f").map(|dt| dt.date()))
+        .map(|dt| dt.naive_local())
+        .or_else(|_| NaiveDateTime::parse_from_str(&s, r"%Y%m%d%H%M%S%.3f"))
+        .map(|dt| (dt.date(), Some(dt)))
+        .or_else(|_| NaiveDate::parse_from_str(&s, r"%Y%m%d").map(|date| (date, None)))
 }
 
 fn deserialize_datetime<'de, D>(deserializer: D) -> Result<NaiveDate, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_timestamp(deserializer).map(|(date, _)| date)
+}
+
+fn deserialize_timestamp<'de, D>(
+    deserializer: D,
+) -> Result<(NaiveDate, Option<NaiveDateTime>), D::Error>
 where
     D: Deserializer<'de>,
 {
     struct YMDStringVisitor;
 
     impl<'de> de::Visitor<'de> for YMDStringVisitor {
-        type Value = NaiveDate;
+        type Value = (NaiveDate, Option<NaiveDateTime>);
 
         fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
             formatter.write_str("a datetime string in the format %Y%m%d%H%M%S%.3f")
@@ -44,14 +92,14 @@ where
         where
             E: de::Error,
         {
-            parse_date(v).map_err(|_| E::custom(format!("Failed to parse datetime: {}", v)))
+            parse_timestamp(v).map_err(|_| E::custom(format!("Failed to parse datetime: {}", v)))
         }
     }
 
     deserializer.deserialize_str(YMDStringVisitor)
 }
 
-#[derive(Debug, Deserialize, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum TransactionKind {
     DEBIT = 1,
     CREDIT = 2,
@@ -73,22 +121,261 @@ pub enum TransactionKind {
     OTHER = 18,
 }
 
+impl TransactionKind {
+    // Maps a TRNTYPE value to its kind. Anything not in the OFX spec (vendor-specific types) is
+    // treated as OTHER rather than failing the whole file.
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_uppercase().as_str() {
+            "DEBIT" => Self::DEBIT,
+            "CREDIT" => Self::CREDIT,
+            "ATM" => Self::ATM,
+            "INT" => Self::INT,
+            "AMOUNT" => Self::AMOUNT,
+            "DIV" => Self::DIV,
+            "FEE" => Self::FEE,
+            "SRVCHG" => Self::SRVCHG,
+            "DEP" => Self::DEP,
+            "POS" => Self::POS,
+            "XFER" => Self::XFER,
+            "CHECK" => Self::CHECK,
+            "PAYMENT" => Self::PAYMENT,
+            "CASH" => Self::CASH,
+            "DIRECTDEP" => Self::DIRECTDEP,
+            "REPEATPMT" => Self::REPEATPMT,
+            "HOLD" => Self::HOLD,
+            _ => Self::OTHER,
+        }
+    }
+
+    // The direction money moves for this kind, -1 out of the account and 1 into it. None for kinds
+    // which can go either way (XFER, OTHER etc.).
+    pub fn sign(&self) -> Option<i64> {
+        match self {
+            Self::DEBIT
+            | Self::FEE
+            | Self::SRVCHG
+            | Self::ATM
+            | Self::POS
+            | Self::CHECK
+            | Self::PAYMENT => Some(-1),
+            Self::CREDIT | Self::DEP | Self::INT | Self::DIV | Self::DIRECTDEP => Some(1),
+            _ => None,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionKind {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TransactionKindVisitor;
+
+        impl de::Visitor<'_> for TransactionKindVisitor {
+            type Value = TransactionKind;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("a TRNTYPE string")
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Ok(TransactionKind::from_name(v))
+            }
+        }
+
+        deserializer.deserialize_str(TransactionKindVisitor)
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
+#[serde(from = "RawOfxTransaction")]
 pub struct OfxTransaction {
-    #[serde(rename = "TRNTYPE")]
     pub transaction_kind: TransactionKind,
-
-    #[serde(rename = "DTPOSTED", deserialize_with = "deserialize_datetime")]
     pub date_posted: NaiveDate,
+    // The full DTPOSTED, None where it only has a date. YNAB only takes the date, but the time
+    // tells apart transactions of the same amount on the same day.
+    pub datetime: Option<NaiveDateTime>,
 
-    #[serde(rename = "TRNAMT")]
+    // Only for display, amount_milli is parsed straight from the text and is what gets imported
     pub amount: f64,
+    pub amount_milli: i64,
 
-    #[serde(rename = "NAME")]
     pub name: Option<String>,
+    pub memo: Option<String>,
+    pub check_number: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawOfxTransaction {
+    #[serde(rename = "TRNTYPE")]
+    transaction_kind: TransactionKind,
+
+    #[serde(rename = "DTPOSTED", deserialize_with = "deserialize_timestamp")]
+    posted: (NaiveDate, Option<NaiveDateTime>),
+
+    #[serde(rename = "TRNAMT", deserialize_with = "deserialize_amount")]
+    amount: (f64, i64),
+
+    #[serde(rename = "NAME")]
+    name: Option<String>,
 
     #[serde(rename = "MEMO")]
-    pub memo: Option<String>,
+    memo: Option<String>,
+
+    #[serde(rename = "CHECKNUM")]
+    check_number: Option<String>,
+}
+
+impl From<RawOfxTransaction> for OfxTransaction {
+    fn from(raw: RawOfxTransaction) -> Self {
+        let (amount, amount_milli) = raw.amount;
+        let (date_posted, datetime) = raw.posted;
+        OfxTransaction {
+            transaction_kind: raw.transaction_kind,
+            date_posted,
+            datetime,
+            amount,
+            amount_milli,
+            name: raw.name,
+            memo: raw.memo,
+            check_number: raw.check_number,
+        }
+    }
+}
+
+impl OfxTransaction {
+    // For banks which export every TRNAMT as a positive number, takes the sign from TRNTYPE
+    // instead. Amounts of kinds without a fixed direction are left as they are.
+    pub fn with_sign_from_kind(self) -> Self {
+        match self.transaction_kind.sign() {
+            Some(sign) => OfxTransaction {
+                amount: self.amount.abs() * sign as f64,
+                amount_milli: self.amount_milli.abs() * sign,
+                ..self
+            },
+            None => self,
+        }
+    }
+}
+
+// Converts a decimal amount string like "-7.35" into integer milliunits without going through a
+// float, rounding half away from zero past the third decimal place
+fn parse_milliunits(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut milli = if whole.is_empty() {
+        0
+    } else {
+        whole.parse::<i64>().ok()?.checked_mul(1000)?
+    };
+    for (i, c) in fraction.chars().take(3).enumerate() {
+        let digit = c.to_digit(10)? as i64;
+        milli += digit * 10_i64.pow(2 - i as u32);
+    }
+    if fraction.chars().nth(3).is_some_and(|c| c >= '5') {
+        milli += 1;
+    }
+
+    Some(if negative { -milli } else { milli })
+}
+
+// Deserializes an amount as both the float value and exact milliunits
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<(f64, i64), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let invalid = || <D::Error as de::Error>::custom(format!("invalid amount '{}'", s));
+    let amount = s.trim().parse::<f64>().map_err(|_| invalid())?;
+    let amount_milli = parse_milliunits(&s).ok_or_else(invalid)?;
+    Ok((amount, amount_milli))
+}
+
+// Deserializes an amount as exact milliunits
+fn deserialize_milliunits<'de, D>(deserializer: D) -> Result<i64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    parse_milliunits(&s)
+        .ok_or_else(|| <D::Error as de::Error>::custom(format!("invalid amount '{}'", s)))
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct LedgerBalance {
+    #[serde(rename = "BALAMT", deserialize_with = "deserialize_milliunits")]
+    pub amount_milli: i64,
+
+    #[serde(rename = "DTASOF", deserialize_with = "deserialize_datetime")]
+    pub as_of: NaiveDate,
+}
+
+// Investment transaction aggregates which nest their details in INVBUY/INVSELL
+const INVESTMENT_BUY_SELL_TAGS: [&str; 10] = [
+    "BUYDEBT",
+    "BUYMF",
+    "BUYOPT",
+    "BUYOTHER",
+    "BUYSTOCK",
+    "SELLDEBT",
+    "SELLMF",
+    "SELLOPT",
+    "SELLOTHER",
+    "SELLSTOCK",
+];
+
+// Investment transaction aggregates holding INVTRAN/SECID/TOTAL directly. REINVEST is left out as
+// it doesn't move any cash.
+const INVESTMENT_INCOME_TAGS: [&str; 2] = ["INCOME", "RETOFCAP"];
+
+#[derive(Debug, Deserialize)]
+struct InvTran {
+    #[serde(rename = "DTTRADE", deserialize_with = "deserialize_datetime")]
+    date_trade: NaiveDate,
+
+    #[serde(rename = "MEMO")]
+    memo: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SecId {
+    #[serde(rename = "UNIQUEID")]
+    unique_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct InvestmentDetails {
+    #[serde(rename = "INVTRAN")]
+    inv_tran: InvTran,
+
+    #[serde(rename = "SECID")]
+    sec_id: Option<SecId>,
+
+    #[serde(rename = "TOTAL", deserialize_with = "deserialize_amount")]
+    total: (f64, i64),
+}
+
+#[derive(Debug, Deserialize)]
+struct SecInfo {
+    #[serde(rename = "SECID")]
+    sec_id: SecId,
+
+    #[serde(rename = "SECNAME")]
+    name: String,
 }
 
 fn get_ofx_block(file_contents: &str) -> Option<&str> {
@@ -97,14 +384,121 @@ fn get_ofx_block(file_contents: &str) -> Option<&str> {
     Some(&file_contents[m.start()..])
 }
 
-fn replace_ampersands(text: &str) -> String {
-    let amp_re = Regex::new("&[a-z]*;?").unwrap();
+// Named entities expanded before parsing, on top of the XML ones sgmlish expands. Mostly accented
+// letters, which some banks write this way in payee names.
+const HTML_ENTITIES: [(&str, &str); 79] = [
+    ("agrave", "à"),
+    ("aacute", "á"),
+    ("acirc", "â"),
+    ("atilde", "ã"),
+    ("auml", "ä"),
+    ("aring", "å"),
+    ("aelig", "æ"),
+    ("ccedil", "ç"),
+    ("egrave", "è"),
+    ("eacute", "é"),
+    ("ecirc", "ê"),
+    ("euml", "ë"),
+    ("igrave", "ì"),
+    ("iacute", "í"),
+    ("icirc", "î"),
+    ("iuml", "ï"),
+    ("ntilde", "ñ"),
+    ("ograve", "ò"),
+    ("oacute", "ó"),
+    ("ocirc", "ô"),
+    ("otilde", "õ"),
+    ("ouml", "ö"),
+    ("oslash", "ø"),
+    ("ugrave", "ù"),
+    ("uacute", "ú"),
+    ("ucirc", "û"),
+    ("uuml", "ü"),
+    ("yacute", "ý"),
+    ("yuml", "ÿ"),
+    ("Agrave", "À"),
+    ("Aacute", "Á"),
+    ("Acirc", "Â"),
+    ("Atilde", "Ã"),
+    ("Auml", "Ä"),
+    ("Aring", "Å"),
+    ("AElig", "Æ"),
+    ("Ccedil", "Ç"),
+    ("Egrave", "È"),
+    ("Eacute", "É"),
+    ("Ecirc", "Ê"),
+    ("Euml", "Ë"),
+    ("Igrave", "Ì"),
+    ("Iacute", "Í"),
+    ("Icirc", "Î"),
+    ("Iuml", "Ï"),
+    ("Ntilde", "Ñ"),
+    ("Ograve", "Ò"),
+    ("Oacute", "Ó"),
+    ("Ocirc", "Ô"),
+    ("Otilde", "Õ"),
+    ("Ouml", "Ö"),
+    ("Oslash", "Ø"),
+    ("Ugrave", "Ù"),
+    ("Uacute", "Ú"),
+    ("Ucirc", "Û"),
+    ("Uuml", "Ü"),
+    ("Yacute", "Ý"),
+    ("Yuml", "Ÿ"),
+    ("szlig", "ß"),
+    ("apos", "'"),
+    ("copy", "©"),
+    ("reg", "®"),
+    ("trade", "™"),
+    ("deg", "°"),
+    ("ndash", "–"),
+    ("mdash", "—"),
+    ("lsquo", "‘"),
+    ("rsquo", "’"),
+    ("ldquo", "“"),
+    ("rdquo", "”"),
+    ("hellip", "…"),
+    ("euro", "€"),
+    ("pound", "£"),
+    ("cent", "¢"),
+    ("yen", "¥"),
+    ("sect", "§"),
+    ("middot", "·"),
+    ("laquo", "«"),
+    ("raquo", "»"),
+];
+
+// The text a named entity stands for, unless it's left to sgmlish or unknown. entities are the
+// extra ones from ParseOptions.
+fn expand_entity(name: &str, entities: &BTreeMap<String, String>) -> Option<String> {
+    if let Some(text) = entities.get(name) {
+        return Some(text.clone());
+    }
+    HTML_ENTITIES
+        .iter()
+        .find(|(entity, _)| *entity == name)
+        .map(|(_, text)| text.to_string())
+}
+
+// Escapes bare ampersands and expands the named entities in HTML_ENTITIES (or the config), leaving
+// the XML ones and numeric character references (decimal and hex) intact
+fn replace_ampersands(text: &str, entities: &BTreeMap<String, String>) -> String {
+    let amp_re = Regex::new("&(#[0-9]+;|#[xX][0-9a-fA-F]+;|[a-zA-Z0-9]*;?)").unwrap();
     let mut res = String::new();
     let mut pos = 0;
     for m in amp_re.find_iter(text) {
         write!(&mut res, "{}", &text[pos..m.start()]).unwrap();
 
-        if ["&amp;", "&lt;", "&gt;", "&quot;", "&nbsp;"].contains(&m.as_str()) {
+        let expanded = m.as_str()[1..]
+            .strip_suffix(';')
+            .and_then(|name| expand_entity(name, entities));
+        if let Some(expanded) = expanded {
+            // In case a configured entity stands for markup characters
+            let escaped = expanded.replace('&', "&amp;").replace('<', "&lt;");
+            write!(&mut res, "{}", escaped).unwrap();
+        } else if m.as_str().starts_with("&#")
+            || ["&amp;", "&lt;", "&gt;", "&quot;", "&nbsp;"].contains(&m.as_str())
+        {
             write!(&mut res, "{}", &text[m.start()..m.end()]).unwrap();
         } else {
             write!(&mut res, "&amp;{}", &text[m.start() + 1..m.end()]).unwrap();
@@ -115,17 +509,274 @@ fn replace_ampersands(text: &str) -> String {
     res
 }
 
-fn preprocess_text(file_contents: &str) -> Option<String> {
+fn preprocess_text(file_contents: &str, entities: &BTreeMap<String, String>) -> Option<String> {
     let ofx_block = get_ofx_block(file_contents);
     if let Some(text) = ofx_block {
-        Some(replace_ampersands(text))
+        Some(replace_ampersands(text, entities))
     } else {
         None
     }
 }
 
-fn parse(file_contents: &str) -> Result<Vec<OfxTransaction>, sgmlish::Error> {
-    let xml = preprocess_text(file_contents).unwrap();
+// Collects the events making up each <tag>...</tag> element in the document
+fn extract_elements<'a, 'b>(
+    events: impl Iterator<Item = &'b SgmlEvent<'a>>,
+    tag: &str,
+) -> Vec<Vec<SgmlEvent<'a>>>
+where
+    'a: 'b,
+{
+    let mut elements = Vec::new();
+    let mut current: Option<Vec<SgmlEvent<'a>>> = None;
+
+    for event in events {
+        match event {
+            SgmlEvent::OpenStartTag { name } if name.to_uppercase() == tag => {
+                current = Some(Vec::new());
+            }
+            SgmlEvent::EndTag { name } if name.to_uppercase() == tag => {
+                if let Some(mut element) = current.take() {
+                    element.push(event.clone());
+                    elements.push(element);
+                }
+                continue;
+            }
+            _ => (),
+        }
+        if let Some(element) = current.as_mut() {
+            element.push(event.clone());
+        }
+    }
+    elements
+}
+
+// Collects the events making up the first <tag>...</tag> element in the document
+fn extract_element<'a, 'b>(
+    events: impl Iterator<Item = &'b SgmlEvent<'a>>,
+    tag: &str,
+) -> Vec<SgmlEvent<'a>>
+where
+    'a: 'b,
+{
+    extract_elements(events, tag)
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+}
+
+fn deserialize_element<T: DeserializeOwned>(events: Vec<SgmlEvent>) -> Result<T, sgmlish::Error> {
+    let fragment = sgmlish::transforms::normalize_end_tags(SgmlFragment::from(events))
+        .map_err(sgmlish::Error::from)?;
+    sgmlish::from_fragment::<T>(fragment).map_err(sgmlish::Error::from)
+}
+
+fn is_investment_statement(sgml: &SgmlFragment) -> bool {
+    sgml.iter().any(|event| match event {
+        SgmlEvent::OpenStartTag { name } => name.to_uppercase() == "INVSTMTMSGSRSV1",
+        _ => false,
+    })
+}
+
+// Converts the trades, income and cash movements of an investment statement into transactions,
+// using the security name from SECLIST as the payee where available.
+fn parse_investments(sgml: &SgmlFragment) -> Result<Vec<OfxTransaction>, sgmlish::Error> {
+    let mut security_names = HashMap::new();
+    for element in extract_elements(sgml.iter(), "SECINFO") {
+        let info: SecInfo = deserialize_element(element)?;
+        security_names.insert(info.sec_id.unique_id, info.name);
+    }
+
+    let tran_list = extract_element(sgml.iter(), "INVTRANLIST");
+    let mut transactions = Vec::new();
+
+    for tag in INVESTMENT_BUY_SELL_TAGS
+        .iter()
+        .chain(INVESTMENT_INCOME_TAGS.iter())
+    {
+        for element in extract_elements(tran_list.iter(), tag) {
+            let mut details = extract_element(element.iter(), "INVBUY");
+            if details.is_empty() {
+                details = extract_element(element.iter(), "INVSELL");
+            }
+            if details.is_empty() {
+                details = element;
+            }
+            let details: InvestmentDetails = deserialize_element(details)?;
+            let (amount, amount_milli) = details.total;
+            let security = details
+                .sec_id
+                .and_then(|id| security_names.get(&id.unique_id).cloned());
+
+            transactions.push(OfxTransaction {
+                transaction_kind: if *tag == "INCOME" {
+                    TransactionKind::DIV
+                } else {
+                    TransactionKind::OTHER
+                },
+                date_posted: details.inv_tran.date_trade,
+                datetime: None,
+                amount,
+                amount_milli,
+                name: security.or(details.inv_tran.memo.clone()),
+                memo: details.inv_tran.memo,
+                check_number: None,
+            });
+        }
+    }
+
+    // Cash movements (INVBANKTRAN) wrap a regular STMTTRN
+    for element in extract_elements(tran_list.iter(), "INVBANKTRAN") {
+        for stmttrn in extract_elements(element.iter(), "STMTTRN") {
+            transactions.push(deserialize_element(stmttrn)?);
+        }
+    }
+
+    transactions.sort_by_key(|t| t.date_posted);
+    Ok(transactions)
+}
+
+// Roughly 80 characters of the file around the given byte offset, on a single line
+fn context_around(text: &str, offset: usize) -> String {
+    let mut start = offset.saturating_sub(40);
+    let mut end = (offset + 40).min(text.len());
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    text[start..end].replace(['\n', '\r'], " ")
+}
+
+// Finds the approximate byte offset and name of the tag that failed to parse within the index-th
+// STMTTRN of the original file contents
+fn locate_transaction_error(
+    file_contents: &str,
+    index: usize,
+    options: &ParseOptions,
+) -> (usize, String) {
+    let start = Regex::new(r"(?i)<STMTTRN>")
+        .unwrap()
+        .find_iter(file_contents)
+        .nth(index)
+        .map(|m| m.start())
+        .unwrap_or(0);
+    let end = Regex::new(r"(?i)</STMTTRN>")
+        .unwrap()
+        .find_at(file_contents, start)
+        .map(|m| m.end())
+        .unwrap_or(file_contents.len());
+    let block = &file_contents[start..end];
+
+    let field_value = |tag: &str| {
+        Regex::new(&format!(r"(?i)<{}>\s*([^<]*)", tag))
+            .unwrap()
+            .captures(block)
+            .map(|caps| (caps.get(0).unwrap().start(), caps[1].trim().to_string()))
+    };
+    let checks: [(&str, &dyn Fn(&str) -> bool); 2] = [
+        ("DTPOSTED", &|v| parse_date_with(v, options).is_some()),
+        ("TRNAMT", &|v| parse_milliunits(v).is_some()),
+    ];
+    for (tag, is_valid) in checks {
+        match field_value(tag) {
+            Some((pos, value)) if !is_valid(&value) => return (start + pos, tag.into()),
+            None => return (start, tag.into()),
+            _ => (),
+        }
+    }
+    (start, "STMTTRN".into())
+}
+
+// An error pointing at the index-th STMTTRN of the file (and the field, where possible)
+fn transaction_error(
+    file_contents: &str,
+    index: usize,
+    options: &ParseOptions,
+    err: sgmlish::Error,
+) -> ImportError {
+    let (offset, tag) = locate_transaction_error(file_contents, index, options);
+    ImportError::TransactionParsingError {
+        tag,
+        offset,
+        context: context_around(file_contents, offset),
+        source: err,
+    }
+}
+
+// Turns a deserialization error into one pointing at the transaction (and field, where possible)
+// responsible, by deserializing each STMTTRN on its own until one fails.
+fn describe_error(
+    file_contents: &str,
+    events: &[SgmlEvent],
+    options: &ParseOptions,
+    err: sgmlish::Error,
+) -> ImportError {
+    let failed = extract_elements(events.iter(), "STMTTRN")
+        .into_iter()
+        .position(|element| deserialize_element::<OfxTransaction>(element).is_err());
+
+    match failed {
+        Some(index) => transaction_error(file_contents, index, options, err),
+        None => ImportError::FileParsingError(err),
+    }
+}
+
+// For statements without a BANKTRANLIST, e.g. ones keeping their transactions in some other
+// container: every STMTTRN in the document, wherever it appears
+fn parse_loose_transactions(
+    file_contents: &str,
+    sgml: &SgmlFragment,
+    options: &ParseOptions,
+) -> Result<Vec<OfxTransaction>, ImportError> {
+    extract_elements(sgml.iter(), "STMTTRN")
+        .into_iter()
+        .enumerate()
+        .map(|(index, element)| {
+            deserialize_element(element)
+                .map_err(|err| transaction_error(file_contents, index, options, err))
+        })
+        .collect()
+}
+
+// Errors point into file_contents, which is the file as it was read, before normalize_dates
+fn parse_bank_statement(
+    file_contents: &str,
+    sgml: &SgmlFragment,
+    options: &ParseOptions,
+) -> Result<Ofx, ImportError> {
+    let events = extract_element(sgml.iter(), "BANKTRANLIST");
+    let mut result = if events.is_empty() {
+        Ofx {
+            transactions: parse_loose_transactions(file_contents, sgml, options)?,
+            ..Default::default()
+        }
+    } else {
+        let fragment = sgmlish::transforms::normalize_end_tags(SgmlFragment::from(events.clone()))
+            .map_err(sgmlish::Error::from)?;
+        sgmlish::from_fragment::<Ofx>(fragment)
+            .map_err(|err| describe_error(file_contents, &events, options, err.into()))?
+    };
+
+    // The balance is only used for an advisory check after importing, so one which doesn't parse
+    // is left out rather than failing the whole statement
+    let events = extract_element(sgml.iter(), "LEDGERBAL");
+    if !events.is_empty() {
+        result.ledger_balance = match deserialize_element(events) {
+            Ok(balance) => Some(balance),
+            Err(err) => {
+                warn!("Ignoring LEDGERBAL which failed to parse: {}", err);
+                None
+            }
+        };
+    }
+    Ok(result)
+}
+
+fn parse_ofx(original: &str, options: &ParseOptions) -> Result<Ofx, ImportError> {
+    let file_contents = &normalize_dates(original, options.date_format.as_deref());
+    let xml =
+        preprocess_text(file_contents, &options.entities).ok_or(ImportError::MissingOfxError)?;
     let builder = sgmlish::Parser::builder()
         .uppercase_names()
         .expand_entities(|entity| match entity {
@@ -137,39 +788,150 @@ fn parse(file_contents: &str) -> Result<Vec<OfxTransaction>, sgmlish::Error> {
             _ => None,
         });
 
-    let sgml = builder.parse(&xml)?;
-    let mut events = Vec::new();
-    let mut include = false;
+    let sgml = builder.parse(&xml).map_err(sgmlish::Error::from)?;
 
-    // Search for the BANKTRANLIST tag
-    for event in sgml.iter() {
-        match event {
-            sgmlish::SgmlEvent::OpenStartTag { name } => {
-                if &name.to_uppercase() == "BANKTRANLIST" {
-                    include = true;
-                }
-            }
-            sgmlish::SgmlEvent::EndTag { name } => {
-                if &name.to_uppercase() == "BANKTRANLIST" {
-                    events.push(event.clone());
-                    break;
-                }
-            }
-            _ => (),
-        }
-        if include {
-            events.push(event.clone());
+    let mut result = if is_investment_statement(&sgml) {
+        Ofx {
+            transactions: parse_investments(&sgml)?,
+            ..Default::default()
         }
+    } else {
+        parse_bank_statement(original, &sgml, options)?
+    };
+    result.start = parse_tag_date(file_contents, "DTSTART");
+    result.end = parse_tag_date(file_contents, "DTEND");
+    result.generated = parse_tag_date(file_contents, "DTSERVER");
+    Ok(result)
+}
+
+fn parse(file_contents: &str, options: &ParseOptions) -> Result<Vec<OfxTransaction>, ImportError> {
+    parse_ofx(file_contents, options).map(|ofx| ofx.transactions)
+}
+
+// A date (and time, if it has one) in the given strftime format
+fn parse_custom_timestamp(s: &str, format: &str) -> Option<(NaiveDate, Option<NaiveDateTime>)> {
+    NaiveDateTime::parse_from_str(s, format)
+        .map(|dt| (dt.date(), Some(dt)))
+        .or_else(|_| NaiveDate::parse_from_str(s, format).map(|date| (date, None)))
+        .ok()
+}
+
+// A date value as the parser reads it: in the account's date format if it's in that, otherwise
+// the standard one (see normalize_dates)
+fn parse_date_with(s: &str, options: &ParseOptions) -> Option<(NaiveDate, Option<NaiveDateTime>)> {
+    let format = options.date_format.as_deref();
+    format
+        .and_then(|format| parse_custom_timestamp(s, format))
+        .or_else(|| parse_timestamp(s).ok())
+}
+
+// Rewrites the DT* values which are in the account's date format into the standard one, leaving
+// the rest of the parser to only deal with that. Values in neither are left for it to reject.
+fn normalize_dates<'a>(file_contents: &'a str, date_format: Option<&str>) -> Cow<'a, str> {
+    let Some(format) = date_format else {
+        return Cow::Borrowed(file_contents);
+    };
+    let re = Regex::new(r"(?i)(<DT[A-Z]+>)([^<\r\n]*)").unwrap();
+    let normalize = |caps: &Captures| match parse_custom_timestamp(caps[2].trim(), format) {
+        Some((_, Some(dt))) => format!("{}{}", &caps[1], dt.format("%Y%m%d%H%M%S%.3f")),
+        Some((date, None)) => format!("{}{}", &caps[1], date.format("%Y%m%d")),
+        None => caps[0].to_string(),
+    };
+    re.replace_all(file_contents, normalize)
+}
+
+// Finds the ACCTID from the BANKACCTFROM/CCACCTFROM aggregate, if any
+fn parse_account_id(file_contents: &str) -> Option<String> {
+    let re = Regex::new(r"(?i)<ACCTID>\s*([^<\s]+)").unwrap();
+    re.captures(file_contents).map(|caps| caps[1].to_string())
+}
+
+// The date of the first tag element in the file, e.g. DTSTART of the transaction list
+fn parse_tag_date(file_contents: &str, tag: &str) -> Option<NaiveDate> {
+    let re = Regex::new(&format!(r"(?i)<{}>\s*([^<\s]+)", tag)).unwrap();
+    re.captures(file_contents)
+        .and_then(|caps| parse_date(&caps[1]).ok())
+}
+
+pub fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+// Reads the file to a string, decompressing it first if it has a .gz extension
+fn read_file(path: &Path) -> Result<String> {
+    if !is_gzip(path) {
+        return decode_text(fs::read(path)?);
     }
-    let sgml = sgmlish::transforms::normalize_end_tags(SgmlFragment::from(events))?;
-    let result = sgmlish::from_fragment::<Ofx>(sgml)?;
-    Ok(result.transactions)
+    let mut content = Vec::new();
+    GzDecoder::new(fs::File::open(path)?)
+        .read_to_end(&mut content)
+        .map_err(|err| ImportError::DecompressionError(path.display().to_string(), err))?;
+    decode_text(content)
+}
+
+// Decodes file contents as UTF-8, or as UTF-16 if they start with its byte-order mark. A leading
+// BOM is dropped either way, so it doesn't end up in front of the OFX header. Anything which isn't
+// valid UTF-8 is taken to be Windows-1252 (CHARSET:1252), which most banks still export.
+fn decode_text(bytes: Vec<u8>) -> Result<String> {
+    if let Some(rest) = bytes.strip_prefix(b"\xEF\xBB\xBF") {
+        return Ok(String::from_utf8(rest.to_vec())?);
+    }
+    if let Some(rest) = bytes.strip_prefix(b"\xFF\xFE") {
+        return decode_utf16(rest, u16::from_le_bytes);
+    }
+    if let Some(rest) = bytes.strip_prefix(b"\xFE\xFF") {
+        return decode_utf16(rest, u16::from_be_bytes);
+    }
+    Ok(String::from_utf8(bytes).unwrap_or_else(|err| decode_cp1252(err.as_bytes())))
+}
+
+// Where Windows-1252 differs from Latin-1, which maps every byte to the code point of the same
+// value. The five bytes 1252 leaves undefined are kept as those control characters too.
+const CP1252_HIGH: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+fn decode_cp1252(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            0x80..=0x9F => CP1252_HIGH[(b - 0x80) as usize],
+            _ => b as char,
+        })
+        .collect()
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Result<String> {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|pair| from_bytes([pair[0], pair[1]]))
+        .collect();
+    Ok(String::from_utf16(&units)?)
+}
+
+// Parses the transactions out of OFX content which is already in memory (e.g. an upload), without
+// going through the filesystem. load_transactions does the same for a file.
+pub fn parse_transactions(content: &str, options: &ParseOptions) -> Result<Vec<OfxTransaction>> {
+    Ok(parse(content, options)?)
+}
+
+// As parse_transactions, but also with the statement's ledger balance
+pub fn parse_statement(content: &str, options: &ParseOptions) -> Result<Ofx> {
+    Ok(parse_ofx(content, options)?)
+}
+
+pub fn load_account_id(path: &PathBuf) -> Result<Option<String>> {
+    let content = read_file(path)?;
+    Ok(parse_account_id(&content))
+}
+
+pub fn load_transactions(path: &PathBuf, options: &ParseOptions) -> Result<Vec<OfxTransaction>> {
+    parse_transactions(&read_file(path)?, options)
 }
 
-pub fn load_transactions(path: &PathBuf) -> Result<Vec<OfxTransaction>> {
-    let content = fs::read_to_string(path)?;
-    let ts = parse(&content).map_err(ImportError::from)?;
-    Ok(ts)
+pub fn load_statement(path: &PathBuf, options: &ParseOptions) -> Result<Ofx> {
+    parse_statement(&read_file(path)?, options)
 }
 
 #[cfg(test)]
@@ -179,23 +941,84 @@ mod tests {
     use pretty_assertions::assert_eq;
     use std::fs;
 
+    const NO_ENTITIES: BTreeMap<String, String> = BTreeMap::new();
+    const DEFAULT_OPTIONS: ParseOptions = ParseOptions {
+        date_format: None,
+        entities: BTreeMap::new(),
+    };
+
     #[test]
     fn test_replace_ampersands() {
-        assert_eq!(replace_ampersands("<TAG>A&W</TAG>"), "<TAG>A&amp;W</TAG>");
-        assert_eq!(replace_ampersands("<TAG>A& W</TAG>"), "<TAG>A&amp; W</TAG>");
-        assert_eq!(replace_ampersands("&&&amp;&"), "&amp;&amp;&amp;&amp;");
         assert_eq!(
-            replace_ampersands("<TAG>A&amp;W</TAG>"),
+            replace_ampersands("<TAG>A&W</TAG>", &NO_ENTITIES),
+            "<TAG>A&amp;W</TAG>"
+        );
+        assert_eq!(
+            replace_ampersands("<TAG>A& W</TAG>", &NO_ENTITIES),
+            "<TAG>A&amp; W</TAG>"
+        );
+        assert_eq!(
+            replace_ampersands("&&&amp;&", &NO_ENTITIES),
+            "&amp;&amp;&amp;&amp;"
+        );
+        assert_eq!(
+            replace_ampersands("<TAG>A&amp;W</TAG>", &NO_ENTITIES),
             "<TAG>A&amp;W</TAG>"
         );
-        assert_eq!(replace_ampersands("<TAG>A&"), "<TAG>A&amp;");
+        assert_eq!(replace_ampersands("<TAG>A&", &NO_ENTITIES), "<TAG>A&amp;");
         assert_eq!(
-            replace_ampersands("&SOME TEXT</OFX>"),
+            replace_ampersands("&SOME TEXT</OFX>", &NO_ENTITIES),
             "&amp;SOME TEXT</OFX>"
         );
-        assert_eq!(replace_ampersands("A&lt;B"), "A&lt;B");
-        assert_eq!(replace_ampersands("&gt;B"), "&gt;B");
-        assert_eq!(replace_ampersands("&quot;B&quot;"), "&quot;B&quot;");
+        assert_eq!(replace_ampersands("A&lt;B", &NO_ENTITIES), "A&lt;B");
+        assert_eq!(replace_ampersands("&gt;B", &NO_ENTITIES), "&gt;B");
+        assert_eq!(
+            replace_ampersands("&quot;B&quot;", &NO_ENTITIES),
+            "&quot;B&quot;"
+        );
+    }
+
+    #[test]
+    fn test_expand_entities() {
+        let transactions = parse(
+            "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115<TRNAMT>-4.25\
+            <NAME>CAF&Eacute; L&apos;&Eacute;T&Eacute;\
+            <MEMO>Cr&egrave;me br&ucirc;l&eacute;e &agrave; emporter</STMTTRN>\
+            </BANKTRANLIST></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert_eq!(transactions[0].name, Some("CAFÉ L'ÉTÉ".into()));
+        assert_eq!(transactions[0].memo, Some("Crème brûlée à emporter".into()));
+
+        // Unknown entities are kept as they are
+        assert_eq!(replace_ampersands("&bogus;", &NO_ENTITIES), "&amp;bogus;");
+        let entities = BTreeMap::from([
+            ("bogus".into(), "B&O".into()),
+            ("eacute".into(), "e".into()),
+        ]);
+        assert_eq!(replace_ampersands("&bogus;", &entities), "B&amp;O");
+        assert_eq!(replace_ampersands("&eacute;", &entities), "e");
+    }
+
+    #[test]
+    fn test_replace_ampersands_numeric_references() {
+        assert_eq!(replace_ampersands("TIM&#39;S", &NO_ENTITIES), "TIM&#39;S");
+        assert_eq!(
+            replace_ampersands("TIM&#x2019;S", &NO_ENTITIES),
+            "TIM&#x2019;S"
+        );
+        assert_eq!(
+            replace_ampersands("TIM&#X2019;S", &NO_ENTITIES),
+            "TIM&#X2019;S"
+        );
+        assert_eq!(
+            replace_ampersands("A&W&#39;S & B&#x2019;S&", &NO_ENTITIES),
+            "A&amp;W&#39;S &amp; B&#x2019;S&amp;"
+        );
+        assert_eq!(replace_ampersands("&#39", &NO_ENTITIES), "&amp;#39");
+        assert_eq!(replace_ampersands("&#xZZ;", &NO_ENTITIES), "&amp;#xZZ;");
     }
 
     #[test]
@@ -253,6 +1076,7 @@ mod tests {
             <AVAILBAL><BALAMT>-11692.05<DTASOF>20241120170806.513[-5:EST]</AVAILBAL></STMTRS>\
             </STMTTRNRS></BANKMSGSRSV1></OFX>\
             ",
+            &DEFAULT_OPTIONS,
         );
         println!("{:?}", transactions);
         let transactions = transactions.unwrap();
@@ -261,30 +1085,50 @@ mod tests {
             OfxTransaction {
                 transaction_kind: TransactionKind::DEBIT,
                 date_posted: NaiveDate::from_ymd_opt(2024, 11, 15).unwrap(),
+                datetime: NaiveDate::from_ymd_opt(2024, 11, 15)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0),
                 amount: -0.5,
+                amount_milli: -500,
                 name: Some("PARKING PAY MACHINE".into()),
                 memo: None,
+                check_number: None,
             },
             OfxTransaction {
                 transaction_kind: TransactionKind::DEBIT,
                 date_posted: NaiveDate::from_ymd_opt(2024, 11, 16).unwrap(),
+                datetime: NaiveDate::from_ymd_opt(2024, 11, 16)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0),
                 amount: -7.88,
+                amount_milli: -7880,
                 name: Some("SQ ICECREAM".into()),
                 memo: Some("Rewards earned: 0.04 ~ Category: Other".into()),
+                check_number: None,
             },
             OfxTransaction {
                 transaction_kind: TransactionKind::DEBIT,
                 date_posted: NaiveDate::from_ymd_opt(2024, 11, 16).unwrap(),
+                datetime: NaiveDate::from_ymd_opt(2024, 11, 16)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0),
                 amount: -7.35,
+                amount_milli: -7350,
                 name: Some("PIZZA RESTAURANT".into()),
                 memo: Some("Rewards earned: 0.04 ~ Category: Restaurant".into()),
+                check_number: None,
             },
             OfxTransaction {
                 transaction_kind: TransactionKind::DEBIT,
                 date_posted: NaiveDate::from_ymd_opt(2024, 11, 12).unwrap(),
+                datetime: NaiveDate::from_ymd_opt(2024, 11, 12)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0),
                 amount: -8.91,
+                amount_milli: -8910,
                 name: Some("City Mall".into()),
                 memo: Some("Rewards earned: 0.18 ~ Category: Entertainment".into()),
+                check_number: None,
             }
         ]);
     }
@@ -307,6 +1151,66 @@ mod tests {
             parse_date("20241108120000.000").unwrap(),
             NaiveDate::from_ymd_opt(2024, 11, 8).unwrap()
         );
+        assert_eq!(
+            parse_date("20240105").unwrap(),
+            NaiveDate::from_ymd_opt(2024, 1, 5).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let date = NaiveDate::from_ymd_opt(2021, 12, 17).unwrap();
+        assert_eq!(
+            parse_timestamp("20211217215753.211[-8:PST]").unwrap(),
+            (date, date.and_hms_milli_opt(21, 57, 53, 211))
+        );
+        assert_eq!(
+            parse_timestamp("20211217090000").unwrap(),
+            (date, date.and_hms_opt(9, 0, 0))
+        );
+        assert_eq!(parse_timestamp("20211217").unwrap(), (date, None));
+    }
+
+    #[test]
+    fn test_normalize_dates() {
+        let content = "<DTSTART>17/12/2021<DTPOSTED>17/12/2021 08:30</DTPOSTED>\n\
+            <DTEND>20211218<NAME>17/12/2021";
+        assert_eq!(normalize_dates(content, None), content);
+        // Values in another format, and other elements, are left alone
+        assert_eq!(
+            normalize_dates(content, Some("%d/%m/%Y %H:%M")),
+            "<DTSTART>17/12/2021<DTPOSTED>20211217083000.000</DTPOSTED>\n\
+            <DTEND>20211218<NAME>17/12/2021"
+        );
+        assert_eq!(
+            normalize_dates(content, Some("%d/%m/%Y")),
+            "<DTSTART>20211217<DTPOSTED>17/12/2021 08:30</DTPOSTED>\n\
+            <DTEND>20211218<NAME>17/12/2021"
+        );
+    }
+
+    #[test]
+    fn test_parse_custom_date_format() {
+        let content = "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>2024-11-15<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241116120000<TRNAMT>-7.35<NAME>PIZZA</STMTTRN>\
+            </BANKTRANLIST></OFX>";
+        assert!(parse_ofx(content, &DEFAULT_OPTIONS).is_err());
+
+        // Standard dates still parse alongside the custom format
+        let options = ParseOptions {
+            date_format: Some("%Y-%m-%d".into()),
+            ..Default::default()
+        };
+        let ofx = parse_ofx(content, &options).unwrap();
+        assert_eq!(
+            ofx.transactions[0].date_posted,
+            NaiveDate::from_ymd_opt(2024, 11, 15).unwrap()
+        );
+        assert_eq!(
+            ofx.transactions[1].date_posted,
+            NaiveDate::from_ymd_opt(2024, 11, 16).unwrap()
+        );
     }
 
     #[test]
@@ -336,6 +1240,7 @@ mod tests {
             </BANKTRANLIST><LEDGERBAL><BALAMT>-50.56<DTASOF>20241226044534</LEDGERBAL><AVAILBAL>\
             <BALAMT>9949.44<DTASOF>20241226044534</AVAILBAL></CCSTMTRS></CCSTMTTRNRS>\
             </CREDITCARDMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
         );
         let transactions = match transactions {
             Ok(t) => t,
@@ -349,33 +1254,458 @@ mod tests {
             OfxTransaction {
                 transaction_kind: TransactionKind::DEBIT,
                 date_posted: NaiveDate::from_ymd_opt(2024, 12, 23).unwrap(),
+                datetime: NaiveDate::from_ymd_opt(2024, 12, 23)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0),
                 amount: -6.10,
+                amount_milli: -6100,
                 name: Some("A&W 1473".into()),
                 memo: Some("TOWN NAME;CC#0000********0000".into()),
+                check_number: None,
             },
             OfxTransaction {
                 transaction_kind: TransactionKind::DEBIT,
                 date_posted: NaiveDate::from_ymd_opt(2024, 12, 23).unwrap(),
+                datetime: NaiveDate::from_ymd_opt(2024, 12, 23)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0),
                 amount: -44.46,
+                amount_milli: -44460,
                 name: Some("GAS STATION 123".into()),
                 memo: Some("TOWN NAME;CC#0000********0000".into()),
+                check_number: None,
             },
             OfxTransaction {
                 transaction_kind: TransactionKind::CREDIT,
                 date_posted: NaiveDate::from_ymd_opt(2024, 12, 18).unwrap(),
+                datetime: NaiveDate::from_ymd_opt(2024, 12, 18)
+                    .unwrap()
+                    .and_hms_opt(12, 0, 0),
                 amount: 152.98,
+                amount_milli: 152980,
                 name: Some("PAYMENT THANK YOU/PAIEMEN".into()),
                 memo: Some("CC#0000********0000".into()),
+                check_number: None,
             }
         ]);
     }
 
+    #[test]
+    fn test_parse_milliunits() {
+        assert_eq!(parse_milliunits("-7.35"), Some(-7350));
+        assert_eq!(parse_milliunits("0.3"), Some(300));
+        assert_eq!(parse_milliunits("+1234.567"), Some(1234567));
+        assert_eq!(parse_milliunits("  12 "), Some(12000));
+        assert_eq!(parse_milliunits(".5"), Some(500));
+        assert_eq!(parse_milliunits("1.0005"), Some(1001));
+        assert_eq!(parse_milliunits("-1.0004"), Some(-1000));
+        assert_eq!(parse_milliunits("1,5"), None);
+        assert_eq!(parse_milliunits("-"), None);
+        assert_eq!(parse_milliunits(""), None);
+    }
+
+    #[test]
+    fn test_parse_investments() {
+        let transactions = parse(
+            "<OFX><INVSTMTMSGSRSV1><INVSTMTTRNRS><INVSTMTRS><INVTRANLIST>\
+            <DTSTART>20240101<DTEND>20240131\
+            <BUYSTOCK><INVBUY><INVTRAN><FITID>1<DTTRADE>20240105<MEMO>BUY AAPL</INVTRAN>\
+            <SECID><UNIQUEID>037833100<UNIQUEIDTYPE>CUSIP</SECID><UNITS>10<UNITPRICE>185\
+            <TOTAL>-1850.00<SUBACCTSEC>CASH<SUBACCTFUND>CASH</INVBUY><BUYTYPE>BUY</BUYSTOCK>\
+            <INCOME><INVTRAN><FITID>2<DTTRADE>20240115120000.000<MEMO>DIVIDEND</INVTRAN>\
+            <SECID><UNIQUEID>037833100<UNIQUEIDTYPE>CUSIP</SECID><INCOMETYPE>DIV<TOTAL>2.40\
+            <SUBACCTSEC>CASH<SUBACCTFUND>CASH</INCOME>\
+            <INVBANKTRAN><STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240102<TRNAMT>2000\
+            <NAME>DEPOSIT</STMTTRN><SUBACCTFUND>CASH</INVBANKTRAN>\
+            </INVTRANLIST></INVSTMTRS></INVSTMTTRNRS></INVSTMTMSGSRSV1>\
+            <SECLISTMSGSRSV1><SECLIST><STOCKINFO><SECINFO><SECID><UNIQUEID>037833100\
+            <UNIQUEIDTYPE>CUSIP</SECID><SECNAME>APPLE INC<TICKER>AAPL</SECINFO></STOCKINFO>\
+            </SECLIST></SECLISTMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+
+        assert_eq!(transactions, vec![
+            OfxTransaction {
+                transaction_kind: TransactionKind::CREDIT,
+                date_posted: NaiveDate::from_ymd_opt(2024, 1, 2).unwrap(),
+                datetime: None,
+                amount: 2000.0,
+                amount_milli: 2000000,
+                name: Some("DEPOSIT".into()),
+                memo: None,
+                check_number: None,
+            },
+            OfxTransaction {
+                transaction_kind: TransactionKind::OTHER,
+                date_posted: NaiveDate::from_ymd_opt(2024, 1, 5).unwrap(),
+                datetime: None,
+                amount: -1850.0,
+                amount_milli: -1850000,
+                name: Some("APPLE INC".into()),
+                memo: Some("BUY AAPL".into()),
+                check_number: None,
+            },
+            OfxTransaction {
+                transaction_kind: TransactionKind::DIV,
+                date_posted: NaiveDate::from_ymd_opt(2024, 1, 15).unwrap(),
+                datetime: None,
+                amount: 2.4,
+                amount_milli: 2400,
+                name: Some("APPLE INC".into()),
+                memo: Some("DIVIDEND".into()),
+                check_number: None,
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let text = "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>2024-11-16<TRNAMT>-7.88<NAME>SQ ICECREAM</STMTTRN>\
+            </BANKTRANLIST></OFX>";
+        let err = parse(text, &DEFAULT_OPTIONS).unwrap_err();
+
+        match err {
+            ImportError::TransactionParsingError {
+                tag,
+                offset,
+                context,
+                ..
+            } => {
+                assert_eq!(tag, "DTPOSTED");
+                assert_eq!(offset, text.find("<DTPOSTED>2024-11-16").unwrap());
+                assert!(context.contains("2024-11-16"));
+            }
+            _ => panic!("Unexpected error {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_parse_error_location_with_date_format() {
+        let options = ParseOptions {
+            date_format: Some("%m/%d/%Y %H:%M".into()),
+            ..Default::default()
+        };
+        let location = |text: &str| match parse(text, &options).unwrap_err() {
+            ImportError::TransactionParsingError { tag, offset, .. } => (tag, offset),
+            err => panic!("Unexpected error {:?}", err),
+        };
+
+        // Dates in the account's format are valid, and the offset is into the file as it was read
+        let text = "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>11/15/2024 12:00<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>11/16/2024 12:00<TRNAMT>1e3<NAME>SQ ICECREAM</STMTTRN>\
+            </BANKTRANLIST></OFX>";
+        let offset = text.find("<TRNAMT>1e3").unwrap();
+        assert_eq!(location(text), ("TRNAMT".into(), offset));
+
+        let text = "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>11/15/2024 12:00<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>2024-11-16<TRNAMT>-7.88<NAME>SQ ICECREAM</STMTTRN>\
+            </BANKTRANLIST></OFX>";
+        let offset = text.find("<DTPOSTED>2024-11-16").unwrap();
+        assert_eq!(location(text), ("DTPOSTED".into(), offset));
+    }
+
+    #[test]
+    fn test_parse_check_number() {
+        let transactions = parse(
+            "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>CHECK<DTPOSTED>20241115120000.000<TRNAMT>-120<CHECKNUM>1042\
+            <NAME>CHECK 1042</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241116120000.000<TRNAMT>-7.88<NAME>SQ ICECREAM\
+            </STMTTRN>\
+            </BANKTRANLIST></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+
+        assert_eq!(transactions[0].check_number, Some("1042".into()));
+        assert_eq!(transactions[1].check_number, None);
+    }
+
+    #[test]
+    fn test_parse_ledger_balance() {
+        let ofx = parse_ofx(
+            "<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            </BANKTRANLIST><LEDGERBAL><BALAMT>-276.39<DTASOF>20241120170806.513[-5:EST]</LEDGERBAL>\
+            <AVAILBAL><BALAMT>-11692.05<DTASOF>20241120170806.513[-5:EST]</AVAILBAL></STMTRS>\
+            </STMTTRNRS></BANKMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+
+        assert_eq!(
+            ofx.ledger_balance,
+            Some(LedgerBalance {
+                amount_milli: -276390,
+                as_of: NaiveDate::from_ymd_opt(2024, 11, 20).unwrap(),
+            })
+        );
+
+        let ofx = parse_ofx(
+            "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            </BANKTRANLIST></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert_eq!(ofx.ledger_balance, None);
+
+        // A malformed balance doesn't keep the transactions from being imported
+        let ofx = parse_ofx(
+            "<OFX><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            </BANKTRANLIST><LEDGERBAL><BALAMT>n/a<DTASOF>20241120</LEDGERBAL></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert_eq!(ofx.transactions.len(), 1);
+        assert_eq!(ofx.ledger_balance, None);
+    }
+
+    #[test]
+    fn test_parse_statement_dates() {
+        let ofx = parse_ofx(
+            "<OFX><SIGNONMSGSRSV1><SONRS><DTSERVER>20241120170806.513[-5:EST]</SONRS>\
+            </SIGNONMSGSRSV1><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\
+            <DTSTART>20241102200000.000[-4:EDT]<DTEND>20241120190000.000[-5:EST]\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert_eq!(ofx.start, NaiveDate::from_ymd_opt(2024, 11, 2));
+        assert_eq!(ofx.end, NaiveDate::from_ymd_opt(2024, 11, 20));
+        assert_eq!(ofx.generated, NaiveDate::from_ymd_opt(2024, 11, 20));
+        assert_eq!(ofx.transactions.len(), 1);
+
+        let ofx = parse_ofx(
+            "<OFX><BANKTRANLIST><DTSTART>not a date\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            </BANKTRANLIST></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert_eq!(ofx.start, None);
+        assert_eq!(ofx.end, None);
+        assert_eq!(ofx.generated, None);
+    }
+
+    #[test]
+    fn test_parse_account_id() {
+        assert_eq!(
+            parse_account_id("<BANKACCTFROM><BANKID>1234<ACCTID>1111111111111111<ACCTTYPE>"),
+            Some("1111111111111111".into())
+        );
+        assert_eq!(
+            parse_account_id("<CCACCTFROM><ACCTID>0000000000000000</ACCTID></CCACCTFROM>"),
+            Some("0000000000000000".into())
+        );
+        assert_eq!(parse_account_id("<BANKTRANLIST></BANKTRANLIST>"), None);
+    }
+
+    #[test]
+    fn test_parse_unknown_transaction_kind() {
+        let transactions = parse(
+            "<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>POS<DTPOSTED>20241115120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            <STMTTRN><TRNTYPE>VENDORXYZ<DTPOSTED>20241116120000.000<TRNAMT>-7.88<NAME>SQ ICECREAM\
+            </STMTTRN>\
+            <STMTTRN><TRNTYPE>credit<DTPOSTED>20241116120000.000<TRNAMT>10<NAME>REFUND</STMTTRN>\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+
+        assert_eq!(
+            transactions
+                .iter()
+                .map(|t| &t.transaction_kind)
+                .collect::<Vec<_>>(),
+            vec![
+                &TransactionKind::POS,
+                &TransactionKind::OTHER,
+                &TransactionKind::CREDIT
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_without_banktranlist() {
+        let transactions = parse(
+            "<OFX><CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-7.35<NAME>PIZZA</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241116120000.000<TRNAMT>-0.5<NAME>PARKING</STMTTRN>\
+            <LEDGERBAL><BALAMT>-7.85<DTASOF>20241116120000</LEDGERBAL>\
+            </CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        let names: Vec<_> = transactions.iter().map(|t| t.name.as_deref()).collect();
+        assert_eq!(names, vec![Some("PIZZA"), Some("PARKING")]);
+
+        let err = parse(
+            "<OFX><STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000<TRNAMT>-7.35</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>2024-11-16<TRNAMT>-0.5</STMTTRN></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ImportError::TransactionParsingError { ref tag, .. } if tag == "DTPOSTED"
+        ));
+    }
+
+    #[test]
+    fn test_parse_without_transactions() {
+        let empty_list = parse(
+            "<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\
+            <DTSTART>20241101120000<DTEND>20241130120000\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert!(empty_list.is_empty());
+
+        let no_list = parse(
+            "<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS>\
+            <LEDGERBAL><BALAMT>0.00<DTASOF>20241130120000</LEDGERBAL>\
+            </STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert!(no_list.is_empty());
+    }
+
+    #[test]
+    fn test_parse_transactions_without_ofx() {
+        let err =
+            parse_transactions("OFXHEADER:100\nDATA:OFXSGML\n", &DEFAULT_OPTIONS).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ImportError>(),
+            Some(ImportError::MissingOfxError)
+        ));
+    }
+
+    #[test]
+    fn test_sign_from_kind() {
+        let transactions = parse(
+            "<OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>\
+            <STMTTRN><TRNTYPE>POS<DTPOSTED>20241115120000.000<TRNAMT>0.5<NAME>PARKING</STMTTRN>\
+            <STMTTRN><TRNTYPE>DEP<DTPOSTED>20241116120000.000<TRNAMT>-10<NAME>PAYROLL</STMTTRN>\
+            <STMTTRN><TRNTYPE>XFER<DTPOSTED>20241116120000.000<TRNAMT>7.88<NAME>SAVINGS</STMTTRN>\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>",
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+
+        let amounts: Vec<(f64, i64)> = transactions
+            .into_iter()
+            .map(OfxTransaction::with_sign_from_kind)
+            .map(|t| (t.amount, t.amount_milli))
+            .collect();
+        assert_eq!(amounts, vec![(-0.5, -500), (10.0, 10000), (7.88, 7880)]);
+    }
+
+    // A directory under the system temp dir for one test, removed when it's dropped even if the
+    // test fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let dir = std::env::temp_dir().join(format!(
+                "ynab-importer-ofx-{}-{}",
+                name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_load_gzip() {
+        use flate2::{Compression, write::GzEncoder};
+        use std::io::Write;
+
+        let dir = TestDir::new("gzip");
+        let path = dir.0.join("statement.qfx.gz");
+        let mut encoder = GzEncoder::new(fs::File::create(&path).unwrap(), Compression::default());
+        encoder
+            .write_all(
+                b"<OFX><BANKTRANLIST><STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20241115120000.000\
+                <TRNAMT>-0.5<NAME>PARKING</STMTTRN></BANKTRANLIST></OFX>",
+            )
+            .unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(load_transactions(&path, &DEFAULT_OPTIONS).unwrap().len(), 1);
+
+        let path = dir.0.join("bad.qfx.gz");
+        fs::write(&path, "<OFX></OFX>").unwrap();
+        let err = load_transactions(&path, &DEFAULT_OPTIONS).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ImportError>(),
+            Some(ImportError::DecompressionError(..))
+        ));
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let text = "<OFX><NAME>ÉPICERIE</OFX>";
+        assert_eq!(decode_text(text.as_bytes().to_vec()).unwrap(), text);
+
+        let utf8_bom = [b"\xEF\xBB\xBF", text.as_bytes()].concat();
+        assert_eq!(decode_text(utf8_bom).unwrap(), text);
+
+        let utf16_le: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        assert_eq!(decode_text(utf16_le).unwrap(), text);
+
+        let utf16_be: Vec<u8> = [0xFE, 0xFF]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect();
+        assert_eq!(decode_text(utf16_be).unwrap(), text);
+
+        let cp1252 = b"<NAME>\xC9PICERIE L\x92\xC9T\xC9 \x96 5\x80</NAME>".to_vec();
+        assert_eq!(
+            decode_text(cp1252).unwrap(),
+            "<NAME>ÉPICERIE L’ÉTÉ – 5€</NAME>"
+        );
+    }
+
+    #[test]
+    fn test_read_cp1252_file() {
+        let ofx = parse_ofx(
+            &read_file(Path::new("test_files/cp1252.ofx")).unwrap(),
+            &DEFAULT_OPTIONS,
+        )
+        .unwrap();
+        assert_eq!(
+            ofx.transactions[0].name.as_deref(),
+            Some("ÉPICERIE L’ÉTÉ – CAFÉ")
+        );
+    }
+
     #[test]
     fn parse_test_files() {
         for f in fs::read_dir("test_files").unwrap() {
             let p = f.unwrap().path();
-            let body = fs::read_to_string(&p).unwrap();
-            parse(&body).expect(&format!("Error parsing {}", p.display()));
+            // read_file decodes fixtures with a byte-order mark like real statements
+            let body = read_file(&p).unwrap();
+            parse(&body, &DEFAULT_OPTIONS).expect(&format!("Error parsing {}", p.display()));
         }
     }
 }
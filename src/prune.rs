@@ -0,0 +1,228 @@
+use crate::db::account::{self, AccountRow};
+use crate::db::budget::{self, BudgetRow};
+use anyhow::Result;
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+use ynab_api::apis::{
+    accounts_api::get_accounts, budgets_api::get_budgets, configuration::Configuration,
+};
+use ynab_api::models::BudgetSummary;
+
+// Folder (relative to the transaction dir) that pruned account folders are moved into
+pub const ARCHIVE_DIR_NAME: &str = ".archived";
+
+// An account which is stored locally but no longer exists in YNAB
+pub struct StaleAccount {
+    pub account: AccountRow,
+    pub budget_name: String,
+
+    // Folder names the budget and account were set up with
+    pub budget_dir: String,
+    pub account_dir: String,
+}
+
+// A budget which is stored locally but no longer exists in YNAB
+pub struct StaleBudget {
+    pub budget: BudgetRow,
+
+    // Folder name the budget was set up with
+    pub budget_dir: String,
+}
+
+// Returns the locally stored budgets which are missing from the latest get_budgets response
+pub async fn find_stale_budgets(
+    conn: &Connection,
+    api_config: &Configuration,
+) -> Result<Vec<StaleBudget>> {
+    let response = get_budgets(api_config, None).await?;
+    stale_budgets(conn, &response.data.budgets)
+}
+
+// Returns the locally stored budgets which aren't among budgets, e.g. ones a caller already fetched
+// from get_budgets
+pub fn stale_budgets(conn: &Connection, budgets: &[BudgetSummary]) -> Result<Vec<StaleBudget>> {
+    let live: HashSet<Uuid> = budgets.iter().map(|b| b.id).collect();
+
+    let mut stale = Vec::new();
+    for budget in budget::get_all(conn)? {
+        if !live.contains(&budget.uuid) {
+            stale.push(StaleBudget {
+                budget_dir: budget::dir_name(conn, budget.id)?,
+                budget,
+            });
+        }
+    }
+    Ok(stale)
+}
+
+// Deletes the DB rows for the given budgets, and everything in them, in a single transaction
+pub fn prune_budgets(conn: &mut Connection, stale: &[StaleBudget]) -> Result<()> {
+    let tx = conn.transaction()?;
+    for s in stale {
+        budget::delete(&tx, s.budget.id)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// Moves <transaction_dir>/<budget> into <transaction_dir>/.archived/<budget>. Does nothing if the
+// budget folder was already removed.
+pub fn archive_budget_dir(transaction_dir: &Path, stale: &StaleBudget) -> Result<()> {
+    let src = transaction_dir.join(&stale.budget_dir);
+    if !fs::exists(&src)? {
+        return Ok(());
+    }
+
+    let dest = transaction_dir.join(ARCHIVE_DIR_NAME);
+    fs::create_dir_all(&dest)?;
+    fs::rename(&src, dest.join(&stale.budget_dir))?;
+    Ok(())
+}
+
+// Compares the locally stored accounts against the latest get_accounts response for each budget and
+// returns the ones which are missing (or marked deleted) on the YNAB side. Accounts in
+// stale_budgets are skipped, YNAB can't list the accounts of a deleted budget.
+pub async fn find_stale_accounts(
+    conn: &Connection,
+    api_config: &Configuration,
+    stale_budgets: &[StaleBudget],
+) -> Result<Vec<StaleAccount>> {
+    let skip: HashSet<i64> = stale_budgets.iter().map(|s| s.budget.id).collect();
+    let mut by_budget: HashMap<i64, Vec<AccountRow>> = HashMap::new();
+    for acc in account::get_all(conn)? {
+        if !skip.contains(&acc.budget_id) {
+            by_budget.entry(acc.budget_id).or_default().push(acc);
+        }
+    }
+
+    let mut stale = Vec::new();
+    for (budget_id, accounts) in by_budget {
+        let budget = budget::get(conn, budget_id)?;
+        let response =
+            get_accounts(api_config, &budget.uuid.hyphenated().to_string(), None).await?;
+        let live: HashSet<Uuid> = response
+            .data
+            .accounts
+            .iter()
+            .filter(|a| !a.deleted)
+            .map(|a| a.id)
+            .collect();
+
+        for acc in accounts {
+            if !live.contains(&acc.uuid) {
+                stale.push(StaleAccount {
+                    account_dir: account::dir_name(conn, acc.id)?,
+                    account: acc,
+                    budget_name: budget.name.clone(),
+                    budget_dir: budget::dir_name(conn, budget_id)?,
+                });
+            }
+        }
+    }
+    Ok(stale)
+}
+
+// Deletes the DB rows for the given accounts in a single transaction
+pub fn prune_accounts(conn: &mut Connection, stale: &[StaleAccount]) -> Result<()> {
+    let tx = conn.transaction()?;
+    for s in stale {
+        account::delete(&tx, s.account.id)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// Moves <transaction_dir>/<budget>/<account> into <transaction_dir>/.archived/<budget>/<account>.
+// Does nothing if the account folder was already removed.
+pub fn archive_account_dir(transaction_dir: &Path, stale: &StaleAccount) -> Result<()> {
+    let mut src = transaction_dir.to_path_buf();
+    src.push(&stale.budget_dir);
+    src.push(&stale.account_dir);
+    if !fs::exists(&src)? {
+        return Ok(());
+    }
+
+    let mut dest = transaction_dir.to_path_buf();
+    dest.push(ARCHIVE_DIR_NAME);
+    dest.push(&stale.budget_dir);
+    fs::create_dir_all(&dest)?;
+    dest.push(&stale.account_dir);
+    fs::rename(&src, &dest)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::statement_import::{self, StatementImportRow};
+    use crate::db::test_conn;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_stale_budgets() {
+        let conn = test_conn();
+        let personal = BudgetSummary::new(Uuid::from_u128(1), "Personal".into());
+        let business = BudgetSummary::new(Uuid::from_u128(2), "Business".into());
+        budget::get_or_create(&conn, &personal).unwrap();
+        budget::get_or_create(&conn, &business).unwrap();
+
+        let stale = stale_budgets(&conn, &[personal.clone(), business]).unwrap();
+        assert!(stale.is_empty());
+
+        let stale = stale_budgets(&conn, &[personal]).unwrap();
+        let names: Vec<_> = stale.iter().map(|s| s.budget.name.as_str()).collect();
+        assert_eq!(names, vec!["Business"]);
+        assert_eq!(stale[0].budget_dir, "Business");
+    }
+
+    #[test]
+    fn test_prune_removes_statements() {
+        let mut conn = test_conn();
+        conn.execute_batch(
+            "INSERT INTO budget(uuid, name) VALUES ('00000000-0000-0000-0000-000000000001', 'b');
+            INSERT INTO account(budget_id, uuid, name) VALUES
+                (1, '00000000-0000-0000-0000-000000000002', 'Checking'),
+                (1, '00000000-0000-0000-0000-000000000003', 'Visa');",
+        )
+        .unwrap();
+        for account_id in [1, 2] {
+            statement_import::record(
+                &conn,
+                &StatementImportRow {
+                    account_id,
+                    path: format!("statement-{}.qfx", account_id),
+                    start: NaiveDate::from_ymd_opt(2024, 11, 1).unwrap(),
+                    end: NaiveDate::from_ymd_opt(2024, 11, 30).unwrap(),
+                    imported_at: "2024-12-01T00:00:00+00:00".into(),
+                },
+            )
+            .unwrap();
+        }
+        let statements = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM statement_import", [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+
+        let checking = account::with_uuid(&conn, Uuid::from_u128(2)).unwrap();
+        let stale = StaleAccount {
+            account: checking,
+            budget_name: "b".into(),
+            budget_dir: "b".into(),
+            account_dir: "Checking".into(),
+        };
+        prune_accounts(&mut conn, &[stale]).unwrap();
+        assert_eq!(statements(&conn), 1);
+
+        let stale = StaleBudget {
+            budget: budget::get(&conn, 1).unwrap(),
+            budget_dir: "b".into(),
+        };
+        prune_budgets(&mut conn, &[stale]).unwrap();
+        assert_eq!(statements(&conn), 0);
+    }
+}
@@ -0,0 +1,192 @@
+use crate::db::account::{self, AccountRow};
+use crate::db::budget;
+use crate::db::transaction::{self, TransactionRow};
+use crate::event::EventHandler;
+use anyhow::Result;
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+use tracing::{info, warn};
+use ynab_api::models::{TransactionClearedStatus, TransactionDetail};
+
+// A transaction only one side has, identified the same way imports are deduplicated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gap {
+    pub date: NaiveDate,
+    pub amount_milli: i64,
+}
+
+// The outcome of comparing one account's imported transactions with YNAB's
+pub struct AccountGaps {
+    pub account: AccountRow,
+
+    // Recorded locally, but YNAB has no transaction with the same date and amount, e.g. one deleted
+    // in YNAB after it was imported
+    pub missing_in_ynab: Vec<Gap>,
+
+    // Cleared but not yet reconciled in YNAB, and never recorded locally, e.g. from a statement
+    // which was never imported
+    pub missing_locally: Vec<Gap>,
+}
+
+// Counts of each (date, amount) pair
+fn count<I: IntoIterator<Item = (NaiveDate, i64)>>(keys: I) -> BTreeMap<(NaiveDate, i64), usize> {
+    let mut counts = BTreeMap::new();
+    for key in keys {
+        *counts.entry(key).or_insert(0) += 1;
+    }
+    counts
+}
+
+// The (date, amount) pairs of which `from` has more than `other`, once for each extra one
+fn excess(
+    from: &BTreeMap<(NaiveDate, i64), usize>,
+    other: &BTreeMap<(NaiveDate, i64), usize>,
+) -> Vec<Gap> {
+    let mut gaps = Vec::new();
+    for (&(date, amount_milli), &n) in from {
+        let extra = n.saturating_sub(other.get(&(date, amount_milli)).copied().unwrap_or(0));
+        gaps.extend((0..extra).map(|_| Gap { date, amount_milli }));
+    }
+    gaps
+}
+
+// Compares the transactions from the oldest cleared but unreconciled one in YNAB on. Anything older
+// has been reconciled against the bank already, so isn't checked. Returns the local rows missing
+// from YNAB and the cleared YNAB transactions missing locally, oldest first.
+pub fn find_gaps(
+    local: &[TransactionRow],
+    ynab: &[TransactionDetail],
+) -> Result<(Vec<Gap>, Vec<Gap>)> {
+    let ynab = ynab
+        .iter()
+        .filter(|t| !t.deleted)
+        .map(|t| Ok((NaiveDate::parse_from_str(&t.date, "%Y-%m-%d")?, t)))
+        .collect::<Result<Vec<_>>>()?;
+    let cleared: Vec<(NaiveDate, i64)> = ynab
+        .iter()
+        .filter(|(_, t)| t.cleared == TransactionClearedStatus::Cleared)
+        .map(|(date, t)| (*date, t.amount))
+        .collect();
+    let Some(start) = cleared.iter().map(|(date, _)| *date).min() else {
+        return Ok((Vec::new(), Vec::new()));
+    };
+
+    let remote = count(
+        ynab.iter()
+            .filter(|(date, _)| *date >= start)
+            .map(|(date, t)| (*date, t.amount)),
+    );
+    let local = count(
+        local
+            .iter()
+            .filter(|t| t.date_posted >= start)
+            .map(|t| (t.date_posted, t.amount_milli)),
+    );
+    Ok((excess(&local, &remote), excess(&count(cleared), &local)))
+}
+
+// Audits every account, logging each gap found. Meant to be run now and then by the service to
+// catch imports which went missing on either side.
+pub async fn reconcile_check(handler: &EventHandler) -> Result<Vec<AccountGaps>> {
+    let mut results = Vec::new();
+    for acc in account::get_all(&handler.db_conn)? {
+        let budget = budget::get(&handler.db_conn, acc.budget_id)?;
+        let budget_uuid = budget.uuid.hyphenated().to_string();
+        let account_uuid = acc.uuid.hyphenated().to_string();
+        let response = handler
+            .call_api(|client| {
+                client.get_transactions_by_account(budget_uuid.clone(), account_uuid.clone(), None)
+            })
+            .await?;
+        let local = transaction::list_for_account(&handler.db_conn, acc.id)?;
+        let (missing_in_ynab, missing_locally) = find_gaps(&local, &response.data.transactions)?;
+
+        for gap in missing_in_ynab.iter() {
+            warn!(
+                budget = %budget.name,
+                account = %acc.name,
+                date = %gap.date,
+                amount_milli = gap.amount_milli,
+                "Imported transaction not found in YNAB"
+            );
+        }
+        for gap in missing_locally.iter() {
+            warn!(
+                budget = %budget.name,
+                account = %acc.name,
+                date = %gap.date,
+                amount_milli = gap.amount_milli,
+                "Cleared YNAB transaction was never imported"
+            );
+        }
+        if missing_in_ynab.is_empty() && missing_locally.is_empty() {
+            info!(budget = %budget.name, account = %acc.name, "Imported transactions match YNAB");
+        }
+        results.push(AccountGaps {
+            account: acc,
+            missing_in_ynab,
+            missing_locally,
+        });
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn ynab_transaction(
+        date: &str,
+        amount: i64,
+        cleared: TransactionClearedStatus,
+    ) -> TransactionDetail {
+        TransactionDetail::new(
+            String::new(),
+            date.into(),
+            amount,
+            cleared,
+            true,
+            Uuid::nil(),
+            false,
+            String::new(),
+            Vec::new(),
+        )
+    }
+
+    fn gap(date: &str, amount_milli: i64) -> Gap {
+        Gap {
+            date: NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap(),
+            amount_milli,
+        }
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        use TransactionClearedStatus::*;
+        let ynab = vec![
+            // Reconciled, like everything before it, so the local row from October isn't checked
+            ynab_transaction("2024-11-01", -1000, Reconciled),
+            ynab_transaction("2024-11-05", -7350, Cleared),
+            ynab_transaction("2024-11-06", -500, Uncleared),
+            // Never imported
+            ynab_transaction("2024-11-07", -2500, Cleared),
+        ];
+        let local = [
+            TransactionRow::new(-9990, "2024-10-30".into(), 1).unwrap(),
+            TransactionRow::new(-7350, "2024-11-05".into(), 1).unwrap(),
+            TransactionRow::new(-500, "2024-11-06".into(), 1).unwrap(),
+            // Deleted from YNAB
+            TransactionRow::new(-7350, "2024-11-08".into(), 1).unwrap(),
+        ];
+        let (missing_in_ynab, missing_locally) = find_gaps(&local, &ynab).unwrap();
+        assert_eq!(missing_in_ynab, vec![gap("2024-11-08", -7350)]);
+        assert_eq!(missing_locally, vec![gap("2024-11-07", -2500)]);
+
+        // Nothing to check without cleared transactions
+        let ynab = vec![ynab_transaction("2024-11-05", -7350, Reconciled)];
+        let (missing_in_ynab, missing_locally) = find_gaps(&local, &ynab).unwrap();
+        assert!(missing_in_ynab.is_empty());
+        assert!(missing_locally.is_empty());
+    }
+}
@@ -0,0 +1,174 @@
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+use ynab_api::apis::Error;
+
+const BASE_DELAY: Duration = Duration::from_millis(250);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+// Exponential backoff before the given retry (starting at 1), capped at 30 seconds
+pub fn backoff_delay(retry: usize) -> Duration {
+    let exponent = retry.saturating_sub(1).min(16) as u32;
+    BASE_DELAY
+        .saturating_mul(2_u32.pow(exponent))
+        .min(MAX_DELAY)
+}
+
+// Rate limiting (429), server errors and dropped connections are worth retrying. Anything else,
+// e.g. a bad token or a malformed request, will fail the same way again.
+pub fn is_retryable<T>(err: &Error<T>) -> bool {
+    match err {
+        Error::ResponseError(resp) => resp.status.as_u16() == 429 || resp.status.is_server_error(),
+        Error::Reqwest(err) => err.is_timeout() || err.is_connect(),
+        _ => false,
+    }
+}
+
+// Whether YNAB certainly didn't act on the request, as it was rate limited or never got through.
+// Only these are retried for requests which aren't safe to repeat, like creating transactions:
+// after a timeout or a server error the transactions may well have been created already.
+pub fn is_retryable_create<T>(err: &Error<T>) -> bool {
+    match err {
+        Error::ResponseError(resp) => resp.status.as_u16() == 429,
+        Error::Reqwest(err) => err.is_connect(),
+        _ => false,
+    }
+}
+
+// The access token was missing, expired or revoked
+pub fn is_unauthorized<T>(err: &Error<T>) -> bool {
+    matches!(err, Error::ResponseError(resp) if resp.status.as_u16() == 401)
+}
+
+// Calls the API function until it succeeds, fails with a non-retryable error, or has been retried
+// max_retries times, backing off exponentially in between. The first attempt is made immediately.
+//
+// The generated client drops response headers, so a Retry-After sent with a 429 can't be honoured
+// and the backoff applies regardless.
+pub async fn with_retry<T, E, F, Fut>(max_retries: usize, call: F) -> Result<T, Error<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error<E>>>,
+{
+    retry_if(max_retries, is_retryable, call).await
+}
+
+// As with_retry, but only retrying the errors `retryable` accepts
+pub async fn retry_if<T, E, F, Fut>(
+    max_retries: usize,
+    retryable: fn(&Error<E>) -> bool,
+    mut call: F,
+) -> Result<T, Error<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Error<E>>>,
+{
+    let mut retry = 0;
+    loop {
+        match call().await {
+            Err(err) if retry < max_retries && retryable(&err) => {
+                retry += 1;
+                let delay = backoff_delay(retry);
+                warn!(
+                    retry,
+                    max_retries, "YNAB request failed ({}), retrying in {:?}", err, delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+            res => return res,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use ynab_api::apis::ResponseContent;
+
+    fn response_error(status: u16) -> Error<()> {
+        Error::ResponseError(ResponseContent {
+            status: status.try_into().unwrap(),
+            content: String::new(),
+            entity: None,
+        })
+    }
+
+    #[test]
+    fn test_backoff_delay() {
+        assert_eq!(backoff_delay(1), Duration::from_millis(250));
+        assert_eq!(backoff_delay(2), Duration::from_millis(500));
+        assert_eq!(backoff_delay(5), Duration::from_secs(4));
+        assert_eq!(backoff_delay(8), Duration::from_secs(30));
+        assert_eq!(backoff_delay(1000), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&response_error(429)));
+        assert!(is_retryable(&response_error(503)));
+        assert!(!is_retryable(&response_error(401)));
+        assert!(!is_retryable(&response_error(400)));
+    }
+
+    #[test]
+    fn test_is_retryable_create() {
+        assert!(is_retryable_create(&response_error(429)));
+        assert!(!is_retryable_create(&response_error(500)));
+        assert!(!is_retryable_create(&response_error(503)));
+        assert!(!is_retryable_create(&response_error(400)));
+    }
+
+    #[test]
+    fn test_is_unauthorized() {
+        assert!(is_unauthorized(&response_error(401)));
+        assert!(!is_unauthorized(&response_error(403)));
+        assert!(!is_unauthorized(&response_error(500)));
+    }
+
+    #[tokio::test]
+    async fn test_with_retry() {
+        let calls = Cell::new(0);
+        let result = with_retry(5, || {
+            calls.set(calls.get() + 1);
+            let n = calls.get();
+            async move {
+                if n < 3 {
+                    Err(response_error(429))
+                } else {
+                    Ok(n)
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), 3);
+
+        calls.set(0);
+        let result: Result<(), _> = with_retry(2, || {
+            calls.set(calls.get() + 1);
+            async { Err(response_error(500)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 3);
+
+        calls.set(0);
+        let result: Result<(), _> = with_retry(2, || {
+            calls.set(calls.get() + 1);
+            async { Err(response_error(401)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+
+        // A create which may have gone through isn't repeated
+        calls.set(0);
+        let result: Result<(), _> = retry_if(2, is_retryable_create, || {
+            calls.set(calls.get() + 1);
+            async { Err(response_error(500)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+}
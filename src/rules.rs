@@ -0,0 +1,159 @@
+use crate::db::category_rules::{self, CategoryRuleRow};
+use crate::db::payee_rules::{self, PayeeRuleRow};
+use anyhow::Result;
+use regex::Regex;
+use rusqlite::Connection;
+use uuid::Uuid;
+
+enum Matcher {
+    // Lowercased, matched case-insensitively anywhere in the payee
+    Substring(String),
+    Regex(Regex),
+}
+
+struct PayeeRule {
+    matcher: Matcher,
+    replacement: String,
+}
+
+// The payee rename rules, compiled once per import
+pub struct PayeeRules {
+    rules: Vec<PayeeRule>,
+}
+
+impl PayeeRules {
+    pub fn load(conn: &Connection) -> Result<Self> {
+        Self::new(payee_rules::get_all(conn)?)
+    }
+
+    // Rules are tried in the order given
+    pub fn new(rows: Vec<PayeeRuleRow>) -> Result<Self> {
+        let mut rules = Vec::new();
+        for row in rows {
+            let matcher = if row.is_regex {
+                Matcher::Regex(Regex::new(&row.pattern)?)
+            } else {
+                Matcher::Substring(row.pattern.to_lowercase())
+            };
+            rules.push(PayeeRule {
+                matcher,
+                replacement: row.replacement,
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    // The new name for the payee according to the first matching rule, or None if no rule matches.
+    // A plain rule replaces the whole name. A regex rule does too, but $1, $name etc. in its
+    // replacement are filled in from the match, e.g. "^AMZN MKTP (\w+)" -> "Amazon $1".
+    pub fn rename(&self, payee: &str) -> Option<String> {
+        self.rules.iter().find_map(|rule| match &rule.matcher {
+            Matcher::Substring(pattern) => payee
+                .to_lowercase()
+                .contains(pattern.as_str())
+                .then(|| rule.replacement.clone()),
+            Matcher::Regex(re) => re.captures(payee).map(|caps| {
+                let mut name = String::new();
+                caps.expand(&rule.replacement, &mut name);
+                name
+            }),
+        })
+    }
+}
+
+// The category rules of a budget, each one a lowercased payee pattern and the YNAB category it
+// assigns
+pub struct CategoryRules {
+    rules: Vec<(String, Uuid)>,
+}
+
+impl CategoryRules {
+    pub fn load(conn: &Connection, budget_id: i64) -> Result<Self> {
+        Ok(Self::new(category_rules::get_for_budget(conn, budget_id)?))
+    }
+
+    // Rules are tried in the order given
+    pub fn new(rows: Vec<CategoryRuleRow>) -> Self {
+        let rules = rows
+            .into_iter()
+            .map(|row| (row.payee_pattern.to_lowercase(), row.category_uuid))
+            .collect();
+        Self { rules }
+    }
+
+    // The category of the first rule whose pattern appears in the payee, case-insensitively
+    pub fn category(&self, payee: &str) -> Option<Uuid> {
+        let payee = payee.to_lowercase();
+        self.rules
+            .iter()
+            .find(|(pattern, _)| payee.contains(pattern.as_str()))
+            .map(|(_, category)| *category)
+    }
+}
+
+// Fails on an invalid pattern, so a broken rule is caught when it's added rather than on import
+pub fn validate_pattern(pattern: &str, is_regex: bool) -> Result<()> {
+    if is_regex {
+        Regex::new(pattern)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, replacement: &str, is_regex: bool) -> PayeeRuleRow {
+        PayeeRuleRow {
+            id: 0,
+            pattern: pattern.into(),
+            replacement: replacement.into(),
+            is_regex,
+        }
+    }
+
+    #[test]
+    fn test_rename_payee() {
+        let rules = PayeeRules::new(vec![
+            rule("icecream", "Ice Cream Shop", false),
+            rule(r"^AMZN MKTP (\w+)", "Amazon $1", true),
+            rule("SQ *", "Square", false),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            rules.rename("SQ *ICECREAM 1473"),
+            Some("Ice Cream Shop".into())
+        );
+        assert_eq!(
+            rules.rename("AMZN MKTP CA*2K4 WWW.AMAZON.CA"),
+            Some("Amazon CA".into())
+        );
+        assert_eq!(rules.rename("SQ *PIZZA"), Some("Square".into()));
+        assert_eq!(rules.rename("PARKING PAY MACHINE"), None);
+    }
+
+    #[test]
+    fn test_category_for_payee() {
+        let rule = |payee_pattern: &str, category: u128| CategoryRuleRow {
+            id: 0,
+            payee_pattern: payee_pattern.into(),
+            category_id: 0,
+            category_uuid: Uuid::from_u128(category),
+            category_name: String::new(),
+            budget_id: 1,
+        };
+        let rules = CategoryRules::new(vec![rule("Ice Cream", 1), rule("shop", 2)]);
+
+        assert_eq!(rules.category("ICE CREAM SHOP"), Some(Uuid::from_u128(1)));
+        assert_eq!(rules.category("Coffee Shop"), Some(Uuid::from_u128(2)));
+        assert_eq!(rules.category("PARKING PAY MACHINE"), None);
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(PayeeRules::new(vec![rule("(", "x", true)]).is_err());
+        assert!(validate_pattern("(", true).is_err());
+        assert!(validate_pattern("(", false).is_ok());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::db::config::{self, ScanOrder};
+use crate::event::{is_statement_file, EventHandler};
+use crate::ofx::{load_transactions, ParseOptions};
+use anyhow::Result;
+use chrono::NaiveDate;
+use futures::{stream, StreamExt};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::info;
+
+// Recursively finds files with one of the given extensions under dir, skipping hidden folders (e.g.
+// .archived)
+pub fn find_statement_files(dir: &Path, extensions: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if path.is_dir() && !hidden {
+            files.extend(find_statement_files(&path, extensions)?);
+        } else if path.is_file() && is_statement_file(&path, extensions) {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+// options are used when reading statement dates, see EventHandler::parse_options
+pub fn sort_files(files: &mut [PathBuf], order: ScanOrder, options: &ParseOptions) {
+    match order {
+        ScanOrder::Mtime => files.sort_by_cached_key(|path| {
+            fs::metadata(path)
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH)
+        }),
+        // Files which fail to parse sort first, they will fail again on import and be reported
+        ScanOrder::StatementDate => files.sort_by_cached_key(|path| {
+            load_transactions(path, options)
+                .ok()
+                .and_then(|ts| ts.iter().map(|t| t.date_posted).max())
+                .unwrap_or(NaiveDate::MIN)
+        }),
+    }
+}
+
+// The statements in dir which are waiting to be imported, in the given order
+pub fn existing_statements(
+    handler: &EventHandler,
+    dir: &Path,
+    order: ScanOrder,
+) -> Result<Vec<PathBuf>> {
+    let archive_dir = config::get_archive_dir(&handler.db_conn)?;
+    let mut files = find_statement_files(dir, &handler.extensions)?;
+    files.retain(|path| !path.starts_with(&archive_dir));
+    sort_files(&mut files, order, &handler.parse_options);
+    Ok(files)
+}
+
+// Splits files by the account each is imported into, as given by account, keeping their order
+// within each account and ordering the accounts by their first file
+pub(crate) fn group_by_account<K: PartialEq>(
+    files: &[PathBuf],
+    account: impl Fn(&PathBuf) -> K,
+) -> Vec<Vec<&PathBuf>> {
+    let mut groups: Vec<(K, Vec<&PathBuf>)> = Vec::new();
+    for path in files {
+        let key = account(path);
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, group)) => group.push(path),
+            None => groups.push((key, vec![path])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+// Imports every statement already present in dir, archiving each one that succeeds. An account's
+// statements are imported one after another in the given order, as each is deduplicated against
+// what the ones before it recorded; up to `concurrency` accounts are imported at a time. Progress
+// is reported as each file finishes.
+pub async fn import_existing(
+    handler: &EventHandler,
+    dir: &Path,
+    order: ScanOrder,
+    concurrency: usize,
+) -> Result<()> {
+    let files = existing_statements(handler, dir, order)?;
+    let total = files.len();
+    info!(total, dir = %dir.display(), "Found existing statements");
+
+    // Statements whose account can't be resolved are grouped together, they fail on import anyway
+    let groups = group_by_account(&files, |path| {
+        handler
+            .resolve_account(path)
+            .ok()
+            .map(|(_, account)| account.id)
+    });
+    let mut results = stream::iter(groups)
+        .map(|group| {
+            stream::iter(group).then(|path| async move { (path, handler.import_file(path).await) })
+        })
+        .flatten_unordered(concurrency.max(1));
+
+    let mut done = 0;
+    while let Some((path, result)) = results.next().await {
+        done += 1;
+        // import_file has already logged the outcome, this only tracks progress through the backlog
+        let status = if result.is_ok() { "imported" } else { "failed" };
+        info!(done, total, path = %path.display(), status, "Processed existing statement");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env::temp_dir;
+
+    #[test]
+    fn test_find_statement_files() {
+        let dir = temp_dir().join(format!("ynab-importer-scan-{}", std::process::id()));
+        let account_dir = dir.join("Budget").join("Checking");
+        let archive_dir = dir.join(".archived").join("Budget").join("Savings");
+        fs::create_dir_all(&account_dir).unwrap();
+        fs::create_dir_all(&archive_dir).unwrap();
+        for name in ["a.qfx", "b.OFX", "c.qfx.gz", "d.qbo", "notes.txt"] {
+            fs::write(account_dir.join(name), "").unwrap();
+        }
+        fs::write(archive_dir.join("d.qfx"), "").unwrap();
+
+        let extensions = vec!["qfx".to_string(), "ofx".to_string(), "qbo".to_string()];
+        let mut files = find_statement_files(&dir, &extensions).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                account_dir.join("a.qfx"),
+                account_dir.join("b.OFX"),
+                account_dir.join("c.qfx.gz"),
+                account_dir.join("d.qbo"),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_group_by_account() {
+        let checking = Path::new("Budget").join("Checking");
+        let savings = Path::new("Budget").join("Savings");
+        let files = vec![
+            checking.join("b.qfx"),
+            savings.join("a.qfx"),
+            checking.join("a.qfx"),
+            savings.join("c.qfx"),
+        ];
+        assert_eq!(
+            group_by_account(&files, |path| path.parent().map(Path::to_path_buf)),
+            vec![vec![&files[0], &files[2]], vec![&files[1], &files[3]]]
+        );
+    }
+}
@@ -1,28 +1,117 @@
 use super::db::account;
 use crate::db::account::AccountRow;
+use crate::db::config::FolderDepth;
 use crate::db::transaction::TransactionRow;
-use crate::db::{budget, config, transaction};
+use crate::db::{budget, category, config, sync_state, transaction};
+use crate::error::ImportError;
+use crate::retry::with_retry;
 use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
 use rusqlite::Connection;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::env::{self, VarError};
 use std::fs;
-use std::io;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
+use tracing::{debug, info};
+use uuid::Uuid;
+use ynab_api::apis::categories_api::get_categories;
 use ynab_api::apis::{configuration::Configuration, transactions_api::get_transactions_by_account};
-use ynab_api::models::{Account, BudgetSummary};
+use ynab_api::models::{Account, BudgetSummary, Category};
+
+// Characters which can't appear in a folder name on at least one of the supported platforms
+const RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+// Turns a budget or account name into a single, valid folder name. Separators and reserved
+// characters become '_', and trailing dots and spaces are dropped since Windows strips them.
+pub fn sanitize_component(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| {
+            if RESERVED_CHARS.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let sanitized = sanitized.trim_end_matches(['.', ' ']);
+    match sanitized {
+        "" | "." | ".." => "_".into(),
+        _ => sanitized.into(),
+    }
+}
+
+// The sanitized name, numbered if another budget (or account in the same budget) already has that
+// folder. Names differing only in case count as taken, as they're the same folder on Windows and
+// macOS.
+pub fn unique_dir_name(name: &str, taken: &[String]) -> String {
+    let base = sanitize_component(name);
+    let is_taken = |candidate: &str| {
+        taken
+            .iter()
+            .any(|t| t.to_lowercase() == candidate.to_lowercase())
+    };
+    let mut candidate = base.clone();
+    let mut n = 1;
+    while is_taken(&candidate) {
+        n += 1;
+        candidate = format!("{} ({})", base, n);
+    }
+    candidate
+}
+
+// Environment variable holding a personal access token, for supplying it at runtime (e.g. as a
+// Docker or CI secret) rather than from a file or the database. Takes precedence over both.
+pub const ACCESS_TOKEN_VAR: &str = "YNAB_ACCESS_TOKEN";
+
+// Whether s looks like a YNAB personal access token
+fn is_token(s: &str) -> bool {
+    s.len() == 64 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// The token with surrounding whitespace removed, or an error naming where it came from if it isn't
+// one, rather than storing it and getting 401s from the API later
+fn check_token(token: &str, source: &str) -> Result<String> {
+    let token = token.trim();
+    if !is_token(token) {
+        return Err(ImportError::InvalidTokenError(source.into()).into());
+    }
+    Ok(token.into())
+}
+
+// Reads a personal access token saved to a file, ignoring surrounding whitespace
+pub fn read_token(path: &Path) -> Result<String> {
+    let mut pat_file = fs::File::open(path)?;
+    let mut token = String::new();
+    pat_file.read_to_string(&mut token)?;
+    check_token(&token, &path.display().to_string())
+}
+
+// The token in YNAB_ACCESS_TOKEN, or None if it's unset or empty
+pub fn env_token() -> Result<Option<String>> {
+    match env::var(ACCESS_TOKEN_VAR) {
+        Ok(token) if token.trim().is_empty() => Ok(None),
+        Ok(token) => Ok(Some(check_token(&token, ACCESS_TOKEN_VAR)?)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
 
 fn create_dir_if_not_exists(path: &Path) -> io::Result<()> {
     match fs::create_dir(path) {
         Ok(()) => {
-            println!("Created {}", path.display());
+            info!(path = %path.display(), "Created directory");
             Ok(())
         }
         Err(err) => match err.kind() {
             io::ErrorKind::AlreadyExists => {
-                println!("{} already exists", path.display());
+                debug!(path = %path.display(), "Directory already exists");
                 Ok(())
             }
             _ => Err(err),
@@ -30,31 +119,80 @@ fn create_dir_if_not_exists(path: &Path) -> io::Result<()> {
     }
 }
 
+// The budget with the given id, or else name (ignoring case)
+pub fn find_budget<'a>(budgets: &'a [BudgetSummary], budget: &str) -> Result<&'a BudgetSummary> {
+    budgets
+        .iter()
+        .find(|b| b.id.to_string().eq_ignore_ascii_case(budget))
+        .or_else(|| budgets.iter().find(|b| b.name.eq_ignore_ascii_case(budget)))
+        .ok_or_else(|| {
+            let names: Vec<_> = budgets.iter().map(|b| b.name.as_str()).collect();
+            anyhow!(
+                "No budget named '{}', expected one of: {}",
+                budget,
+                names.join(", ")
+            )
+        })
+}
+
+// The folder to monitor as it will be stored, i.e. canonicalized, as long as it's an existing,
+// writable directory
+pub fn check_transaction_dir(path: &Path) -> Result<PathBuf> {
+    if !path.is_dir() {
+        return Err(anyhow!("'{}' is not an existing folder", path.display()));
+    }
+    let path = path.canonicalize()?;
+    if fs::metadata(&path)?.permissions().readonly() {
+        return Err(anyhow!("'{}' is not writable", path.display()));
+    }
+    Ok(path)
+}
+
+// Takes the folder names recorded for the budget and its accounts, see unique_dir_name
 pub fn create_directories(
     transaction_dir: &Path,
-    budget: &BudgetSummary,
-    accounts: &[Account],
+    budget_dir: &str,
+    account_dirs: &[String],
+    depth: FolderDepth,
 ) -> io::Result<()> {
     let mut path = transaction_dir.to_path_buf();
-    path.push(&budget.name);
+    path.push(budget_dir);
     create_dir_if_not_exists(&path)?;
+    if depth == FolderDepth::BudgetOnly {
+        return Ok(());
+    }
 
-    for acc in accounts.iter() {
-        path.push(&acc.name);
+    for dir in account_dirs.iter() {
+        path.push(dir);
         create_dir_if_not_exists(&path)?;
         path.pop();
     }
     Ok(())
 }
 
+// The transactions fetched for one account, along with the server_knowledge to send next time
+struct AccountTransactions {
+    account_id: i64,
+    server_knowledge: i64,
+    transactions: Vec<TransactionRow>,
+}
+
+// Fetches the transactions of each account concurrently, at most `concurrency` at a time. Accounts
+// with a stored server_knowledge only get what changed since that sync, the rest get their full
+// history, or the part of it from since on if given. Each account's outcome is returned separately,
+// so one which failed doesn't lose the others.
 async fn make_transactions_request(
     api_config: Configuration,
     budget_uuids: HashMap<i64, String>,
-    accounts: Vec<AccountRow>,
+    accounts: Vec<(AccountRow, Option<i64>)>,
+    since: Option<NaiveDate>,
+    api_retries: usize,
+    concurrency: usize,
     tx: Sender<String>,
-) -> Result<Vec<TransactionRow>> {
-    let mut set: JoinSet<Result<Vec<TransactionRow>>> = JoinSet::new();
-    for acc in accounts {
+) -> Result<Vec<(AccountRow, Result<AccountTransactions>)>> {
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut set: JoinSet<(AccountRow, Result<AccountTransactions>)> = JoinSet::new();
+    for (acc, last_knowledge) in accounts {
         let budget_uuid = budget_uuids
             .get(&acc.id)
             .ok_or(anyhow!("Missing account id {}", acc.id))?
@@ -62,67 +200,223 @@ async fn make_transactions_request(
         let api_config = api_config.clone();
         let acc = acc.clone();
         let tx = tx.clone();
+        let semaphore = semaphore.clone();
 
         set.spawn(async move {
-            let response = get_transactions_by_account(
-                &api_config,
-                &budget_uuid,
-                &acc.uuid.hyphenated().to_string(),
-                None,
-                None,
-                None,
-            )
-            .await?;
-            let transactions: Vec<TransactionRow> = response
-                .data
-                .transactions
-                .into_iter()
-                .map(|t| TransactionRow::new(t.amount, t.date, acc.id))
-                .collect::<Result<Vec<TransactionRow>>>()
-                .unwrap_or_else(|err| panic!("Failed to create transaction row: {}", err));
-            let msg = format!(
-                "Storing {} transactions for account {}",
-                transactions.len(),
-                acc.name
-            );
-            tx.send(msg).expect("Channel was closed");
-            Ok(transactions)
+            let result = async {
+                // Held until the account's transactions have been fetched, including retries
+                let _permit = semaphore.acquire_owned().await?;
+                let account_uuid = acc.uuid.hyphenated().to_string();
+                let since_date = match last_knowledge {
+                    None => since.map(|date| date.to_string()),
+                    Some(_) => None,
+                };
+                let response = with_retry(api_retries, || {
+                    get_transactions_by_account(
+                        &api_config,
+                        &budget_uuid,
+                        &account_uuid,
+                        since_date.clone(),
+                        None,
+                        last_knowledge,
+                    )
+                })
+                .await?;
+                // A delta also includes transactions deleted since the last sync. Rows are only
+                // keyed by amount and date, so they are left in place rather than risk removing a
+                // different transaction's row.
+                let transactions: Vec<TransactionRow> = response
+                    .data
+                    .transactions
+                    .into_iter()
+                    .filter(|t| !t.deleted)
+                    .map(|t| {
+                        Ok(TransactionRow {
+                            payee_name: t.payee_name.flatten(),
+                            memo: t.memo.flatten(),
+                            import_id: t.import_id.flatten(),
+                            ..TransactionRow::new(t.amount, t.date, acc.id)?
+                        })
+                    })
+                    .collect::<Result<Vec<TransactionRow>>>()?;
+                let msg = match last_knowledge {
+                    Some(_) => format!(
+                        "Storing {} new or changed transactions for account {}",
+                        transactions.len(),
+                        acc.name
+                    ),
+                    None => format!(
+                        "Storing {} transactions for account {}",
+                        transactions.len(),
+                        acc.name
+                    ),
+                };
+                tx.send(msg).expect("Channel was closed");
+                Ok(AccountTransactions {
+                    account_id: acc.id,
+                    server_knowledge: response.data.server_knowledge,
+                    transactions,
+                })
+            }
+            .await;
+            (acc, result)
         });
     }
-    let joined: Vec<Result<Vec<TransactionRow>>> = set.join_all().await;
-    let transactions = joined
-        .into_iter()
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .flatten()
-        .collect::<Vec<TransactionRow>>();
-    Ok(transactions)
+    Ok(set.join_all().await)
 }
 
+// Stores the transactions YNAB has for the given accounts. Accounts whose transactions couldn't be
+// fetched are reported through tx_msg, and in the error returned once the rest are stored.
 pub fn sync_transactions(
     mut conn: Connection,
     api_config: &Configuration,
+    account_uuids: &HashSet<Uuid>,
     tx_msg: Sender<String>,
 ) -> Result<()> {
-    let accounts = account::get_all(&conn)?;
-
+    let mut accounts = Vec::new();
     let mut budget_uuids = HashMap::new();
-    for acc in accounts.iter() {
+    for acc in account::get_all(&conn)? {
+        if !account_uuids.contains(&acc.uuid) {
+            continue;
+        }
         let budget = budget::get(&conn, acc.budget_id)?;
         budget_uuids.insert(acc.id, budget.uuid.hyphenated().to_string());
+        let last_knowledge = sync_state::get(&conn, acc.id)?;
+        accounts.push((acc, last_knowledge));
     }
 
+    let since = config::get_sync_since(&conn)?;
+    let api_retries = config::get_api_retries(&conn)?;
+    let concurrency = config::get_sync_concurrency(&conn)?;
     let (tx_trans, rx) = mpsc::channel();
     let api_config = api_config.clone();
+    let tx_failed = tx_msg.clone();
     tokio::spawn(async move {
-        let result = make_transactions_request(api_config, budget_uuids, accounts, tx_msg).await;
+        let result = make_transactions_request(
+            api_config,
+            budget_uuids,
+            accounts,
+            since,
+            api_retries,
+            concurrency,
+            tx_msg,
+        )
+        .await;
         tx_trans.send(result).expect("Channel was closed");
     });
 
+    // Knowledge is stored in the same DB transaction as the rows, so a failed sync keeps the old
+    // value
+    let mut failed = Vec::new();
     let tx = conn.transaction()?;
     for res in rx {
-        for t in res? {
-            transaction::create_if_not_exists(&tx, t)?;
+        for (acc, fetched) in res? {
+            let fetched = match fetched {
+                Ok(fetched) => fetched,
+                Err(err) => {
+                    tx_failed
+                        .send(format!(
+                            "Failed to fetch transactions for account {}: {:#}",
+                            acc.name, err
+                        ))
+                        .expect("Channel was closed");
+                    failed.push(acc.name);
+                    continue;
+                }
+            };
+            for t in fetched.transactions {
+                transaction::create_if_not_exists(&tx, t)?;
+            }
+            sync_state::set(&tx, fetched.account_id, fetched.server_knowledge)?;
+        }
+    }
+    tx.commit()?;
+    if !failed.is_empty() {
+        return Err(ImportError::SyncError(failed).into());
+    }
+    Ok(())
+}
+
+// Fetches the categories of each budget concurrently, as (budget row id, categories) pairs. The
+// group name is filled in from the group for categories which don't carry it.
+async fn make_categories_request(
+    api_config: Configuration,
+    budgets: Vec<(i64, String)>,
+    api_retries: usize,
+) -> Result<Vec<(i64, Vec<Category>)>> {
+    let mut set: JoinSet<Result<(i64, Vec<Category>)>> = JoinSet::new();
+    for (budget_id, budget_uuid) in budgets {
+        let api_config = api_config.clone();
+        set.spawn(async move {
+            let response = with_retry(api_retries, || {
+                get_categories(&api_config, &budget_uuid, None)
+            })
+            .await?;
+            let categories = response
+                .data
+                .category_groups
+                .into_iter()
+                .filter(|group| !group.deleted)
+                .flat_map(|group| {
+                    let group_name = group.name;
+                    group
+                        .categories
+                        .into_iter()
+                        .filter(|c| !c.deleted)
+                        .map(move |c| Category {
+                            category_group_name: c.category_group_name.or(Some(group_name.clone())),
+                            ..c
+                        })
+                })
+                .collect();
+            Ok((budget_id, categories))
+        });
+    }
+    let joined: Vec<Result<(i64, Vec<Category>)>> = set.join_all().await;
+    joined.into_iter().collect()
+}
+
+// Refreshes the cached categories of every budget, which category rules are checked against. Rules
+// for categories which no longer exist in YNAB are dropped.
+pub fn sync_categories(
+    conn: &mut Connection,
+    api_config: &Configuration,
+    tx_msg: Sender<String>,
+) -> Result<()> {
+    let budgets = budget::get_all(conn)?;
+    let budget_uuids = budgets
+        .iter()
+        .map(|b| (b.id, b.uuid.hyphenated().to_string()))
+        .collect();
+
+    let api_retries = config::get_api_retries(conn)?;
+    let (tx_categories, rx) = mpsc::channel();
+    let api_config = api_config.clone();
+    tokio::spawn(async move {
+        let result = make_categories_request(api_config, budget_uuids, api_retries).await;
+        tx_categories.send(result).expect("Channel was closed");
+    });
+
+    let tx = conn.transaction()?;
+    for res in rx {
+        for (budget_id, categories) in res? {
+            let removed = category::replace_for_budget(&tx, budget_id, &categories)?;
+            let name = &budgets.iter().find(|b| b.id == budget_id).unwrap().name;
+            tx_msg
+                .send(format!(
+                    "Stored {} categories for {}",
+                    categories.len(),
+                    name
+                ))
+                .expect("Channel was closed");
+            if removed > 0 {
+                tx_msg
+                    .send(format!(
+                        "Removed {} category rules for categories no longer in {}",
+                        removed, name
+                    ))
+                    .expect("Channel was closed");
+            }
         }
     }
     tx.commit()?;
@@ -136,44 +430,342 @@ pub fn run_setup(
     // API configuration object (with bearer access token)
     api_config: &Configuration,
 
+    // YNAB user the access token belongs to, from the user API
+    user_id: Uuid,
+
     // Path to create subdirectories in
     transaction_dir: &PathBuf,
 
-    // Budget objects from get_budgets API, with accounts loaded
+    // Budget objects from get_budgets API, with accounts loaded. Only the accounts listed are set up,
+    // so callers can drop the ones that shouldn't be monitored.
     budgets: Vec<BudgetSummary>,
 
+    // Whether to create per-account subfolders or route by ACCTID within budget folders
+    folder_depth: FolderDepth,
+
     // Channel to send status messages over
     tx_msg: Sender<String>,
 ) -> Result<()> {
     if !fs::exists(transaction_dir)? {
         return Err(anyhow!("Directory does not exist"));
     }
+    let initial_sync = config::get_initial_sync(&conn)?;
     let tx = conn.transaction()?;
-    for budget in budgets {
-        let accounts = budget.accounts.clone().unwrap_or(Vec::new());
-        create_directories(transaction_dir, &budget, &accounts)?;
-        tx_msg
-            .send(format!("Created directories for {}", &budget.name.clone()))
-            .expect("Channel was closed");
+    config::set(&tx, config::USER_ID, &user_id.to_string())?;
+    config::set_transaction_dir(&tx, transaction_dir)?;
+    config::set_folder_depth(&tx, folder_depth)?;
 
+    // Re-running setup, e.g. after adding an account in YNAB, keeps the folders of the budgets and
+    // accounts set up before. Only the new ones are named, though every folder is created again if
+    // it's missing.
+    let configured_budgets: HashSet<Uuid> = budget::get_all(&tx)?.iter().map(|b| b.uuid).collect();
+    let configured: HashSet<Uuid> = account::get_all(&tx)?.iter().map(|a| a.uuid).collect();
+    let mut selected = HashSet::new();
+    let mut new_accounts = 0;
+    for budget in budgets {
+        let accounts: Vec<Account> = budget.accounts.clone().unwrap_or_default();
         let budget_id = budget::get_or_create(&tx, &budget)?;
+        let new_budget = !configured_budgets.contains(&budget.id);
+        let budget_dir = if new_budget {
+            let dir = unique_dir_name(&budget.name, &budget::other_dir_names(&tx, budget_id)?);
+            budget::set_dir_name(&tx, budget_id, &dir)?;
+            dir
+        } else {
+            budget::dir_name(&tx, budget_id)?
+        };
         account::create_if_not_exists(&tx, budget_id, &accounts)?;
-        config::set_transaction_dir(&tx, transaction_dir)?;
-        config::set(
-            &tx,
-            config::TRANSACTION_DIR,
-            &serde_json::to_string(transaction_dir.as_os_str())?,
-        )?;
-        config::set(
-            &tx,
-            config::ACCESS_TOKEN,
-            &api_config.bearer_access_token.clone().unwrap(),
-        )?;
+        let mut account_dirs = Vec::new();
+        let mut added = 0;
+        for acc in accounts.iter() {
+            let dir = if configured.contains(&acc.id) {
+                account::dir_name(&tx, account::with_uuid(&tx, acc.id)?.id)?
+            } else {
+                let taken = account::other_dir_names(&tx, budget_id, acc.id)?;
+                let dir = unique_dir_name(&acc.name, &taken);
+                account::set_dir_name(&tx, acc.id, &dir)?;
+                added += 1;
+                dir
+            };
+            account_dirs.push(dir);
+            selected.insert(acc.id);
+        }
+        create_directories(transaction_dir, &budget_dir, &account_dirs, folder_depth)?;
+        if new_budget || added > 0 {
+            tx_msg
+                .send(format!("Created directories for {}", &budget.name.clone()))
+                .expect("Channel was closed");
+        }
+        new_accounts += added;
     }
     tx.commit()?;
-    sync_transactions(conn, api_config, tx_msg.clone())?;
+    tx_msg
+        .send(format!("{} new accounts configured", new_accounts))
+        .expect("Channel was closed");
+    if initial_sync {
+        // Accounts whose history was synced before are left alone, including ones set up with
+        // syncing turned off or whose sync failed, which are synced now
+        let mut unsynced = HashSet::new();
+        for uuid in selected {
+            let account = account::with_uuid(&conn, uuid)?;
+            if sync_state::get(&conn, account.id)?.is_none() {
+                unsynced.insert(uuid);
+            }
+        }
+        sync_categories(&mut conn, api_config, tx_msg.clone())?;
+        // Accounts which failed to sync were reported as they failed. They're left without a sync
+        // state, so setup is completed for the rest and they're synced when it's run again.
+        match sync_transactions(conn, api_config, &unsynced, tx_msg.clone()) {
+            Err(err) => match err.downcast_ref::<ImportError>() {
+                Some(ImportError::SyncError(failed)) => tx_msg
+                    .send(format!(
+                        "Transactions of {} weren't synced, run setup again to retry",
+                        failed.join(", ")
+                    ))
+                    .expect("Channel was closed"),
+                _ => return Err(err),
+            },
+            Ok(()) => (),
+        }
+    } else {
+        tx_msg
+            .send("Skipping sync of existing transactions".into())
+            .expect("Channel was closed");
+    }
+    // YNAB doesn't know the bank's account numbers, so they can only be linked by hand
+    let link_message = match folder_depth {
+        FolderDepth::BudgetOnly => "Use link-account to set the ACCTID of each account",
+        FolderDepth::BudgetAccount => {
+            "Use link-account to set the ACCTID of each account, so that statements filed in the \
+            wrong folder are imported into the right one"
+        }
+    };
+    tx_msg
+        .send(link_message.into())
+        .expect("Channel was closed");
     tx_msg
         .send("Setup Complete".into())
         .expect("Channel was closed");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{migrate, test_conn};
+    use std::env::temp_dir;
+    use ynab_api::models::AccountType;
+
+    fn test_budget() -> (BudgetSummary, Vec<Account>) {
+        let accounts = ["Checking", "Savings"]
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                Account::new(
+                    Uuid::from_u128(i as u128 + 1),
+                    name.to_string(),
+                    AccountType::Checking,
+                    true,
+                    false,
+                    0,
+                    0,
+                    0,
+                    None,
+                    false,
+                )
+            })
+            .collect();
+        (BudgetSummary::new(Uuid::nil(), "Budget".into()), accounts)
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = temp_dir().join(format!("ynab-importer-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_create_directories_budget_account() {
+        let dir = test_dir("budget-account");
+        let accounts = ["Checking".to_string(), "Savings".to_string()];
+        create_directories(&dir, "Budget", &accounts, FolderDepth::BudgetAccount).unwrap();
+
+        assert!(dir.join("Budget").is_dir());
+        assert!(dir.join("Budget").join("Checking").is_dir());
+        assert!(dir.join("Budget").join("Savings").is_dir());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_check_transaction_dir() {
+        let dir = test_dir("check-transaction-dir");
+        fs::create_dir_all(dir.join("Budget")).unwrap();
+        assert_eq!(
+            check_transaction_dir(&dir.join("Budget").join("..")).unwrap(),
+            dir.canonicalize().unwrap()
+        );
+
+        assert!(check_transaction_dir(&dir.join("Missing")).is_err());
+        fs::write(dir.join("statement.qfx"), "").unwrap();
+        assert!(check_transaction_dir(&dir.join("statement.qfx")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_token() {
+        let dir = test_dir("read-token");
+        let token = "0123456789abcdef".repeat(4);
+
+        let path = dir.join("token.txt");
+        fs::write(&path, format!("  {}\r\n", token)).unwrap();
+        assert_eq!(read_token(&path).unwrap(), token);
+
+        fs::write(&path, format!("token = \"{}\"\n", token)).unwrap();
+        assert!(read_token(&path).is_err());
+        fs::write(&path, &token[1..]).unwrap();
+        assert!(read_token(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_budget() {
+        let budgets = vec![
+            BudgetSummary::new(Uuid::from_u128(1), "Personal".into()),
+            BudgetSummary::new(Uuid::from_u128(2), "Business".into()),
+        ];
+        assert_eq!(find_budget(&budgets, "business").unwrap().name, "Business");
+        assert_eq!(
+            find_budget(&budgets, "00000000-0000-0000-0000-000000000001")
+                .unwrap()
+                .name,
+            "Personal"
+        );
+        let err = find_budget(&budgets, "Savings").unwrap_err();
+        assert!(err.to_string().contains("Personal, Business"));
+    }
+
+    #[test]
+    fn test_sanitize_component() {
+        assert_eq!(sanitize_component("Checking"), "Checking");
+        assert_eq!(
+            sanitize_component("Checking / Savings"),
+            "Checking _ Savings"
+        );
+        assert_eq!(sanitize_component("Joint: Bills"), "Joint_ Bills");
+        assert_eq!(sanitize_component(r#"a<b>c"d\e|f?g*h"#), "a_b_c_d_e_f_g_h");
+        assert_eq!(sanitize_component("Visa..."), "Visa");
+        assert_eq!(sanitize_component(".."), "_");
+        assert_eq!(sanitize_component(""), "_");
+    }
+
+    #[test]
+    fn test_unique_dir_name() {
+        assert_eq!(unique_dir_name("Checking", &[]), "Checking");
+        let taken = vec![
+            "Checking _ Savings".to_string(),
+            "checking _ savings (2)".into(),
+        ];
+        assert_eq!(
+            unique_dir_name("Checking / Savings", &taken),
+            "Checking _ Savings (3)"
+        );
+        assert_eq!(
+            unique_dir_name("Checking | Savings", &taken[..1]),
+            "Checking _ Savings (2)"
+        );
+    }
+
+    #[test]
+    fn test_create_directories_budget_only() {
+        let dir = test_dir("budget-only");
+        let accounts = ["Checking".to_string(), "Savings".to_string()];
+        create_directories(&dir, "Budget", &accounts, FolderDepth::BudgetOnly).unwrap();
+
+        assert!(dir.join("Budget").is_dir());
+        assert_eq!(fs::read_dir(dir.join("Budget")).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_setup_without_initial_sync() {
+        let dir = test_dir("no-sync");
+        let conn = test_conn();
+        config::set_initial_sync(&conn, false).unwrap();
+
+        // Nothing listens on this address, so setup can only succeed if no transactions are fetched
+        let mut api_config = Configuration::new();
+        api_config.base_path = "http://127.0.0.1:9".into();
+        api_config.bearer_access_token = Some("token".into());
+
+        let (budget, accounts) = test_budget();
+        let budget = BudgetSummary {
+            accounts: Some(accounts),
+            ..budget
+        };
+        let (tx, rx) = mpsc::channel();
+        run_setup(
+            conn,
+            &api_config,
+            Uuid::from_u128(1),
+            &dir,
+            vec![budget],
+            FolderDepth::BudgetAccount,
+            tx,
+        )
+        .unwrap();
+
+        let messages: Vec<String> = rx.iter().collect();
+        assert!(messages.contains(&"Skipping sync of existing transactions".into()));
+        assert!(messages.contains(&"Setup Complete".into()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_setup_again_with_new_account() {
+        let dir = test_dir("rerun");
+        let db_path = dir.join("db.sqlite");
+        let mut conn = Connection::open(&db_path).unwrap();
+        migrate(&mut conn);
+        config::set_initial_sync(&conn, false).unwrap();
+
+        let (budget, mut accounts) = test_budget();
+        let mut api_config = Configuration::new();
+        api_config.bearer_access_token = Some("token".into());
+        let run = |conn, accounts: &[Account]| {
+            let budget = BudgetSummary {
+                accounts: Some(accounts.to_vec()),
+                ..budget.clone()
+            };
+            let (tx, rx) = mpsc::channel();
+            run_setup(
+                conn,
+                &api_config,
+                Uuid::from_u128(1),
+                &dir,
+                vec![budget],
+                FolderDepth::BudgetAccount,
+                tx,
+            )
+            .unwrap();
+            rx.iter().collect::<Vec<String>>()
+        };
+        let messages = run(conn, &accounts);
+        assert!(messages.contains(&"2 new accounts configured".into()));
+
+        // The new account gets a folder, and the removed one for Checking is created again
+        fs::remove_dir(dir.join("Budget").join("Checking")).unwrap();
+        accounts.push(Account {
+            id: Uuid::from_u128(3),
+            name: "Visa".into(),
+            ..accounts[0].clone()
+        });
+        let messages = run(Connection::open(&db_path).unwrap(), &accounts);
+        assert!(messages.contains(&"1 new accounts configured".into()));
+        assert!(dir.join("Budget").join("Visa").is_dir());
+        assert!(dir.join("Budget").join("Checking").is_dir());
+
+        let messages = run(Connection::open(&db_path).unwrap(), &accounts);
+        assert!(messages.contains(&"0 new accounts configured".into()));
+        assert!(!messages.contains(&"Created directories for Budget".into()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,20 +1,25 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, Utc};
 use eframe::egui::{self, Context, FontId, Spinner, Theme};
 use eframe::{self, egui::RichText};
 use egui::{Align2, Color32, Id, LayerId, Order, TextStyle};
+use rusqlite::Connection;
+use std::collections::{HashMap, HashSet};
 use std::env::current_dir;
 use std::fmt::Write as _;
-use std::fs;
-use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, channel, Receiver, Sender};
+use tracing::error;
+use uuid::Uuid;
 use ynab_api::{
-    apis::{budgets_api::get_budgets, configuration::Configuration},
-    models::BudgetSummary,
+    apis::{budgets_api::get_budgets, configuration::Configuration, user_api::get_user},
+    models::{BudgetSummary, TransactionClearedStatus},
 };
 
-use crate::db::get_sqlite_conn;
-use crate::setup::run_setup;
+use crate::auth::{self, RefreshRequest};
+use crate::db::config::{self, BudgetCache, GuiTheme, WindowGeometry};
+use crate::db::{account, budget, get_sqlite_conn};
+use crate::setup::{check_transaction_dir, env_token, read_token, run_setup};
 
 type View = Box<dyn eframe::App + Send>;
 
@@ -25,34 +30,151 @@ the states (Views) themselves.
 pub struct ConfigApp {
     current_view: View,
     rx: Receiver<View>,
+    // Last known window geometry, saved when the window is closed
+    geometry: Option<WindowGeometry>,
 }
 
 impl ConfigApp {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        cc.egui_ctx.set_theme(Theme::Dark);
+        let theme = get_sqlite_conn()
+            .and_then(|conn| config::get_theme(&conn))
+            .unwrap_or_default();
+        cc.egui_ctx.set_theme(match theme {
+            GuiTheme::Dark => Theme::Dark,
+            GuiTheme::Light => Theme::Light,
+        });
         cc.egui_ctx.set_zoom_factor(1.5);
         let (tx, rx) = channel();
         Self {
             current_view: Box::new(DragAndDropFileView::new(tx.clone())),
             rx,
+            geometry: None,
+        }
+    }
+
+    // Viewport rects are reported in zoomed ui points, while the ViewportBuilder on startup expects
+    // unzoomed ones
+    fn track_geometry(&mut self, ctx: &egui::Context) {
+        let zoom = ctx.zoom_factor();
+        let (inner, outer, close_requested) = ctx.input(|i| {
+            let viewport = i.viewport();
+            (
+                viewport.inner_rect,
+                viewport.outer_rect,
+                viewport.close_requested(),
+            )
+        });
+        if let Some(inner) = inner {
+            self.geometry = Some(WindowGeometry {
+                size: [inner.width() * zoom, inner.height() * zoom],
+                position: outer.map(|outer| [outer.min.x * zoom, outer.min.y * zoom]),
+            });
+        }
+
+        if close_requested {
+            if let Some(geometry) = self.geometry {
+                if let Err(err) =
+                    get_sqlite_conn().and_then(|conn| config::set_window_geometry(&conn, geometry))
+                {
+                    error!("Failed to save window geometry: {:?}", err);
+                }
+            }
         }
     }
 }
 
+// Shown above every view. Switches between the light and dark theme and saves the choice.
+fn theme_panel(ctx: &egui::Context) {
+    egui::TopBottomPanel::top("theme_panel")
+        .show_separator_line(false)
+        .show(ctx, |ui| {
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                let mut theme = ctx.theme();
+                ui.selectable_value(&mut theme, Theme::Light, "Light");
+                ui.selectable_value(&mut theme, Theme::Dark, "Dark");
+                if theme != ctx.theme() {
+                    ctx.set_theme(theme);
+                    let saved = match theme {
+                        Theme::Dark => GuiTheme::Dark,
+                        Theme::Light => GuiTheme::Light,
+                    };
+                    if let Err(err) =
+                        get_sqlite_conn().and_then(|conn| config::set_theme(&conn, saved))
+                    {
+                        error!("Failed to save theme: {:?}", err);
+                    }
+                }
+            });
+        });
+}
+
+// Picks the cleared status of imported transactions. Reconciled is left out, imports haven't been
+// reconciled yet.
+fn cleared_status_combo(
+    ui: &mut egui::Ui,
+    id_salt: impl std::hash::Hash,
+    status: &mut TransactionClearedStatus,
+) {
+    let label = |status: TransactionClearedStatus| match status {
+        TransactionClearedStatus::Uncleared => "Uncleared",
+        _ => "Cleared",
+    };
+    egui::ComboBox::from_id_salt(id_salt)
+        .selected_text(label(*status))
+        .show_ui(ui, |ui| {
+            for option in [
+                TransactionClearedStatus::Cleared,
+                TransactionClearedStatus::Uncleared,
+            ] {
+                ui.selectable_value(status, option, label(option));
+            }
+        });
+}
+
+// Error messages use the theme's error colour so they stay readable on light backgrounds
+fn error_label(ui: &mut egui::Ui, msg: &str) {
+    let color = ui.visuals().error_fg_color;
+    ui.label(RichText::new(msg).color(color));
+}
+
 impl eframe::App for ConfigApp {
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        theme_panel(ctx);
         self.current_view.update(ctx, frame);
+        self.track_geometry(ctx);
         if let Ok(next_state) = self.rx.try_recv() {
             self.current_view = next_state;
         }
     }
 }
 
+// Returns up to `max` paths of files dropped onto the window this frame, along with the number of
+// extra files that were ignored so views can tell the user about them.
+fn dropped_files(ctx: &egui::Context, max: usize) -> (Vec<PathBuf>, usize) {
+    let mut paths: Vec<PathBuf> = ctx.input(|i| {
+        i.raw
+            .dropped_files
+            .iter()
+            .filter_map(|file| file.path.clone())
+            .collect()
+    });
+    let ignored = paths.len().saturating_sub(max);
+    paths.truncate(max);
+    (paths, ignored)
+}
+
 // Initial state, asks the user to provide a file containing the personal access token
 struct DragAndDropFileView {
     tx: Sender<View>,
     picked_path: Option<PathBuf>,
+    // Token file used on a previous run, if it still exists
+    previous_path: Option<PathBuf>,
+    // Token and monitored folder from a completed setup, if there was one
+    existing_config: Option<(String, PathBuf)>,
     error: Option<String>,
+    // Outcome of the last "Test Connection", and the channel its result arrives on while running
+    connection_status: Option<Result<String, String>>,
+    rx_connection: Option<Receiver<Result<String, String>>>,
 }
 
 impl DragAndDropFileView {
@@ -60,24 +182,55 @@ impl DragAndDropFileView {
         Self {
             tx,
             picked_path: None,
+            previous_path: Self::load_previous_path(),
+            existing_config: Self::load_existing_config(),
             error: None,
+            connection_status: None,
+            rx_connection: None,
         }
     }
 
+    fn pick(&mut self, path: PathBuf) {
+        self.picked_path = Some(path);
+        self.connection_status = None;
+        self.error = None;
+    }
+
+    fn load_previous_path() -> Option<PathBuf> {
+        let conn = get_sqlite_conn().ok()?;
+        config::get_token_path(&conn)
+            .ok()
+            .flatten()
+            .filter(|path| path.is_file())
+    }
+
+    fn load_existing_config() -> Option<(String, PathBuf)> {
+        let conn = get_sqlite_conn().ok()?;
+        let token = match env_token().ok()? {
+            Some(token) => token,
+            None => config::get_access_token(&conn).ok()?,
+        };
+        let transaction_dir = config::get_transaction_dir(&conn).ok()?;
+        Some((token, transaction_dir))
+    }
+
     fn open_file_dialog(&mut self) {
         if let Some(path) = rfd::FileDialog::new().pick_file() {
-            self.picked_path = Some(path);
+            self.pick(path);
         }
     }
 
+    // Only a single token file makes sense here, so reject the drop entirely if there are more
     fn check_dropped_files(&mut self, ctx: &egui::Context) {
-        ctx.input(|i| {
-            if !i.raw.dropped_files.is_empty() {
-                if let Some(path) = &i.raw.dropped_files[0].path {
-                    self.picked_path = Some(path.clone());
-                }
-            }
-        });
+        let (mut paths, ignored) = dropped_files(ctx, 1);
+        if ignored > 0 {
+            self.error = Some(format!(
+                "Drop a single token file ({} files were dropped)",
+                ignored + paths.len()
+            ));
+        } else if let Some(path) = paths.pop() {
+            self.pick(path);
+        }
     }
 
     fn preview_files_being_dropped(&self, ctx: &egui::Context) {
@@ -107,68 +260,236 @@ impl DragAndDropFileView {
         }
     }
 
+    // Checks the picked token against the budgets endpoint in the background, staying on this view.
+    // The result is picked up by poll_connection_test.
+    fn test_connection(&mut self, ctx: Context) -> Result<()> {
+        if let Some(path) = &self.picked_path {
+            let mut api_config = Configuration::new();
+            api_config.bearer_access_token = Some(read_token(path)?);
+
+            let (tx, rx) = mpsc::channel();
+            self.rx_connection = Some(rx);
+            self.connection_status = None;
+            tokio::spawn(async move {
+                let result = match get_budgets(&api_config, Some(false)).await {
+                    Ok(resp) => Ok(format!(
+                        "Connected - {} budgets found",
+                        resp.data.budgets.len()
+                    )),
+                    Err(err) => Err(err.to_string()),
+                };
+                tx.send(result).expect("Channel was closed");
+                ctx.request_repaint();
+            });
+        }
+        Ok(())
+    }
+
+    fn poll_connection_test(&mut self) {
+        if let Some(rx) = &self.rx_connection {
+            if let Ok(result) = rx.try_recv() {
+                self.connection_status = Some(result);
+                self.rx_connection = None;
+            }
+        }
+    }
+
     // Transitions to the loading state and also initiates the tokio task to hit the YNAB api, which
     // will initiate transition to the budget select state on completion.
     fn next_state(&self, ctx: Context) -> Result<()> {
         if let Some(path) = &self.picked_path {
-            let mut pat_file = fs::File::open(path)?;
-            let mut token = String::new();
-            pat_file.read_to_string(&mut token)?;
+            let token = read_token(path)?;
+
+            let conn = get_sqlite_conn()?;
+            config::set_token_path(&conn, path)?;
+            // The token file replaces any OAuth login, which would overwrite it on refresh
+            auth::logout(&conn)?;
+            config::set(&conn, config::ACCESS_TOKEN, &token)?;
 
             let mut api_config = Configuration::new();
             api_config.bearer_access_token = Some(token);
+            self.open_form(ctx, api_config, None, None, None)?;
+        }
+        Ok(())
+    }
 
+    // Skips the token step, editing the settings saved by a previous setup. A token from an OAuth
+    // login which has expired since is refreshed first. The budgets cached for the same user are
+    // shown straight away if there are any.
+    fn reconfigure(&self, ctx: Context) -> Result<()> {
+        if let Some((token, transaction_dir)) = &self.existing_config {
+            let conn = get_sqlite_conn()?;
+            let refresh = auth::refresh_request(&conn)?;
+            let user_id = config::get_opt(&conn, config::USER_ID)?;
+            let cache = config::get_budget_cache(&conn)?
+                .filter(|cache| user_id == Some(cache.user_id.to_string()));
+            let mut api_config = Configuration::new();
+            api_config.bearer_access_token = Some(token.clone());
+            self.open_form(
+                ctx,
+                api_config,
+                refresh,
+                Some(transaction_dir.clone()),
+                cache,
+            )?;
+        }
+        Ok(())
+    }
+
+    // With a cache the form opens on the cached budgets, which are replaced once fresh ones have
+    // been fetched. Otherwise the loading screen is shown until they have been.
+    fn open_form(
+        &self,
+        ctx: Context,
+        api_config: Configuration,
+        refresh: Option<RefreshRequest>,
+        transaction_dir: Option<PathBuf>,
+        cache: Option<BudgetCache>,
+    ) -> Result<()> {
+        if let Some(cache) = cache {
+            let mut form_view =
+                MonitoredFolderFormView::new(api_config.clone(), cache, transaction_dir)?;
+            let (tx_refresh, rx_refresh) = mpsc::channel();
+            form_view.rx_refresh = Some(rx_refresh);
             self.tx
-                .send(Box::new(LoadingView()))
+                .send(Box::new(form_view))
                 .expect("Channel was closed");
 
-            let tx = self.tx.clone();
             tokio::spawn(async move {
-                let next = match MonitoredFolderFormView::init(api_config).await {
-                    // Go to form view
-                    Ok(form_view) => Box::new(form_view) as View,
-                    // Go back to initial state and show error message
-                    Err(err) => Box::new(DragAndDropFileView {
-                        tx: tx.clone(),
-                        picked_path: None,
-                        error: Some(err.to_string()),
-                    }),
+                let result = async {
+                    let api_config = refreshed_config(api_config, refresh).await?;
+                    let cache = MonitoredFolderFormView::fetch(&api_config).await?;
+                    anyhow::Ok((api_config, cache))
                 };
-                tx.send(next).expect("Channel was closed");
+                // The form may have been closed in the meantime
+                let _ = tx_refresh.send(result.await.map_err(|err| err.to_string()));
                 ctx.request_repaint();
             });
+            return Ok(());
         }
+
+        self.tx
+            .send(Box::new(LoadingView()))
+            .expect("Channel was closed");
+
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let form_view = async {
+                let api_config = refreshed_config(api_config, refresh).await?;
+                MonitoredFolderFormView::init(api_config, transaction_dir).await
+            };
+            let next = match form_view.await {
+                // Go to form view
+                Ok(form_view) => Box::new(form_view) as View,
+                // Go back to initial state and show error message
+                Err(err) => {
+                    let mut view = DragAndDropFileView::new(tx.clone());
+                    view.error = Some(err.to_string());
+                    Box::new(view)
+                }
+            };
+            tx.send(next).expect("Channel was closed");
+            ctx.request_repaint();
+        });
         Ok(())
     }
 }
 
+// Swaps in a new access token first if the OAuth login needs refreshing
+async fn refreshed_config(
+    mut api_config: Configuration,
+    refresh: Option<RefreshRequest>,
+) -> Result<Configuration> {
+    if let Some(refresh) = refresh {
+        let tokens = refresh.send().await?;
+        auth::store(&get_sqlite_conn()?, &tokens)?;
+        api_config.bearer_access_token = Some(tokens.access_token);
+    }
+    Ok(api_config)
+}
+
 impl eframe::App for DragAndDropFileView {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.vertical_centered(|ui| {
                 ui.label("");
+                if let Some((_, transaction_dir)) = &self.existing_config {
+                    if ui
+                        .button(format!(
+                            "Edit current settings ({})",
+                            transaction_dir.display()
+                        ))
+                        .clicked()
+                    {
+                        if let Err(err) = self.reconfigure(ctx.clone()) {
+                            self.error = Some(err.to_string());
+                        }
+                    }
+                    ui.add_space(10.0);
+                }
                 ui.label(RichText::new("Personal Access Token").font(FontId::proportional(20.0)));
                 ui.label("Drag-and-drop file here or");
 
                 if ui.button("Browse").clicked() {
                     self.open_file_dialog();
                 }
+
+                if let Some(path) = &self.previous_path {
+                    ui.add_space(10.0);
+                    if ui
+                        .button(format!("Use previous token ({})", path.display()))
+                        .clicked()
+                    {
+                        let path = path.clone();
+                        self.pick(path);
+                    }
+                }
+
+                if let Some(path) = &self.picked_path {
+                    ui.add_space(10.0);
+                    ui.label(format!("Selected: {}", path.display()));
+                    ui.horizontal(|ui| {
+                        if self.rx_connection.is_some() {
+                            ui.spinner();
+                        } else if ui.button("Test Connection").clicked() {
+                            if let Err(err) = self.test_connection(ctx.clone()) {
+                                self.error = Some(err.to_string());
+                            }
+                        }
+                        if ui.button("Continue").clicked() {
+                            if let Err(err) = self.next_state(ctx.clone()) {
+                                self.error = Some(err.to_string());
+                            }
+                        }
+                    });
+                    match &self.connection_status {
+                        Some(Ok(msg)) => {
+                            let color = if ui.visuals().dark_mode {
+                                Color32::LIGHT_GREEN
+                            } else {
+                                Color32::DARK_GREEN
+                            };
+                            ui.label(RichText::new(msg).color(color));
+                        }
+                        Some(Err(msg)) => error_label(ui, msg),
+                        None => (),
+                    }
+                }
             });
 
             self.preview_files_being_dropped(ctx);
             self.check_dropped_files(ctx);
-            if let Err(err) = self.next_state(ctx.clone()) {
-                self.error = Some(err.to_string());
-            }
         });
 
         egui::TopBottomPanel::bottom("error_pannel")
             .show_separator_line(false)
             .show(ctx, |ui| {
                 if let Some(msg) = &self.error {
-                    ui.label(RichText::new(msg).color(Color32::LIGHT_RED));
+                    error_label(ui, msg);
                 }
             });
+
+        self.poll_connection_test();
     }
 }
 
@@ -191,42 +512,204 @@ impl eframe::App for LoadingView {
 // Final state. Form for selecting the folder to monitor and which budgets to create subfolders for.
 struct MonitoredFolderFormView {
     api_config: Configuration,
+    // Owner of the access token, shown at the top of the form and stored by setup
+    user_id: Uuid,
     budgets: Vec<BudgetSummary>,
+    // Budgets which already exist in the local DB from a previous setup
+    configured: Vec<bool>,
     selected: Vec<bool>,
+    // Per budget, which of its accounts get a subfolder and are monitored
+    selected_accounts: Vec<Vec<bool>>,
+    // Per budget, the cleared status given to transactions imported into each of its accounts
+    cleared_statuses: Vec<Vec<TransactionClearedStatus>>,
     transaction_dir: String,
+    // Start of the history synced for new accounts as YYYY-MM-DD, blank for all of it
+    sync_since: String,
     setup_running: bool,
     error: Option<String>,
-    log_msg: Option<String>,
+    // Timestamped status messages from run_setup, oldest first
+    log: Vec<String>,
     rx_msg: Option<Receiver<String>>,
     tx_err: Sender<String>,
     rx_err: Receiver<String>,
+    // Set while fresh budgets are fetched to replace cached ones, along with the config to use from
+    // then on (its token may have been refreshed)
+    rx_refresh: Option<Receiver<std::result::Result<(Configuration, BudgetCache), String>>>,
 }
 
 impl MonitoredFolderFormView {
-    async fn init(api_config: Configuration) -> Result<Self> {
-        let budgets = get_budgets(&api_config, Some(true))
+    // transaction_dir pre-fills the folder field, otherwise it defaults to the working directory
+    async fn init(api_config: Configuration, transaction_dir: Option<PathBuf>) -> Result<Self> {
+        let cache = Self::fetch(&api_config).await?;
+        Self::new(api_config, cache, transaction_dir)
+    }
+
+    // Fetches the token owner and their budgets, caching them for the next launch
+    async fn fetch(api_config: &Configuration) -> Result<BudgetCache> {
+        let budgets = get_budgets(api_config, Some(true))
             .await
             .map(|resp| resp.data.budgets)?;
+        let user_id = get_user(api_config).await?.data.user.id;
+        let cache = BudgetCache {
+            user_id,
+            budgets,
+            fetched_at: Utc::now(),
+        };
+        config::set_budget_cache(&get_sqlite_conn()?, &cache)?;
+        Ok(cache)
+    }
 
+    fn new(
+        api_config: Configuration,
+        cache: BudgetCache,
+        transaction_dir: Option<PathBuf>,
+    ) -> Result<Self> {
+        let conn = get_sqlite_conn()?;
+        let sync_since = config::get_sync_since(&conn)?
+            .map(|date| date.to_string())
+            .unwrap_or_default();
         let (tx_err, rx_err) = mpsc::channel();
 
-        Ok(MonitoredFolderFormView {
+        let mut view = MonitoredFolderFormView {
             api_config,
-            selected: vec![false; budgets.len()],
-            budgets,
-            transaction_dir: current_dir()
+            user_id: cache.user_id,
+            budgets: Vec::new(),
+            configured: Vec::new(),
+            selected: Vec::new(),
+            selected_accounts: Vec::new(),
+            cleared_statuses: Vec::new(),
+            transaction_dir: transaction_dir
+                .or_else(|| current_dir().ok())
                 .map(|b| b.display().to_string())
                 .unwrap_or_default(),
+            sync_since,
             setup_running: false,
             error: None,
-            log_msg: None,
+            log: Vec::new(),
             rx_msg: None,
             tx_err,
             rx_err,
-        })
+            rx_refresh: None,
+        };
+        view.set_budgets(&conn, cache.budgets)?;
+        Ok(view)
+    }
+
+    // Replaces the listed budgets. Budgets and accounts listed before keep the choices made for
+    // them, while new ones start out with budgets from a previous setup checked, every account
+    // checked, and accounts from a previous setup keeping the status they were given.
+    fn set_budgets(&mut self, conn: &Connection, budgets: Vec<BudgetSummary>) -> Result<()> {
+        let mut chosen_budgets = HashMap::new();
+        let mut chosen_accounts = HashMap::new();
+        for (i, budget) in self.budgets.iter().enumerate() {
+            chosen_budgets.insert(budget.id, self.selected[i]);
+            for (j, acc) in budget
+                .accounts
+                .as_deref()
+                .unwrap_or_default()
+                .iter()
+                .enumerate()
+            {
+                chosen_accounts.insert(
+                    acc.id,
+                    (self.selected_accounts[i][j], self.cleared_statuses[i][j]),
+                );
+            }
+        }
+
+        let configured_uuids: HashSet<Uuid> =
+            budget::get_all(conn)?.into_iter().map(|b| b.uuid).collect();
+        self.configured = budgets
+            .iter()
+            .map(|b| configured_uuids.contains(&b.id))
+            .collect();
+        self.selected = budgets
+            .iter()
+            .zip(self.configured.iter())
+            .map(|(b, configured)| *chosen_budgets.get(&b.id).unwrap_or(configured))
+            .collect();
+
+        self.selected_accounts = Vec::new();
+        self.cleared_statuses = Vec::new();
+        for budget in budgets.iter() {
+            let mut selected = Vec::new();
+            let mut statuses = Vec::new();
+            for acc in budget.accounts.as_deref().unwrap_or_default() {
+                let (checked, status) = match chosen_accounts.get(&acc.id) {
+                    Some(chosen) => *chosen,
+                    None => (
+                        true,
+                        account::get_cleared_status(conn, acc.id)?.unwrap_or_default(),
+                    ),
+                };
+                selected.push(checked);
+                statuses.push(status);
+            }
+            self.selected_accounts.push(selected);
+            self.cleared_statuses.push(statuses);
+        }
+        self.budgets = budgets;
+        Ok(())
+    }
+
+    // The checked budgets, each with only its checked accounts
+    fn selected_budgets(&self) -> Vec<BudgetSummary> {
+        self.budgets
+            .iter()
+            .zip(self.selected.iter())
+            .zip(self.selected_accounts.iter())
+            .filter(|((_, selected), _)| **selected)
+            .map(|((budget, _), accounts_selected)| {
+                let mut budget = budget.clone();
+                budget.accounts = budget.accounts.map(|accounts| {
+                    accounts
+                        .into_iter()
+                        .zip(accounts_selected.iter())
+                        .filter_map(|(acc, selected)| selected.then_some(acc))
+                        .collect()
+                });
+                budget
+            })
+            .collect()
+    }
+
+    // The chosen cleared status of every checked account in the checked budgets
+    fn selected_cleared_statuses(&self) -> Vec<(Uuid, TransactionClearedStatus)> {
+        let mut statuses = Vec::new();
+        for (i, budget) in self.budgets.iter().enumerate() {
+            if !self.selected[i] {
+                continue;
+            }
+            for (j, acc) in budget
+                .accounts
+                .as_deref()
+                .unwrap_or_default()
+                .iter()
+                .enumerate()
+            {
+                if self.selected_accounts[i][j] {
+                    statuses.push((acc.id, self.cleared_statuses[i][j]));
+                }
+            }
+        }
+        statuses
     }
 
+    // Only the checked budgets (and accounts) are set up
     fn start_setup(&mut self) -> Result<()> {
+        let budgets = self.selected_budgets();
+        if budgets.is_empty() {
+            return Err(anyhow!("Select at least one budget"));
+        }
+        // Checked up front, rather than failing part way through setup
+        let path = check_transaction_dir(Path::new(&self.transaction_dir))?;
+        let sync_since = match self.sync_since.trim() {
+            "" => None,
+            date => Some(
+                NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                    .map_err(|_| anyhow!("Enter the sync start date as YYYY-MM-DD"))?,
+            ),
+        };
         self.setup_running = true;
         self.error = None;
 
@@ -234,13 +717,24 @@ impl MonitoredFolderFormView {
         self.rx_msg = Some(rx);
 
         let conn = get_sqlite_conn()?;
+        config::set_sync_since(&conn, sync_since)?;
         let config = self.api_config.clone();
-        let path = PathBuf::from(&self.transaction_dir);
-        let budgets = self.budgets.clone();
+        let user_id = self.user_id;
+        let cleared_statuses = self.selected_cleared_statuses();
+        // Keep the layout chosen by a previous setup when reconfiguring
+        let folder_depth = config::get_folder_depth(&conn)?;
 
         let tx_err = self.tx_err.clone();
         tokio::task::spawn_blocking(move || {
-            let result = run_setup(conn, &config, &path, budgets, tx);
+            // The accounts only exist in the DB once run_setup has created them
+            let result = run_setup(conn, &config, user_id, &path, budgets, folder_depth, tx)
+                .and_then(|_| {
+                    let conn = get_sqlite_conn()?;
+                    for (uuid, status) in cleared_statuses {
+                        account::set_cleared_status(&conn, uuid, status)?;
+                    }
+                    Ok(())
+                });
             if let Err(err) = result {
                 tx_err.send(err.to_string()).expect("Channel was closed");
             }
@@ -252,17 +746,37 @@ impl MonitoredFolderFormView {
         if let Ok(err) = self.rx_err.try_recv() {
             self.error = Some(err.to_string());
         }
+        // rx_refresh is None unless the form opened on cached budgets
+        if let Some(rx) = &self.rx_refresh {
+            if let Ok(result) = rx.try_recv() {
+                self.rx_refresh = None;
+                let result = result.and_then(|(api_config, cache)| {
+                    self.api_config = api_config;
+                    self.user_id = cache.user_id;
+                    let conn = get_sqlite_conn().map_err(|err| err.to_string())?;
+                    self.set_budgets(&conn, cache.budgets)
+                        .map_err(|err| err.to_string())
+                });
+                if let Err(err) = result {
+                    self.error = Some(format!("Failed to refresh budgets: {}", err));
+                }
+            }
+        }
         // rx_msg is None until setup is started
         if let Some(rx) = &self.rx_msg {
-            match rx.try_recv() {
-                Ok(msg) => {
-                    self.log_msg = Some(msg);
-                }
-                Err(err) => {
-                    // Sender was dropped meaning setup task has completed
-                    if err == mpsc::TryRecvError::Disconnected {
-                        self.rx_msg = None;
-                        self.setup_running = false;
+            loop {
+                match rx.try_recv() {
+                    Ok(msg) => {
+                        let time = chrono::Local::now().format("%H:%M:%S");
+                        self.log.push(format!("[{}] {}", time, msg));
+                    }
+                    Err(err) => {
+                        // Sender was dropped meaning setup task has completed
+                        if err == mpsc::TryRecvError::Disconnected {
+                            self.rx_msg = None;
+                            self.setup_running = false;
+                        }
+                        break;
                     }
                 }
             }
@@ -272,7 +786,56 @@ impl MonitoredFolderFormView {
 
 impl eframe::App for MonitoredFolderFormView {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &mut eframe::Frame) {
+        // Bottom panels are laid out before the central panel so they stay pinned while the budget
+        // list scrolls
+        egui::TopBottomPanel::bottom("error_pannel")
+            .show_separator_line(false)
+            .show(ctx, |ui| {
+                if let Some(msg) = &self.error {
+                    error_label(ui, msg);
+                }
+            });
+
+        egui::TopBottomPanel::bottom("action_panel")
+            .show_separator_line(false)
+            .show(ctx, |ui| {
+                ui.add_space(5.0);
+                ui.horizontal(|ui| {
+                    if self.setup_running {
+                        ui.spinner();
+                    } else if ui
+                        // The cached budgets may be out of date, and the token expired
+                        .add_enabled(self.rx_refresh.is_none(), egui::Button::new("Start Setup"))
+                        .clicked()
+                    {
+                        if let Err(err) = self.start_setup() {
+                            self.error = Some(err.to_string())
+                        }
+                    }
+                    if self.rx_refresh.is_some() {
+                        ui.spinner();
+                        ui.weak("Refreshing budgets");
+                    }
+                });
+                if !self.log.is_empty() {
+                    ui.add_space(5.0);
+                    egui::ScrollArea::vertical()
+                        .max_height(80.0)
+                        .auto_shrink([false, true])
+                        .stick_to_bottom(true)
+                        .show(ui, |ui| {
+                            for line in self.log.iter() {
+                                ui.label(RichText::new(line).monospace());
+                            }
+                        });
+                }
+            });
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            // YNAB only exposes the user's id, but that's enough to tell logins apart
+            ui.label(RichText::new(format!("Signed in as {}", self.user_id)).weak());
+            ui.add_space(5.0);
+
             ui.label("Monitored folder location:");
             ui.end_row();
 
@@ -282,37 +845,57 @@ impl eframe::App for MonitoredFolderFormView {
                     todo!();
                 }
             });
-            ui.add_space(10.0);
+            ui.add_space(5.0);
 
-            ui.label("Select the budget(s) to create subfolders for:");
-            for (i, b) in self.budgets.iter().enumerate() {
-                ui.checkbox(&mut self.selected[i], b.name.clone());
-                ui.end_row();
-            }
+            ui.label("Sync existing transactions since (YYYY-MM-DD, leave blank for all):");
+            ui.text_edit_singleline(&mut self.sync_since);
             ui.add_space(10.0);
 
-            ui.horizontal(|ui| {
-                if self.setup_running {
-                    ui.spinner();
-                } else if ui.button("Start Setup").clicked() {
-                    if let Err(err) = self.start_setup() {
-                        self.error = Some(err.to_string())
+            ui.label("Select the budget(s) to create subfolders for:");
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    for (i, b) in self.budgets.iter().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.checkbox(&mut self.selected[i], b.name.clone());
+                            if self.configured[i] {
+                                ui.label(RichText::new("(already configured)").weak());
+                            }
+                        });
+                        let accounts = b.accounts.as_deref().unwrap_or_default();
+                        if !accounts.is_empty() {
+                            ui.indent(i, |ui| {
+                                egui::CollapsingHeader::new(format!("{} accounts", accounts.len()))
+                                    .id_salt(i)
+                                    .show(ui, |ui| {
+                                        for (j, acc) in accounts.iter().enumerate() {
+                                            ui.horizontal(|ui| {
+                                                ui.add_enabled(
+                                                    self.selected[i],
+                                                    egui::Checkbox::new(
+                                                        &mut self.selected_accounts[i][j],
+                                                        acc.name.clone(),
+                                                    ),
+                                                );
+                                                let enabled = self.selected[i]
+                                                    && self.selected_accounts[i][j];
+                                                ui.add_enabled_ui(enabled, |ui| {
+                                                    cleared_status_combo(
+                                                        ui,
+                                                        (i, j),
+                                                        &mut self.cleared_statuses[i][j],
+                                                    );
+                                                });
+                                            });
+                                        }
+                                    });
+                            });
+                        }
+                        ui.end_row();
                     }
-                }
-                if let Some(msg) = &self.log_msg {
-                    ui.label(msg);
-                }
-            });
+                });
         });
 
-        egui::TopBottomPanel::bottom("error_pannel")
-            .show_separator_line(false)
-            .show(ctx, |ui| {
-                if let Some(msg) = &self.error {
-                    ui.label(RichText::new(msg).color(Color32::LIGHT_RED));
-                }
-            });
-
         self.poll_messages();
     }
 }
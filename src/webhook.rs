@@ -0,0 +1,72 @@
+use crate::event::ImportSummary;
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tracing::{debug, warn};
+use url::Url;
+
+// Kept short so an unreachable endpoint doesn't hold up the next import for long
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn payload(summary: &ImportSummary, at: DateTime<Local>) -> Value {
+    json!({
+        "budget": summary.budget,
+        "account": summary.account,
+        "transaction_count": summary.created,
+        "total_amount_milli": summary.total_milli,
+        "timestamp": at.to_rfc3339(),
+    })
+}
+
+async fn post(url: &Url, body: &Value) -> Result<()> {
+    reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()?
+        .post(url.clone())
+        .json(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+// Posts a JSON description of the import to url. Best effort: a failure is only logged, the import
+// has already succeeded.
+pub async fn post_import(url: &Url, summary: &ImportSummary) {
+    let body = payload(summary, Local::now());
+    match post(url, &body).await {
+        Ok(()) => debug!(%url, "Posted import to webhook"),
+        Err(err) => warn!(%url, "Failed to post import to webhook: {:?}", err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_payload() {
+        let summary = ImportSummary {
+            budget: "Budget".into(),
+            account: "Checking".into(),
+            created: 2,
+            total_milli: -7850,
+            duplicates: 0,
+            skipped_existing: Vec::new(),
+            overlapping: None,
+        };
+        let at = Local.with_ymd_and_hms(2024, 11, 20, 9, 30, 0).unwrap();
+        assert_eq!(
+            payload(&summary, at),
+            json!({
+                "budget": "Budget",
+                "account": "Checking",
+                "transaction_count": 2,
+                "total_amount_milli": -7850,
+                "timestamp": at.to_rfc3339(),
+            })
+        );
+    }
+}